@@ -1,6 +1,10 @@
 package main
 
 import (
+	"bytes"
+	"crypto/sha256"
+	"encoding/base64"
+	"encoding/hex"
 	"encoding/json"
 	"errors"
 	"fmt"
@@ -8,7 +12,9 @@ import (
 	"net/http"
 	"net/url"
 	"os"
+	"path"
 	"path/filepath"
+	"regexp"
 	"strconv"
 	"strings"
 	"sync"
@@ -24,40 +30,110 @@ const (
 	defaultEffectsFile   = "effects_manifest.json"
 	defaultWebUIURL      = "http://localhost:5173"
 	defaultSignatureFile = "engine.wasm.sig.b64"
+	defaultManifestSignatureFile = "engine.manifest.sig.json"
+	defaultKeyStatementsFile     = "key_statements.json"
+	defaultChannelsFile          = "channels.json"
+	releasesDir                  = "releases"
+	deltasDir                    = "deltas"
+	deltaSuffix                  = ".zst"
 	defaultRateLimitRPS  = 60.0
 	defaultRateLimitBurst = 120.0
 	defaultRateLimitTTL  = 2 * time.Minute
-	defaultCORSOrigins   = "http://localhost:5173,https://vst.tonelab.dev,https://tonelab.dev,https://*.vercel.app"
+	defaultCORSOrigins   = "http://localhost:5173,https://vst.tonelab.dev,https://tonelab.dev,https://*.vercel.app,tonelab://assets,http://tonelab.assets"
+	defaultABIVersion    = 1
 )
 
+// Must match SyncManifest::signed_payload in the plugin.
+const manifestSignatureTag = "tonelab-evergreen-manifest-v1"
+
+// Plugins that predate the ABI handshake send no "abi" query parameter but can
+// drive ABI 1 bundles.
+const legacyClientABIVersion = 1
+
+// Release channels a plugin can follow. "pinned" asks for one exact version.
+const (
+	channelStable = "stable"
+	channelPinned = "pinned"
+)
+
+// releaseVersionPattern keeps versions usable as directory names under
+// assets/releases/.
+var releaseVersionPattern = regexp.MustCompile(`^v?[0-9]+(\.[0-9]+)*([-+][0-9A-Za-z.-]+)?$`)
+
+// sha256HexPattern matches the bundle hash a delta file is named after.
+var sha256HexPattern = regexp.MustCompile(`^[0-9a-f]{64}$`)
+
+var errReleaseNotFound = errors.New("release not found")
+
 type syncAssets struct {
-	IconsURL   string `json:"icons_url"`
-	WebUIURL   string `json:"web_ui_url"`
-	EffectsURL string `json:"effects_url"`
+	IconsURL      string `json:"icons_url"`
+	WebUIURL      string `json:"web_ui_url"`
+	EffectsURL    string `json:"effects_url"`
+	IconsSHA256   string `json:"icons_sha256,omitempty"`
+	EffectsSHA256 string `json:"effects_sha256,omitempty"`
 }
 
 type syncResponse struct {
-	Version   string     `json:"version"`
-	WasmURL   string     `json:"wasm_url"`
-	Signature string     `json:"signature"`
-	Assets    syncAssets `json:"assets"`
+	Version           string          `json:"version"`
+	WasmURL           string          `json:"wasm_url"`
+	Signature         string          `json:"signature"`
+	SHA256            string          `json:"sha256,omitempty"`
+	Assets            syncAssets      `json:"assets"`
+	ABIVersion        int             `json:"abi_version"`
+	MinHostVersion    string          `json:"min_host_version,omitempty"`
+	KeyID             string          `json:"key_id,omitempty"`
+	ManifestSignature string          `json:"manifest_signature,omitempty"`
+	KeyStatements     json.RawMessage `json:"key_statements,omitempty"`
+	Channel           string          `json:"channel,omitempty"`
+	Deltas            []wasmDelta     `json:"deltas,omitempty"`
+}
+
+// wasmDelta is a zstd --patch-from diff to the served wasm from an earlier
+// bundle. Deltas are not signed: the plugin checks the patched wasm against
+// the signed sha256.
+type wasmDelta struct {
+	FromSHA256 string `json:"from_sha256"`
+	URL        string `json:"url"`
+	Size       int64  `json:"size,omitempty"`
+}
+
+// release is the bundle a sync request resolves to: the top-level assets, or
+// a directory under assets/releases/ named after its version.
+type release struct {
+	Channel string
+	Version string
+	// Dir is relative to the assets dir; empty for the top-level bundle.
+	Dir string
+}
+
+// manifestSignatureFile is written by security/sign_bundle.sh. The payload it
+// signed is kept so the backend can tell when its config has drifted from it.
+type manifestSignatureFile struct {
+	KeyID      string `json:"key_id"`
+	Signature  string `json:"signature"`
+	PayloadB64 string `json:"payload_b64"`
 }
 
 type serverConfig struct {
-	ListenAddr        string
-	PublicBaseURL     string
-	Version           string
-	WasmFile          string
-	IconsFile         string
-	EffectsFile       string
-	WebUIURL          string
-	AssetsDir         string
-	SignatureOverride string
-	SignatureFilePath string
-	RateLimitRPS      float64
-	RateLimitBurst    float64
-	RateLimitTTL      time.Duration
-	CORSOrigins       []string
+	ListenAddr                string
+	PublicBaseURL             string
+	Version                   string
+	WasmFile                  string
+	IconsFile                 string
+	EffectsFile               string
+	WebUIURL                  string
+	AssetsDir                 string
+	SignatureOverride         string
+	SignatureFilePath         string
+	ManifestSignatureFilePath string
+	KeyStatementsFilePath     string
+	ChannelsFilePath          string
+	RateLimitRPS              float64
+	RateLimitBurst            float64
+	RateLimitTTL              time.Duration
+	CORSOrigins               []string
+	ABIVersion                int
+	MinHostVersion            string
 }
 
 func main() {
@@ -80,20 +156,58 @@ func main() {
 			return
 		}
 
-		signature, sigErr := cfg.resolveSignatureB64()
+		if reason := cfg.incompatibleClientReason(r.URL.Query()); reason != "" {
+			http.Error(w, reason, http.StatusUpgradeRequired)
+			return
+		}
+
+		rel, relErr := cfg.resolveRelease(r.URL.Query())
+		if errors.Is(relErr, errReleaseNotFound) {
+			http.Error(w, relErr.Error(), http.StatusNotFound)
+			return
+		}
+		if relErr != nil {
+			http.Error(w, relErr.Error(), http.StatusBadRequest)
+			return
+		}
+
+		signature, sigErr := cfg.resolveSignatureB64(rel)
 		if sigErr != nil {
 			log.Printf("warning: failed to load wasm signature: %v", sigErr)
 		}
 
+		wasmSHA256, shaErr := fileHashes.sha256Hex(filepath.Join(cfg.AssetsDir, filepath.FromSlash(rel.Dir), cfg.WasmFile))
+		if shaErr != nil {
+			log.Printf("warning: failed to hash wasm: %v", shaErr)
+		}
+		// Pinned assets are optional: without a hash the plugin loads the URL as-is.
+		iconsSHA256, _ := fileHashes.sha256Hex(filepath.Join(cfg.AssetsDir, cfg.IconsFile))
+		effectsSHA256, _ := fileHashes.sha256Hex(filepath.Join(cfg.AssetsDir, cfg.EffectsFile))
+
 		resp := syncResponse{
-			Version:   cfg.Version,
-			WasmURL:   fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, cfg.WasmFile),
+			Version:   rel.Version,
+			WasmURL:   fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, path.Join(rel.Dir, cfg.WasmFile)),
 			Signature: signature,
+			SHA256:    wasmSHA256,
 			Assets: syncAssets{
-				IconsURL:   fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, cfg.IconsFile),
-				WebUIURL:   cfg.WebUIURL,
-				EffectsURL: fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, cfg.EffectsFile),
+				IconsURL:      fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, cfg.IconsFile),
+				WebUIURL:      cfg.WebUIURL,
+				EffectsURL:    fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, cfg.EffectsFile),
+				IconsSHA256:   iconsSHA256,
+				EffectsSHA256: effectsSHA256,
 			},
+			ABIVersion:     cfg.ABIVersion,
+			MinHostVersion: cfg.MinHostVersion,
+			Channel:        rel.Channel,
+			Deltas:         cfg.listDeltas(rel),
+		}
+		if err := cfg.attachManifestSignature(&resp, rel); err != nil {
+			log.Printf("warning: serving manifest without manifest signature: %v", err)
+		}
+		if statements, err := cfg.loadKeyStatements(); err != nil {
+			log.Printf("warning: failed to load key statements: %v", err)
+		} else {
+			resp.KeyStatements = statements
 		}
 
 		w.Header().Set("Content-Type", "application/json")
@@ -155,25 +269,39 @@ func loadConfig() (serverConfig, error) {
 	}
 
 	cfg := serverConfig{
-		ListenAddr:        listenAddr,
-		PublicBaseURL:     strings.TrimSuffix(envOrDefault("EVERGREEN_PUBLIC_BASE_URL", defaultPublicBaseURL), "/"),
-		Version:           envOrDefault("EVERGREEN_VERSION", defaultVersion),
-		WasmFile:          envOrDefault("EVERGREEN_WASM_FILE", defaultWasmFile),
-		IconsFile:         envOrDefault("EVERGREEN_ICONS_FILE", defaultIconsFile),
-		EffectsFile:       envOrDefault("EVERGREEN_EFFECTS_FILE", defaultEffectsFile),
-		WebUIURL:          envOrDefault("EVERGREEN_WEB_UI_URL", defaultWebUIURL),
-		AssetsDir:         envOrDefault("EVERGREEN_ASSETS_DIR", assetsDir),
-		SignatureOverride: strings.TrimSpace(os.Getenv("EVERGREEN_WASM_SIGNATURE_B64")),
-		SignatureFilePath: envOrDefault("EVERGREEN_SIGNATURE_FILE", defaultSignatureFile),
-		RateLimitRPS:      envOrDefaultFloat("EVERGREEN_RATE_LIMIT_RPS", defaultRateLimitRPS),
-		RateLimitBurst:    envOrDefaultFloat("EVERGREEN_RATE_LIMIT_BURST", defaultRateLimitBurst),
-		RateLimitTTL:      envOrDefaultDuration("EVERGREEN_RATE_LIMIT_TTL", defaultRateLimitTTL),
-		CORSOrigins:       parseCORSOrigins(envOrDefault("EVERGREEN_CORS_ORIGINS", defaultCORSOrigins)),
+		ListenAddr:                listenAddr,
+		PublicBaseURL:             strings.TrimSuffix(envOrDefault("EVERGREEN_PUBLIC_BASE_URL", defaultPublicBaseURL), "/"),
+		Version:                   envOrDefault("EVERGREEN_VERSION", defaultVersion),
+		WasmFile:                  envOrDefault("EVERGREEN_WASM_FILE", defaultWasmFile),
+		IconsFile:                 envOrDefault("EVERGREEN_ICONS_FILE", defaultIconsFile),
+		EffectsFile:               envOrDefault("EVERGREEN_EFFECTS_FILE", defaultEffectsFile),
+		WebUIURL:                  envOrDefault("EVERGREEN_WEB_UI_URL", defaultWebUIURL),
+		AssetsDir:                 envOrDefault("EVERGREEN_ASSETS_DIR", assetsDir),
+		SignatureOverride:         strings.TrimSpace(os.Getenv("EVERGREEN_WASM_SIGNATURE_B64")),
+		SignatureFilePath:         envOrDefault("EVERGREEN_SIGNATURE_FILE", defaultSignatureFile),
+		ManifestSignatureFilePath: envOrDefault("EVERGREEN_MANIFEST_SIGNATURE_FILE", defaultManifestSignatureFile),
+		KeyStatementsFilePath:     envOrDefault("EVERGREEN_KEY_STATEMENTS_FILE", defaultKeyStatementsFile),
+		ChannelsFilePath:          envOrDefault("EVERGREEN_CHANNELS_FILE", defaultChannelsFile),
+		RateLimitRPS:              envOrDefaultFloat("EVERGREEN_RATE_LIMIT_RPS", defaultRateLimitRPS),
+		RateLimitBurst:            envOrDefaultFloat("EVERGREEN_RATE_LIMIT_BURST", defaultRateLimitBurst),
+		RateLimitTTL:              envOrDefaultDuration("EVERGREEN_RATE_LIMIT_TTL", defaultRateLimitTTL),
+		CORSOrigins:               parseCORSOrigins(envOrDefault("EVERGREEN_CORS_ORIGINS", defaultCORSOrigins)),
+		ABIVersion:                envOrDefaultInt("EVERGREEN_ABI_VERSION", defaultABIVersion),
+		MinHostVersion:            strings.TrimSpace(os.Getenv("EVERGREEN_MIN_HOST_VERSION")),
 	}
 
 	if !filepath.IsAbs(cfg.SignatureFilePath) {
 		cfg.SignatureFilePath = filepath.Join(cfg.AssetsDir, cfg.SignatureFilePath)
 	}
+	if !filepath.IsAbs(cfg.ManifestSignatureFilePath) {
+		cfg.ManifestSignatureFilePath = filepath.Join(cfg.AssetsDir, cfg.ManifestSignatureFilePath)
+	}
+	if !filepath.IsAbs(cfg.KeyStatementsFilePath) {
+		cfg.KeyStatementsFilePath = filepath.Join(cfg.AssetsDir, cfg.KeyStatementsFilePath)
+	}
+	if !filepath.IsAbs(cfg.ChannelsFilePath) {
+		cfg.ChannelsFilePath = filepath.Join(cfg.AssetsDir, cfg.ChannelsFilePath)
+	}
 
 	if cfg.ListenAddr == "" {
 		return serverConfig{}, errors.New("EVERGREEN_LISTEN_ADDR must not be empty")
@@ -181,22 +309,322 @@ func loadConfig() (serverConfig, error) {
 	if cfg.PublicBaseURL == "" {
 		return serverConfig{}, errors.New("EVERGREEN_PUBLIC_BASE_URL must not be empty")
 	}
+	if cfg.MinHostVersion != "" {
+		if _, ok := parseVersion(cfg.MinHostVersion); !ok {
+			return serverConfig{}, fmt.Errorf("EVERGREEN_MIN_HOST_VERSION '%s' is not a version", cfg.MinHostVersion)
+		}
+	}
 	return cfg, nil
 }
 
-func (cfg serverConfig) resolveSignatureB64() (string, error) {
-	if cfg.SignatureOverride != "" {
+// incompatibleClientReason explains why the requesting plugin cannot drive the
+// configured engine, or returns "" when it can.
+func (cfg serverConfig) incompatibleClientReason(query url.Values) string {
+	clientABI := legacyClientABIVersion
+	if raw := strings.TrimSpace(query.Get("abi")); raw != "" {
+		parsed, err := strconv.Atoi(raw)
+		if err != nil {
+			return fmt.Sprintf("invalid abi '%s'", raw)
+		}
+		clientABI = parsed
+	}
+	if clientABI < cfg.ABIVersion {
+		return fmt.Sprintf("engine requires ABI v%d but plugin supports up to v%d; update the plugin", cfg.ABIVersion, clientABI)
+	}
+
+	if cfg.MinHostVersion == "" {
+		return ""
+	}
+	hostVersion := strings.TrimSpace(query.Get("host_version"))
+	if hostVersion == "" {
+		return fmt.Sprintf("engine requires plugin %s or newer; update the plugin", cfg.MinHostVersion)
+	}
+	if compareVersions(hostVersion, cfg.MinHostVersion) < 0 {
+		return fmt.Sprintf("engine requires plugin %s or newer (running %s); update the plugin", cfg.MinHostVersion, hostVersion)
+	}
+	return ""
+}
+
+// parseVersion reads dotted numeric versions ("1.2", "v1.2.3", "1.2.3-beta"),
+// ignoring any pre-release or build suffix.
+func parseVersion(value string) ([]int, bool) {
+	trimmed := strings.TrimPrefix(strings.TrimSpace(value), "v")
+	if idx := strings.IndexAny(trimmed, "-+"); idx >= 0 {
+		trimmed = trimmed[:idx]
+	}
+	parts := strings.Split(trimmed, ".")
+	out := make([]int, 0, len(parts))
+	for _, part := range parts {
+		parsed, err := strconv.Atoi(part)
+		if err != nil || parsed < 0 {
+			return nil, false
+		}
+		out = append(out, parsed)
+	}
+	return out, true
+}
+
+// compareVersions orders two versions; unparseable versions sort first.
+func compareVersions(left, right string) int {
+	l, lok := parseVersion(left)
+	r, rok := parseVersion(right)
+	switch {
+	case !lok && !rok:
+		return 0
+	case !lok:
+		return -1
+	case !rok:
+		return 1
+	}
+	for i := 0; i < len(l) || i < len(r); i++ {
+		var a, b int
+		if i < len(l) {
+			a = l[i]
+		}
+		if i < len(r) {
+			b = r[i]
+		}
+		if a != b {
+			if a < b {
+				return -1
+			}
+			return 1
+		}
+	}
+	return 0
+}
+
+// resolveRelease picks the bundle for the requested channel. Channels listed
+// in channels.json ({"stable": "1.2.0", "beta": "1.3.0-beta.1"}) serve
+// assets/releases/<version>/; stable without an entry, unknown channels and
+// plugins that send no channel get the top-level bundle as stable. A pinned
+// version must exist.
+func (cfg serverConfig) resolveRelease(query url.Values) (release, error) {
+	channel := strings.ToLower(strings.TrimSpace(query.Get("channel")))
+	if channel == channelPinned {
+		version := strings.TrimSpace(query.Get("version"))
+		if !releaseVersionPattern.MatchString(version) {
+			return release{}, fmt.Errorf("invalid pinned version '%s'", version)
+		}
+		if version == cfg.Version {
+			return release{Channel: channelPinned, Version: version}, nil
+		}
+		if !cfg.hasRelease(version) {
+			return release{}, fmt.Errorf("%w: version %s is not available", errReleaseNotFound, version)
+		}
+		return release{Channel: channelPinned, Version: version, Dir: path.Join(releasesDir, version)}, nil
+	}
+
+	if channel == "" {
+		channel = channelStable
+	}
+	channels, err := cfg.loadChannels()
+	if err != nil {
+		log.Printf("warning: failed to load channels: %v", err)
+	}
+	if version, ok := channels[channel]; ok {
+		if cfg.hasRelease(version) {
+			return release{Channel: channel, Version: version, Dir: path.Join(releasesDir, version)}, nil
+		}
+		log.Printf("warning: channel %s points at missing release %s", channel, version)
+	}
+	return release{Channel: channelStable, Version: cfg.Version}, nil
+}
+
+func (cfg serverConfig) hasRelease(version string) bool {
+	if !releaseVersionPattern.MatchString(version) {
+		return false
+	}
+	st, err := os.Stat(filepath.Join(cfg.AssetsDir, releasesDir, version, cfg.WasmFile))
+	return err == nil && !st.IsDir()
+}
+
+// loadChannels reads the channel -> version map, or nil when there is none.
+func (cfg serverConfig) loadChannels() (map[string]string, error) {
+	raw, err := os.ReadFile(cfg.ChannelsFilePath)
+	if errors.Is(err, os.ErrNotExist) {
+		return nil, nil
+	}
+	if err != nil {
+		return nil, fmt.Errorf("read channels '%s': %w", cfg.ChannelsFilePath, err)
+	}
+
+	var channels map[string]string
+	if err := json.Unmarshal(raw, &channels); err != nil {
+		return nil, fmt.Errorf("channels '%s' must map channel names to versions: %w", cfg.ChannelsFilePath, err)
+	}
+	out := make(map[string]string, len(channels))
+	for channel, version := range channels {
+		out[strings.ToLower(strings.TrimSpace(channel))] = strings.TrimSpace(version)
+	}
+	return out, nil
+}
+
+// releaseFile locates one of a release's signature files: the configured path
+// for the top-level bundle, the same file name inside a release directory.
+func (cfg serverConfig) releaseFile(rel release, configured string) string {
+	if rel.Dir == "" {
+		return configured
+	}
+	return filepath.Join(cfg.AssetsDir, filepath.FromSlash(rel.Dir), filepath.Base(configured))
+}
+
+func (cfg serverConfig) resolveSignatureB64(rel release) (string, error) {
+	if cfg.SignatureOverride != "" && rel.Dir == "" {
 		return cfg.SignatureOverride, nil
 	}
 
-	bytes, err := os.ReadFile(cfg.SignatureFilePath)
+	signatureFilePath := cfg.releaseFile(rel, cfg.SignatureFilePath)
+	bytes, err := os.ReadFile(signatureFilePath)
 	if err != nil {
-		return "", fmt.Errorf("read signature file '%s': %w", cfg.SignatureFilePath, err)
+		return "", fmt.Errorf("read signature file '%s': %w", signatureFilePath, err)
 	}
 
 	return strings.TrimSpace(string(bytes)), nil
 }
 
+// listDeltas advertises the patches in a release's deltas/ directory, each
+// named <sha256 of the old wasm>.zst as `xtask delta` writes them.
+func (cfg serverConfig) listDeltas(rel release) []wasmDelta {
+	dir := path.Join(rel.Dir, deltasDir)
+	entries, err := os.ReadDir(filepath.Join(cfg.AssetsDir, filepath.FromSlash(dir)))
+	if err != nil {
+		return nil
+	}
+	var deltas []wasmDelta
+	for _, entry := range entries {
+		from, ok := strings.CutSuffix(entry.Name(), deltaSuffix)
+		if !ok || entry.IsDir() || !sha256HexPattern.MatchString(from) {
+			continue
+		}
+		info, err := entry.Info()
+		if err != nil {
+			continue
+		}
+		deltas = append(deltas, wasmDelta{
+			FromSHA256: from,
+			URL:        fmt.Sprintf("%s/assets/%s", cfg.PublicBaseURL, path.Join(dir, entry.Name())),
+			Size:       info.Size(),
+		})
+	}
+	return deltas
+}
+
+// manifestPayload renders the bytes covered by manifest_signature.
+func manifestPayload(resp syncResponse) []byte {
+	abiVersion := ""
+	if resp.ABIVersion != 0 {
+		abiVersion = strconv.Itoa(resp.ABIVersion)
+	}
+	fields := []struct{ name, value string }{
+		{"version", resp.Version},
+		{"wasm_url", resp.WasmURL},
+		{"sha256", strings.ToLower(resp.SHA256)},
+		{"abi_version", abiVersion},
+		{"min_host_version", resp.MinHostVersion},
+		{"web_ui_url", resp.Assets.WebUIURL},
+		{"icons_url", resp.Assets.IconsURL},
+		{"effects_url", resp.Assets.EffectsURL},
+		{"icons_sha256", strings.ToLower(resp.Assets.IconsSHA256)},
+		{"effects_sha256", strings.ToLower(resp.Assets.EffectsSHA256)},
+	}
+
+	var payload strings.Builder
+	payload.WriteString(manifestSignatureTag + "\n")
+	for _, field := range fields {
+		payload.WriteString(field.name + "=" + strings.TrimSpace(field.value) + "\n")
+	}
+	return []byte(payload.String())
+}
+
+// attachManifestSignature adds the offline manifest signature when it was made
+// for exactly the manifest being served. Without the file, or when the config
+// or wasm changed since signing, plugins fall back to the wasm-only signature.
+func (cfg serverConfig) attachManifestSignature(resp *syncResponse, rel release) error {
+	manifestSignatureFilePath := cfg.releaseFile(rel, cfg.ManifestSignatureFilePath)
+	raw, err := os.ReadFile(manifestSignatureFilePath)
+	if errors.Is(err, os.ErrNotExist) {
+		return nil
+	}
+	if err != nil {
+		return fmt.Errorf("read manifest signature '%s': %w", manifestSignatureFilePath, err)
+	}
+
+	var signed manifestSignatureFile
+	if err := json.Unmarshal(raw, &signed); err != nil {
+		return fmt.Errorf("parse manifest signature '%s': %w", manifestSignatureFilePath, err)
+	}
+	signedPayload, err := base64.StdEncoding.DecodeString(strings.TrimSpace(signed.PayloadB64))
+	if err != nil {
+		return fmt.Errorf("decode signed payload: %w", err)
+	}
+	if !bytes.Equal(signedPayload, manifestPayload(*resp)) {
+		return errors.New("signed manifest does not match the served manifest; re-run security/sign_bundle.sh")
+	}
+
+	resp.KeyID = strings.TrimSpace(signed.KeyID)
+	resp.ManifestSignature = strings.TrimSpace(signed.Signature)
+	return nil
+}
+
+// loadKeyStatements returns the signed key rotations/revocations to publish,
+// or nil when there are none.
+func (cfg serverConfig) loadKeyStatements() (json.RawMessage, error) {
+	raw, err := os.ReadFile(cfg.KeyStatementsFilePath)
+	if errors.Is(err, os.ErrNotExist) {
+		return nil, nil
+	}
+	if err != nil {
+		return nil, fmt.Errorf("read key statements '%s': %w", cfg.KeyStatementsFilePath, err)
+	}
+
+	var statements []json.RawMessage
+	if err := json.Unmarshal(raw, &statements); err != nil {
+		return nil, fmt.Errorf("key statements '%s' must be a JSON array: %w", cfg.KeyStatementsFilePath, err)
+	}
+	if len(statements) == 0 {
+		return nil, nil
+	}
+	return json.RawMessage(raw), nil
+}
+
+// fileHashCache remembers file hashes until the file's size or mtime changes,
+// so /vst/sync does not re-read the wasm and assets on every request.
+type fileHashCache struct {
+	mu      sync.Mutex
+	entries map[string]fileHashEntry
+}
+
+type fileHashEntry struct {
+	size    int64
+	modTime time.Time
+	sum     string
+}
+
+var fileHashes = &fileHashCache{entries: map[string]fileHashEntry{}}
+
+func (c *fileHashCache) sha256Hex(path string) (string, error) {
+	st, err := os.Stat(path)
+	if err != nil {
+		return "", err
+	}
+
+	c.mu.Lock()
+	defer c.mu.Unlock()
+	if entry, ok := c.entries[path]; ok && entry.size == st.Size() && entry.modTime.Equal(st.ModTime()) {
+		return entry.sum, nil
+	}
+
+	bytes, err := os.ReadFile(path)
+	if err != nil {
+		return "", err
+	}
+	digest := sha256.Sum256(bytes)
+	sum := hex.EncodeToString(digest[:])
+	c.entries[path] = fileHashEntry{size: st.Size(), modTime: st.ModTime(), sum: sum}
+	return sum, nil
+}
+
 func envOrDefault(name, fallback string) string {
 	if value, ok := os.LookupEnv(name); ok {
 		trimmed := strings.TrimSpace(value)
@@ -220,6 +648,19 @@ func envOrDefaultFloat(name string, fallback float64) float64 {
 	return fallback
 }
 
+func envOrDefaultInt(name string, fallback int) int {
+	if value, ok := os.LookupEnv(name); ok {
+		trimmed := strings.TrimSpace(value)
+		if trimmed != "" {
+			parsed, err := strconv.Atoi(trimmed)
+			if err == nil && parsed > 0 {
+				return parsed
+			}
+		}
+	}
+	return fallback
+}
+
 func envOrDefaultDuration(name string, fallback time.Duration) time.Duration {
 	if value, ok := os.LookupEnv(name); ok {
 		trimmed := strings.TrimSpace(value)
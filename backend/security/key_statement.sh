@@ -0,0 +1,63 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+# Appends a signed key statement to backend/assets/key_statements.json, which
+# the backend publishes in /vst/sync.
+#
+#   ./key_statement.sh trust <new-public-key.b64>   # rotate in a new key
+#   ./key_statement.sh revoke <key-id>              # revoke a key
+#
+# Statements are signed with SIGNING_KEY (default: the dev private key), which
+# plugins must already trust.
+
+ROOT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+SECURITY_DIR="${ROOT_DIR}/security"
+KEYS_DIR="${SECURITY_DIR}/keys"
+STATEMENTS_FILE="${KEY_STATEMENTS_FILE:-${ROOT_DIR}/assets/key_statements.json}"
+SIGNING_KEY="${SIGNING_KEY:-${KEYS_DIR}/dev_ed25519_private.pem}"
+
+ACTION="${1:-}"
+SUBJECT="${2:-}"
+if [[ "${ACTION}" != "trust" && "${ACTION}" != "revoke" ]] || [[ -z "${SUBJECT}" ]]; then
+  echo "usage: $0 trust <public-key.b64> | revoke <key-id>" >&2
+  exit 1
+fi
+if [[ ! -f "${SIGNING_KEY}" ]]; then
+  echo "Signing key not found: ${SIGNING_KEY}" >&2
+  exit 1
+fi
+
+key_id_of_b64() {
+  printf '%s' "$1" | openssl base64 -d -A | openssl dgst -sha256 -r | cut -c1-16
+}
+
+SIGNER_PUBLIC_B64="$(openssl pkey -in "${SIGNING_KEY}" -pubout -outform DER 2>/dev/null | tail -c 32 | base64 | tr -d '\n')"
+SIGNED_BY="$(key_id_of_b64 "${SIGNER_PUBLIC_B64}")"
+
+if [[ "${ACTION}" == "trust" ]]; then
+  PUBLIC_KEY="$(tr -d '\r\n' < "${SUBJECT}")"
+  KEY_ID="$(key_id_of_b64 "${PUBLIC_KEY}")"
+else
+  PUBLIC_KEY=""
+  KEY_ID="${SUBJECT}"
+fi
+
+PAYLOAD_FILE="$(mktemp)"
+SIG_FILE="$(mktemp)"
+trap 'rm -f "${PAYLOAD_FILE}" "${SIG_FILE}"' EXIT
+
+# Must match KeyStatement::payload in the plugin.
+printf 'tonelab-evergreen-key-statement-v1\naction=%s\nkey_id=%s\npublic_key=%s\n' \
+  "${ACTION}" "${KEY_ID}" "${PUBLIC_KEY}" > "${PAYLOAD_FILE}"
+openssl pkeyutl -sign -inkey "${SIGNING_KEY}" -rawin -in "${PAYLOAD_FILE}" -out "${SIG_FILE}"
+SIGNATURE="$(base64 < "${SIG_FILE}" | tr -d '\n')"
+
+[[ -f "${STATEMENTS_FILE}" ]] || echo '[]' > "${STATEMENTS_FILE}"
+jq --arg action "${ACTION}" --arg key_id "${KEY_ID}" --arg public_key "${PUBLIC_KEY}" \
+  --arg signed_by "${SIGNED_BY}" --arg signature "${SIGNATURE}" \
+  '. + [{action: $action, key_id: $key_id, signed_by: $signed_by, signature: $signature}
+        + (if $public_key == "" then {} else {public_key: $public_key} end)]' \
+  "${STATEMENTS_FILE}" > "${STATEMENTS_FILE}.tmp"
+mv "${STATEMENTS_FILE}.tmp" "${STATEMENTS_FILE}"
+
+echo "Appended ${ACTION} ${KEY_ID} (signed by ${SIGNED_BY}) to ${STATEMENTS_FILE}"
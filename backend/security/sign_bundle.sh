@@ -6,13 +6,38 @@ SECURITY_DIR="${ROOT_DIR}/security"
 ASSETS_DIR="${ROOT_DIR}/assets"
 KEYS_DIR="${SECURITY_DIR}/keys"
 
-WASM_FILE="${1:-${ASSETS_DIR}/engine.wasm}"
-SIG_FILE="${2:-${ASSETS_DIR}/engine.wasm.sig.b64}"
+# EVERGREEN_RELEASE=<version> signs assets/releases/<version>/ for release
+# channels instead of the top-level bundle.
+RELEASE="${EVERGREEN_RELEASE:-}"
+RELEASE_PATH=""
+BUNDLE_DIR="${ASSETS_DIR}"
+if [[ -n "${RELEASE}" ]]; then
+  RELEASE_PATH="releases/${RELEASE}/"
+  BUNDLE_DIR="${ASSETS_DIR}/releases/${RELEASE}"
+fi
+
+WASM_FILE="${1:-${BUNDLE_DIR}/engine.wasm}"
+SIG_FILE="${2:-${BUNDLE_DIR}/engine.wasm.sig.b64}"
 PUBLIC_KEY_B64_FILE="${3:-${SECURITY_DIR}/dev_ed25519_public_key.b64}"
 PRIVATE_KEY_FILE="${KEYS_DIR}/dev_ed25519_private.pem"
 PUBLIC_KEY_PEM_FILE="${KEYS_DIR}/dev_ed25519_public.pem"
 PUBLIC_KEY_DER_FILE="${KEYS_DIR}/dev_ed25519_public.der"
 SIGNATURE_BIN_FILE="${KEYS_DIR}/engine.wasm.sig.bin"
+MANIFEST_SIG_FILE="${BUNDLE_DIR}/engine.manifest.sig.json"
+MANIFEST_PAYLOAD_FILE="${KEYS_DIR}/engine.manifest.payload"
+MANIFEST_SIG_BIN_FILE="${KEYS_DIR}/engine.manifest.sig.bin"
+
+# The manifest signature covers what the backend will serve, so these must
+# match the backend's EVERGREEN_* settings (same defaults as backend/main.go).
+PUBLIC_BASE_URL="${EVERGREEN_PUBLIC_BASE_URL:-http://localhost:8080}"
+PUBLIC_BASE_URL="${PUBLIC_BASE_URL%/}"
+MANIFEST_VERSION="${RELEASE:-${EVERGREEN_VERSION:-1.0.0-dev}}"
+MANIFEST_WEB_UI_URL="${EVERGREEN_WEB_UI_URL:-http://localhost:5173}"
+MANIFEST_ABI_VERSION="${EVERGREEN_ABI_VERSION:-1}"
+MANIFEST_MIN_HOST_VERSION="${EVERGREEN_MIN_HOST_VERSION:-}"
+MANIFEST_WASM_URL="${PUBLIC_BASE_URL}/assets/${RELEASE_PATH}${EVERGREEN_WASM_FILE:-engine.wasm}"
+MANIFEST_ICONS_URL="${PUBLIC_BASE_URL}/assets/${EVERGREEN_ICONS_FILE:-icons.zip}"
+MANIFEST_EFFECTS_URL="${PUBLIC_BASE_URL}/assets/${EVERGREEN_EFFECTS_FILE:-effects_manifest.json}"
 
 mkdir -p "${KEYS_DIR}"
 
@@ -40,6 +65,35 @@ openssl pkeyutl \
 
 base64 < "${SIGNATURE_BIN_FILE}" | tr -d '\n' > "${SIG_FILE}"
 
+KEY_ID="$(tail -c 32 "${PUBLIC_KEY_DER_FILE}" | openssl dgst -sha256 -r | cut -c1-16)"
+WASM_SHA256="$(openssl dgst -sha256 -r "${WASM_FILE}" | cut -d' ' -f1)"
+sha256_if_exists() {
+  if [[ -f "$1" ]]; then openssl dgst -sha256 -r "$1" | cut -d' ' -f1; fi
+}
+ICONS_SHA256="$(sha256_if_exists "${ASSETS_DIR}/${EVERGREEN_ICONS_FILE:-icons.zip}")"
+EFFECTS_SHA256="$(sha256_if_exists "${ASSETS_DIR}/${EVERGREEN_EFFECTS_FILE:-effects_manifest.json}")"
+
+# Must match SyncManifest::signed_payload in the plugin.
+printf 'tonelab-evergreen-manifest-v1\nversion=%s\nwasm_url=%s\nsha256=%s\nabi_version=%s\nmin_host_version=%s\nweb_ui_url=%s\nicons_url=%s\neffects_url=%s\nicons_sha256=%s\neffects_sha256=%s\n' \
+  "${MANIFEST_VERSION}" "${MANIFEST_WASM_URL}" "${WASM_SHA256}" "${MANIFEST_ABI_VERSION}" \
+  "${MANIFEST_MIN_HOST_VERSION}" "${MANIFEST_WEB_UI_URL}" "${MANIFEST_ICONS_URL}" "${MANIFEST_EFFECTS_URL}" \
+  "${ICONS_SHA256}" "${EFFECTS_SHA256}" \
+  > "${MANIFEST_PAYLOAD_FILE}"
+
+openssl pkeyutl \
+  -sign \
+  -inkey "${PRIVATE_KEY_FILE}" \
+  -rawin \
+  -in "${MANIFEST_PAYLOAD_FILE}" \
+  -out "${MANIFEST_SIG_BIN_FILE}"
+
+printf '{"key_id":"%s","signature":"%s","payload_b64":"%s"}\n' \
+  "${KEY_ID}" \
+  "$(base64 < "${MANIFEST_SIG_BIN_FILE}" | tr -d '\n')" \
+  "$(base64 < "${MANIFEST_PAYLOAD_FILE}" | tr -d '\n')" \
+  > "${MANIFEST_SIG_FILE}"
+
 echo "Signed bundle: ${WASM_FILE}"
 echo "Signature (base64): ${SIG_FILE}"
+echo "Manifest signature: ${MANIFEST_SIG_FILE} (key ${KEY_ID})"
 echo "Public key (base64): ${PUBLIC_KEY_B64_FILE}"
@@ -286,7 +286,7 @@ main() {
   cp "${WASM_BUILD_OUTPUT}" "${WASM_ASSET_TARGET}"
 
   log "Signing wasm bundle..."
-  "${SIGN_SCRIPT}"
+  EVERGREEN_WEB_UI_URL="${UI_URL}" "${SIGN_SCRIPT}"
 
   export_runtime_env
 
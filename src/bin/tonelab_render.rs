@@ -0,0 +1,22 @@
+use tonelab_vst::render::{parse_render_args, run_render, RENDER_HELP_TEXT};
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_render_args(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{RENDER_HELP_TEXT}");
+            return;
+        }
+        Err(error) => {
+            eprintln!("{error}\n\n{RENDER_HELP_TEXT}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(error) = run_render(&options) {
+        eprintln!("render failed: {error}");
+        std::process::exit(1);
+    }
+    println!("Rendered {}", options.output.display());
+}
@@ -0,0 +1,630 @@
+//! HTTP fetching for Evergreen: bounded bodies, retries with backoff,
+//! conditional requests for the sync manifest and resumable bundle
+//! downloads, through the proxy the environment asks for.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Overrides the proxy from `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY`; `direct`
+/// turns proxying off.
+const ENV_PROXY: &str = "TONELAB_EVERGREEN_PROXY";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(8);
+/// Applies to each read, so a large bundle on a slow link is not cut off as
+/// long as bytes keep arriving.
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+const COPY_CHUNK: usize = 64 * 1024;
+
+pub(crate) const MAX_MANIFEST_BYTES: u64 = 1024 * 1024;
+pub(crate) const MAX_BUNDLE_BYTES: u64 = 64 * 1024 * 1024;
+/// Icons, effects catalog and the cached UI page.
+pub(crate) const MAX_ASSET_BYTES: u64 = 16 * 1024 * 1024;
+
+/// How often a request is tried and how long to wait in between. Transport
+/// errors, 5xx and 429 are retried; the delay doubles up to `max_delay`
+/// unless the server sends `Retry-After`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Retry {
+    pub attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Retry {
+    pub(crate) const DEFAULT: Retry = Retry {
+        attempts: 3,
+        initial_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(8),
+    };
+}
+
+/// Cache validators a server sent with a response.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_response(response: &ureq::Response) -> Self {
+        let header = |name| {
+            response
+                .header(name)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        Self {
+            etag: header("ETag"),
+            last_modified: header("Last-Modified"),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+
+    /// Value for `If-Range`: the ETag when there is one, as it is exact.
+    fn if_range(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Conditional {
+    NotModified,
+    Modified {
+        body: Vec<u8>,
+        validators: Validators,
+    },
+}
+
+enum Failure {
+    /// Worth another attempt, after the server's `Retry-After` if it sent one.
+    Transient(String, Option<Duration>),
+    Fatal(String),
+}
+
+pub(crate) fn get(url: &str, limit: u64, retry: Retry) -> Result<Vec<u8>, String> {
+    with_retries(retry, || {
+        let response = checked(agent_for(url)?.get(url).call())?;
+        read_body(url, response, limit)
+    })
+}
+
+/// GETs `url`, revalidating against `known` so an unchanged resource costs a
+/// `304` instead of its body.
+pub(crate) fn get_conditional(
+    url: &str,
+    limit: u64,
+    known: Option<&Validators>,
+    retry: Retry,
+) -> Result<Conditional, String> {
+    with_retries(retry, || {
+        let mut request = agent_for(url)?.get(url);
+        if let Some(known) = known {
+            if let Some(etag) = &known.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &known.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+        let response = checked(request.call())?;
+        if response.status() == 304 {
+            return match known {
+                Some(_) => Ok(Conditional::NotModified),
+                None => Err(Failure::Fatal(format!(
+                    "'{}' answered 304 to an unconditional request",
+                    url
+                ))),
+            };
+        }
+        let validators = Validators::from_response(&response);
+        let body = read_body(url, response, limit)?;
+        Ok(Conditional::Modified { body, validators })
+    })
+}
+
+/// GETs `url` into `partial`, picking up where an earlier attempt (in this
+/// call or a previous one) stopped. The server's validator is kept next to
+/// the partial file and sent as `If-Range`, so a changed resource restarts
+/// from scratch. The partial file is removed once the body is complete.
+pub(crate) fn get_resumable(
+    url: &str,
+    limit: u64,
+    partial: &Path,
+    retry: Retry,
+) -> Result<Vec<u8>, String> {
+    if let Some(dir) = partial.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("failed to create '{}': {}", dir.display(), e))?;
+    }
+    let validator_path = validator_path(partial);
+    with_retries(retry, || {
+        let mut resume_from = std::fs::metadata(partial)
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+        if resume_from > limit {
+            remove_partial(partial);
+            resume_from = 0;
+        }
+        let validators = std::fs::read(&validator_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<Validators>(&bytes).ok())
+            .unwrap_or_default();
+        let mut request = agent_for(url)?.get(url);
+        if resume_from > 0 {
+            request = request.set("Range", &format!("bytes={}-", resume_from));
+            if let Some(validator) = validators.if_range() {
+                request = request.set("If-Range", validator);
+            }
+        }
+        let response = match request.call() {
+            Err(ureq::Error::Status(416, _)) => {
+                // The partial file is no prefix of what the server has now.
+                remove_partial(partial);
+                return Err(Failure::Transient(
+                    "HTTP status 416".to_string(),
+                    Some(Duration::ZERO),
+                ));
+            }
+            result => checked(result)?,
+        };
+        let resumed = response.status() == 206
+            && resume_from > 0
+            && response
+                .header("Content-Range")
+                .is_some_and(|range| range.trim().starts_with(&format!("bytes {}-", resume_from)));
+        if response.status() == 206 && !resumed {
+            // Some other part of the body: it can neither be appended nor
+            // stand for the whole. Start over without a range.
+            remove_partial(partial);
+            return Err(Failure::Transient(
+                format!("'{}' answered with a range that was not asked for", url),
+                Some(Duration::ZERO),
+            ));
+        }
+        let mut file = if resumed {
+            std::fs::OpenOptions::new().append(true).open(partial)
+        } else {
+            let validators = Validators::from_response(&response);
+            let _ = std::fs::write(
+                &validator_path,
+                serde_json::to_vec(&validators).unwrap_or_default(),
+            );
+            std::fs::File::create(partial)
+        }
+        .map_err(|e| Failure::Fatal(format!("failed to open '{}': {}", partial.display(), e)))?;
+        let offset = if resumed { resume_from } else { 0 };
+        check_content_length(url, &response, limit.saturating_sub(offset))?;
+
+        let mut reader = response.into_reader().take(limit - offset + 1);
+        let mut written = offset;
+        let mut chunk = vec![0u8; COPY_CHUNK];
+        loop {
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // What arrived so far stays on disk for the next attempt.
+                    let _ = file.sync_data();
+                    return Err(Failure::Transient(
+                        format!("failed to read response body from '{}': {}", url, e),
+                        None,
+                    ));
+                }
+            };
+            written += read as u64;
+            if written > limit {
+                drop(file);
+                remove_partial(partial);
+                return Err(Failure::Fatal(too_large(url, limit)));
+            }
+            file.write_all(&chunk[..read]).map_err(|e| {
+                Failure::Fatal(format!("failed to write '{}': {}", partial.display(), e))
+            })?;
+        }
+        drop(file);
+        let bytes = std::fs::read(partial).map_err(|e| {
+            Failure::Fatal(format!("failed to read '{}': {}", partial.display(), e))
+        })?;
+        remove_partial(partial);
+        Ok(bytes)
+    })
+}
+
+/// Deletes a partial download and its validator.
+pub(crate) fn remove_partial(partial: &Path) {
+    let _ = std::fs::remove_file(partial);
+    let _ = std::fs::remove_file(validator_path(partial));
+}
+
+fn validator_path(partial: &Path) -> PathBuf {
+    let mut name = partial.file_name().unwrap_or_default().to_os_string();
+    name.push(".validators");
+    partial.with_file_name(name)
+}
+
+fn with_retries<T>(
+    retry: Retry,
+    mut attempt: impl FnMut() -> Result<T, Failure>,
+) -> Result<T, String> {
+    let mut delay = retry.initial_delay;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match attempt() {
+            Ok(value) => return Ok(value),
+            Err(Failure::Fatal(message)) => return Err(message),
+            Err(Failure::Transient(message, _)) if attempts >= retry.attempts.max(1) => {
+                return Err(if attempts > 1 {
+                    format!("{} (after {} attempts)", message, attempts)
+                } else {
+                    message
+                });
+            }
+            Err(Failure::Transient(_, retry_after)) => {
+                std::thread::sleep(retry_after.unwrap_or(delay).min(retry.max_delay));
+                delay = (delay * 2).min(retry.max_delay);
+            }
+        }
+    }
+}
+
+fn checked(result: Result<ureq::Response, ureq::Error>) -> Result<ureq::Response, Failure> {
+    match result {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(code, response)) => {
+            let message = format!("HTTP status {}", code);
+            if code == 429 || (500..600).contains(&code) {
+                let retry_after = response
+                    .header("Retry-After")
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                Err(Failure::Transient(message, retry_after))
+            } else {
+                Err(Failure::Fatal(message))
+            }
+        }
+        Err(ureq::Error::Transport(err)) => {
+            let message = format!("transport error: {}", err);
+            match err.kind() {
+                ureq::ErrorKind::InvalidUrl
+                | ureq::ErrorKind::UnknownScheme
+                | ureq::ErrorKind::InvalidProxyUrl => Err(Failure::Fatal(message)),
+                _ => Err(Failure::Transient(message, None)),
+            }
+        }
+    }
+}
+
+fn read_body(url: &str, response: ureq::Response, limit: u64) -> Result<Vec<u8>, Failure> {
+    check_content_length(url, &response, limit)?;
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(limit + 1)
+        .read_to_end(&mut body)
+        .map_err(|e| {
+            Failure::Transient(
+                format!("failed to read response body from '{}': {}", url, e),
+                None,
+            )
+        })?;
+    if body.len() as u64 > limit {
+        return Err(Failure::Fatal(too_large(url, limit)));
+    }
+    Ok(body)
+}
+
+/// Refuses an oversized body before reading any of it.
+fn check_content_length(url: &str, response: &ureq::Response, limit: u64) -> Result<(), Failure> {
+    let length = response
+        .header("Content-Length")
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    match length {
+        Some(length) if length > limit => Err(Failure::Fatal(too_large(url, limit))),
+        _ => Ok(()),
+    }
+}
+
+fn too_large(url: &str, limit: u64) -> String {
+    format!("response from '{}' exceeds {} bytes", url, limit)
+}
+
+/// One agent per proxy, so connections are pooled across requests.
+fn agent_for(url: &str) -> Result<ureq::Agent, Failure> {
+    static AGENTS: OnceLock<Mutex<HashMap<Option<String>, ureq::Agent>>> = OnceLock::new();
+    let proxy = proxy_for(url, |name| std::env::var(name).ok());
+    let mut agents = AGENTS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(agent) = agents.get(&proxy) {
+        return Ok(agent.clone());
+    }
+    let mut builder = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT);
+    if let Some(proxy) = &proxy {
+        let parsed = ureq::Proxy::new(proxy)
+            .map_err(|e| Failure::Fatal(format!("invalid proxy '{}': {}", proxy, e)))?;
+        builder = builder.proxy(parsed);
+    }
+    let agent = builder.build();
+    agents.insert(proxy, agent.clone());
+    Ok(agent)
+}
+
+/// Proxy for `url`: `TONELAB_EVERGREEN_PROXY` if set, otherwise the usual
+/// `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` (either case) unless `NO_PROXY`
+/// lists the host.
+fn proxy_for(url: &str, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let lookup = |names: &[&str]| {
+        names
+            .iter()
+            .filter_map(|name| var(name))
+            .map(|value| value.trim().to_string())
+            .find(|value| !value.is_empty())
+    };
+    if let Some(proxy) = lookup(&[ENV_PROXY]) {
+        return (!proxy.eq_ignore_ascii_case("direct")).then_some(proxy);
+    }
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+    let host = match authority.strip_prefix('[') {
+        Some(v6) => v6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    if let Some(no_proxy) = lookup(&["NO_PROXY", "no_proxy"]) {
+        if bypasses_proxy(host, &no_proxy) {
+            return None;
+        }
+    }
+    let scheme_vars: &[&str] = if scheme.eq_ignore_ascii_case("https") {
+        &["HTTPS_PROXY", "https_proxy"]
+    } else {
+        &["HTTP_PROXY", "http_proxy"]
+    };
+    lookup(scheme_vars).or_else(|| lookup(&["ALL_PROXY", "all_proxy"]))
+}
+
+/// Whether `NO_PROXY` covers `host`: `*`, the host itself, or a parent
+/// domain (with or without a leading dot).
+fn bypasses_proxy(host: &str, no_proxy: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    no_proxy.split(',').any(|entry| {
+        let entry = entry.trim().to_ascii_lowercase();
+        let entry = entry.split(':').next().unwrap_or_default();
+        let domain = entry.trim_start_matches('.');
+        entry == "*"
+            || (!domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain))))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    const QUICK: Retry = Retry {
+        attempts: 3,
+        initial_delay: Duration::from_millis(1),
+        max_delay: Duration::from_millis(5),
+    };
+
+    /// Serves one scripted raw response per connection and reports each
+    /// request's head.
+    fn stand_in(responses: Vec<Vec<u8>>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind stand-in");
+        let url = format!("http://{}/vst/sync", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let _ = sender.send(head);
+                let _ = stream.write_all(&response);
+            }
+        });
+        (url, requests)
+    }
+
+    fn response(status: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+        let mut bytes = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            bytes.push_str(header);
+            bytes.push_str("\r\n");
+        }
+        bytes.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+        let mut bytes = bytes.into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn no_proxy_env(_: &str) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn retries_server_errors_with_backoff() {
+        let (url, requests) = stand_in(vec![
+            response("503 Service Unavailable", &["Retry-After: 0"], b""),
+            response("502 Bad Gateway", &[], b""),
+            response("200 OK", &[], b"manifest"),
+        ]);
+        assert_eq!(get(&url, 64, QUICK).expect("third attempt"), b"manifest");
+        assert_eq!(requests.try_iter().count(), 3);
+
+        let (url, requests) = stand_in(vec![response("404 Not Found", &[], b"")]);
+        assert_eq!(get(&url, 64, QUICK).unwrap_err(), "HTTP status 404");
+        assert_eq!(requests.try_iter().count(), 1);
+
+        let (url, _) = stand_in(vec![response("500 Internal Server Error", &[], b""); 3]);
+        let error = get(&url, 64, QUICK).unwrap_err();
+        assert!(error.contains("after 3 attempts"), "{}", error);
+    }
+
+    #[test]
+    fn revalidates_with_etag_and_last_modified() {
+        let (url, requests) = stand_in(vec![
+            response(
+                "200 OK",
+                &[
+                    "ETag: \"v1\"",
+                    "Last-Modified: Sat, 17 Oct 2026 10:00:00 GMT",
+                ],
+                b"{}",
+            ),
+            response("304 Not Modified", &[], b""),
+        ]);
+        let Conditional::Modified { body, validators } =
+            get_conditional(&url, 64, None, QUICK).expect("first fetch")
+        else {
+            panic!("first fetch has a body");
+        };
+        assert_eq!(body, b"{}");
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+
+        let second = get_conditional(&url, 64, Some(&validators), QUICK).expect("revalidate");
+        assert!(matches!(second, Conditional::NotModified));
+        let heads: Vec<String> = requests.try_iter().collect();
+        assert!(!heads[0].to_ascii_lowercase().contains("if-none-match"));
+        assert!(heads[1].contains("If-None-Match: \"v1\""), "{}", heads[1]);
+        assert!(heads[1].contains("If-Modified-Since: Sat, 17 Oct 2026 10:00:00 GMT"));
+    }
+
+    #[test]
+    fn resumes_a_cut_download_with_range() {
+        let dir =
+            std::env::temp_dir().join(format!("tonelab_download_resume_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let partial = dir.join("bundle.part");
+        let body = b"0123456789abcdef";
+        // Promises the whole body but hangs up after ten bytes.
+        let mut cut = response("200 OK", &["ETag: \"wasm\""], body);
+        cut.truncate(cut.len() - 6);
+        let (url, requests) = stand_in(vec![
+            cut,
+            response(
+                "206 Partial Content",
+                &["Content-Range: bytes 10-15/16"],
+                &body[10..],
+            ),
+        ]);
+
+        let bytes = get_resumable(&url, 64, &partial, QUICK).expect("resumed download");
+        assert_eq!(bytes, body);
+        let heads: Vec<String> = requests.try_iter().collect();
+        assert_eq!(heads.len(), 2);
+        assert!(heads[1].contains("Range: bytes=10-"), "{}", heads[1]);
+        assert!(heads[1].contains("If-Range: \"wasm\""), "{}", heads[1]);
+        assert!(!partial.exists());
+        assert!(!validator_path(&partial).exists());
+
+        // A range other than the one asked for is dropped for a fresh request.
+        std::fs::write(&partial, b"0123456789").unwrap();
+        let (url, requests) = stand_in(vec![
+            response(
+                "206 Partial Content",
+                &["Content-Range: bytes 4-15/16"],
+                &body[4..],
+            ),
+            response("200 OK", &[], body),
+        ]);
+        assert_eq!(get_resumable(&url, 64, &partial, QUICK).unwrap(), body);
+        let heads: Vec<String> = requests.try_iter().collect();
+        assert!(heads[0].contains("Range: bytes=10-"), "{}", heads[0]);
+        assert!(!heads[1].contains("Range:"), "{}", heads[1]);
+
+        // A server that ignores the range sends the whole body again.
+        std::fs::write(&partial, b"stale").unwrap();
+        let (url, _) = stand_in(vec![response("200 OK", &[], body)]);
+        assert_eq!(get_resumable(&url, 64, &partial, QUICK).unwrap(), body);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn refuses_oversized_bodies() {
+        let (url, _) = stand_in(vec![response("200 OK", &[], &[0u8; 32])]);
+        let error = get(&url, 16, QUICK).unwrap_err();
+        assert!(error.contains("exceeds 16 bytes"), "{}", error);
+
+        // Without a Content-Length the cap applies while reading.
+        let mut unbounded = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+        unbounded.extend_from_slice(&[0u8; 32]);
+        let (url, _) = stand_in(vec![unbounded]);
+        assert!(get(&url, 16, QUICK)
+            .unwrap_err()
+            .contains("exceeds 16 bytes"));
+    }
+
+    #[test]
+    fn picks_proxy_from_environment() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        assert_eq!(
+            proxy_for("https://example.com/vst/sync", no_proxy_env),
+            None
+        );
+
+        let vars = env(&[
+            ("HTTPS_PROXY", "http://secure:3128"),
+            ("http_proxy", "http://plain:3128"),
+            ("NO_PROXY", "localhost, .internal.example"),
+        ]);
+        assert_eq!(
+            proxy_for("https://example.com/vst/sync", vars).as_deref(),
+            Some("http://secure:3128")
+        );
+        assert_eq!(
+            proxy_for("http://example.com/ui", vars).as_deref(),
+            Some("http://plain:3128")
+        );
+        assert_eq!(proxy_for("http://localhost:8787/vst/sync", vars), None);
+        assert_eq!(proxy_for("https://cdn.internal.example/a", vars), None);
+        assert_eq!(proxy_for("https://internal.example/a", vars), None);
+
+        let vars = env(&[
+            (ENV_PROXY, "socks5://tunnel:1080"),
+            ("HTTPS_PROXY", "http://secure:3128"),
+            ("NO_PROXY", "*"),
+        ]);
+        assert_eq!(
+            proxy_for("https://example.com/", vars).as_deref(),
+            Some("socks5://tunnel:1080")
+        );
+        let vars = env(&[(ENV_PROXY, "direct"), ("ALL_PROXY", "http://all:3128")]);
+        assert_eq!(proxy_for("https://example.com/", vars), None);
+        let vars = env(&[("ALL_PROXY", "http://all:3128")]);
+        assert_eq!(
+            proxy_for("https://example.com/", vars).as_deref(),
+            Some("http://all:3128")
+        );
+    }
+}
@@ -1,21 +1,243 @@
-use base64::Engine as _;
+use crate::download::{self, Conditional, Validators};
+use crate::tone_engine::{EngineBackend, NativeEngine, NullEngine, ToneEngine};
+use crate::trust::{KeyStatement, TrustStore};
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
-use std::time::Duration;
-use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, SystemTime};
+use tonelab_wasm_engine::{ABI_VERSION, CAP_GET_CHAIN_JSON, CAP_LAST_ERROR, CAP_SET_PARAM_JSON};
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+    TypedFunc,
+};
 
 const ENV_ENABLED: &str = "TONELAB_EVERGREEN_ENABLED";
 const ENV_SYNC_URL: &str = "TONELAB_EVERGREEN_SYNC_URL";
 const ENV_ALLOW_UNSIGNED: &str = "TONELAB_EVERGREEN_ALLOW_UNSIGNED";
 const ENV_PUBLIC_KEY_B64: &str = "TONELAB_EVERGREEN_ED25519_PUBLIC_KEY_B64";
+const ENV_MAX_MEMORY_MB: &str = "TONELAB_EVERGREEN_MAX_MEMORY_MB";
+const ENV_PROCESS_DEADLINE_MS: &str = "TONELAB_EVERGREEN_PROCESS_DEADLINE_MS";
+const ENV_CHANNEL: &str = "TONELAB_EVERGREEN_CHANNEL";
+const ENV_WATCH: &str = "TONELAB_EVERGREEN_WATCH";
 const EMBEDDED_PUBLIC_KEY_B64: Option<&str> = option_env!("TONELAB_EVERGREEN_PUBLIC_KEY_B64");
 const DEFAULT_SYNC_URL: &str =
     "https://tonelab-vst-api-1017035171566.us-central1.run.app/vst/sync";
 const CACHE_DIR_NAME: &str = "evergreen_cache";
+/// Per-user Evergreen settings, next to the cache in the data dir.
+const SETTINGS_FILE: &str = "evergreen_settings.json";
 const CACHE_MANIFEST_FILE: &str = "sync_manifest.json";
 const CACHE_WASM_FILE: &str = "engine.wasm";
+const CACHE_BUNDLES_DIR: &str = "bundles";
+const CACHE_STATE_FILE: &str = "cache_state.json";
+const CACHE_LOCK_FILE: &str = ".lock";
+const CACHE_COMPILED_DIR: &str = "compiled";
+const PRECOMPILED_EXTENSION: &str = "cwasm";
+const CACHE_ASSETS_DIR: &str = "assets";
+/// Last sync manifest fetched over HTTP, revalidated on the next check.
+const CACHE_SYNC_RESPONSE_FILE: &str = "sync_response.json";
+/// Interrupted bundle downloads, resumed on the next check.
+const CACHE_PARTIAL_DIR: &str = "partial";
+/// Scheme of the custom protocol the editor serves cached assets on.
+pub const ASSET_PROTOCOL: &str = "tonelab";
+const ASSET_PROTOCOL_HOST: &str = "assets";
+/// Path the cached web UI page is served at under `ASSET_PROTOCOL`.
+const UI_ENTRY_FILE: &str = "index.html";
+/// Bundles kept besides the known-good one; older ones are garbage collected.
+const CACHE_KEEP_BUNDLES: usize = 3;
+/// Rolled-back bundle hashes remembered so a server still offering them is ignored.
+const CACHE_KEEP_BAD: usize = 16;
+/// Seconds of audio a bundle must process without error to become known-good.
+const KNOWN_GOOD_AFTER_SECS: u64 = 30;
+/// Deadline misses that roll a bundle back; fewer only reload it.
+const DEADLINE_STRIKES: u32 = 3;
+/// Seconds of clean audio after which a bundle's deadline misses are forgotten.
+const DEADLINE_STRIKES_RESET_SECS: u64 = 60;
+const HOST_VERSION: &str = env!("CARGO_PKG_VERSION");
+const MANIFEST_SIGNATURE_TAG: &str = "tonelab-evergreen-manifest-v1";
+/// Oldest guest ABI this host can still drive. Guests that predate the
+/// `abi_version` export are assumed to speak this version.
+const MIN_GUEST_ABI_VERSION: u32 = 1;
+/// Newest guest ABI this host can drive: whatever the linked `dsp_core` speaks.
+const MAX_GUEST_ABI_VERSION: u32 = ABI_VERSION;
+const DEFAULT_MAX_MEMORY_MB: usize = 64;
+const DEFAULT_PROCESS_DEADLINE_MS: u64 = 100;
+/// Chain rebuilds allocate delay lines and reverb buffers, so control calls
+/// (instantiation, `set_chain_json`, ...) get a looser deadline than `process`.
+const CONTROL_DEADLINE_MS: u64 = 1_000;
+/// Granularity of wasm deadlines: the epoch ticker bumps the engine epoch
+/// this often while any runtime is alive.
+const EPOCH_TICK: Duration = Duration::from_millis(1);
+/// Largest zstd window a wasm delta may use: enough to reach back over a
+/// bundle at the download cap.
+const DELTA_WINDOW_LOG_MAX: u32 = 27;
+/// Longest guest `last_error` message read back; the rest is cut off.
+const MAX_GUEST_ERROR_BYTES: usize = 4 << 10;
+/// Largest chain JSON accepted back from `get_chain_json`.
+const MAX_GUEST_CHAIN_JSON_BYTES: usize = 1 << 20;
+/// How often watch mode looks at a local bundle source for changes.
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Why an Evergreen operation failed. The kind tells the host what to do
+/// about it: try again later ([`EvergreenError::is_transient`]), roll the
+/// bundle back ([`EvergreenError::is_bundle_fault`]) or show the user what
+/// was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvergreenError {
+    /// The sync server or a download could not be reached, answered with an
+    /// error or sent more than allowed.
+    Network(String),
+    /// A signature, hash pin or trust statement did not verify.
+    Signature(String),
+    /// Reading or writing the cache or the settings file failed.
+    Cache(String),
+    /// The bundle does not speak a guest ABI this host can drive.
+    Abi(String),
+    /// The guest trapped, ran out of memory or otherwise misbehaved at runtime.
+    Trap(String),
+    /// A guest call ran past its time budget and was interrupted. One miss
+    /// may just be a busy machine, so it is not a bundle fault by itself.
+    Deadline(String),
+    /// The host failed on its own side, e.g. a poisoned lock; says nothing
+    /// about the bundle.
+    Host(String),
+    /// A manifest, channel, chain or buffer was rejected by the host.
+    Validation(String),
+    /// A guest call returned a non-zero status, with the guest's own
+    /// explanation when it exports `last_error`.
+    GuestStatus {
+        call: &'static str,
+        status: i32,
+        message: Option<String>,
+    },
+}
+
+impl EvergreenError {
+    /// Short name of the kind, for logs and the UI.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::Signature(_) => "signature",
+            Self::Cache(_) => "cache",
+            Self::Abi(_) => "abi",
+            Self::Trap(_) => "trap",
+            Self::Deadline(_) => "deadline",
+            Self::Host(_) => "host",
+            Self::Validation(_) => "validation",
+            Self::GuestStatus { .. } => "guest_status",
+        }
+    }
+
+    /// Whether the same request may succeed later without anything changing
+    /// on this side.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Network(_))
+    }
+
+    /// Whether the running bundle itself misbehaved, as opposed to being
+    /// handed input it rightly refused. Only these roll the bundle back.
+    pub fn is_bundle_fault(&self) -> bool {
+        matches!(self, Self::Abi(_) | Self::Trap(_))
+    }
+
+    /// Prefixes the message with `context`, keeping the kind.
+    fn context(self, context: impl std::fmt::Display) -> Self {
+        self.map_message(|message| format!("{}: {}", context, message))
+    }
+
+    fn map_message(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            Self::Network(message) => Self::Network(f(message)),
+            Self::Signature(message) => Self::Signature(f(message)),
+            Self::Cache(message) => Self::Cache(f(message)),
+            Self::Abi(message) => Self::Abi(f(message)),
+            Self::Trap(message) => Self::Trap(f(message)),
+            Self::Deadline(message) => Self::Deadline(f(message)),
+            Self::Host(message) => Self::Host(f(message)),
+            Self::Validation(message) => Self::Validation(f(message)),
+            // The status is the whole message; callers match on it.
+            status @ Self::GuestStatus { .. } => status,
+        }
+    }
+}
+
+/// Folds the errors of several attempts into one, reported under the kind
+/// of the first. A guest status has no room for the others, so next to them
+/// it counts as the bundle not being drivable.
+fn join_errors(errors: Vec<EvergreenError>) -> Option<EvergreenError> {
+    let mut errors = errors.into_iter();
+    let first = errors.next()?;
+    let rest: Vec<String> = errors.map(|error| error.to_string()).collect();
+    if rest.is_empty() {
+        return Some(first);
+    }
+    let first = match first {
+        status @ EvergreenError::GuestStatus { .. } => EvergreenError::Abi(status.to_string()),
+        first => first,
+    };
+    Some(first.map_message(|message| format!("{}; {}", message, rest.join("; "))))
+}
+
+impl std::fmt::Display for EvergreenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(message)
+            | Self::Signature(message)
+            | Self::Cache(message)
+            | Self::Abi(message)
+            | Self::Trap(message)
+            | Self::Deadline(message)
+            | Self::Host(message)
+            | Self::Validation(message) => f.write_str(message),
+            Self::GuestStatus {
+                call,
+                status,
+                message,
+            } => {
+                write!(f, "wasm {} returned status {}", call, status)?;
+                match message {
+                    Some(message) => write!(f, ": {}", message),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvergreenError {}
+
+/// What the editor gets in IPC replies: the `kind`, a `message` (the guest's
+/// own for guest statuses) and, for guest statuses, the `call` and `status`.
+impl Serialize for EvergreenError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("kind", self.kind())?;
+        match self {
+            Self::GuestStatus {
+                call,
+                status,
+                message,
+            } => {
+                map.serialize_entry("call", call)?;
+                map.serialize_entry("status", status)?;
+                let message = message.clone().unwrap_or_else(|| self.to_string());
+                map.serialize_entry("message", &message)?;
+            }
+            _ => map.serialize_entry("message", &self.to_string())?,
+        }
+        map.end()
+    }
+}
+
+impl From<EvergreenError> for String {
+    fn from(error: EvergreenError) -> Self {
+        error.to_string()
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct SyncAssets {
@@ -25,6 +247,144 @@ pub struct SyncAssets {
     pub web_ui_url: String,
     #[serde(default)]
     pub effects_url: String,
+    /// Hex SHA-256 of the file at `icons_url`. Pinned assets only reach the UI
+    /// as a verified cached copy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icons_sha256: Option<String>,
+    /// Hex SHA-256 of the file at `effects_url`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects_sha256: Option<String>,
+}
+
+/// Manifest assets that can be pinned by hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinnedAsset {
+    Icons,
+    Effects,
+}
+
+impl PinnedAsset {
+    pub const ALL: [Self; 2] = [Self::Icons, Self::Effects];
+
+    /// Path the asset is served at under `ASSET_PROTOCOL`.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::Icons => "icons.zip",
+            Self::Effects => "effects_manifest.json",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Icons => "application/zip",
+            Self::Effects => "application/json",
+        }
+    }
+
+    /// The asset's URL and expected hash, if the manifest pins it.
+    fn pin(self, assets: &SyncAssets) -> Option<(&str, String)> {
+        let (url, sha256) = match self {
+            Self::Icons => (&assets.icons_url, &assets.icons_sha256),
+            Self::Effects => (&assets.effects_url, &assets.effects_sha256),
+        };
+        let sha256 = sha256.as_deref().map(str::trim).filter(|v| !v.is_empty())?;
+        Some((url.trim(), sha256.to_ascii_lowercase()))
+    }
+}
+
+/// Which bundles the plugin follows: the `stable` or `beta` release line, or
+/// one exact version.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ReleaseChannel {
+    #[default]
+    Stable,
+    Beta,
+    Pinned(String),
+}
+
+impl ReleaseChannel {
+    /// Parses `stable`, `beta`, `pinned:<version>` or a bare version, which
+    /// pins it.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        match value.to_ascii_lowercase().as_str() {
+            "stable" => return Ok(Self::Stable),
+            "beta" => return Ok(Self::Beta),
+            _ => {}
+        }
+        let version = value.strip_prefix("pinned:").unwrap_or(value).trim();
+        // Anything `compare_versions` can read is a version.
+        if compare_versions(version, version).is_none() {
+            return Err(format!("unknown release channel '{}'", value));
+        }
+        Ok(Self::Pinned(version.to_string()))
+    }
+
+    /// Channel name as sent to the sync endpoint and reported in manifests.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Beta => "beta",
+            Self::Pinned(_) => "pinned",
+        }
+    }
+
+    fn query(&self) -> String {
+        match self {
+            Self::Pinned(version) => {
+                format!("channel=pinned&version={}", percent_encode(version))
+            }
+            channel => format!("channel={}", channel.name()),
+        }
+    }
+
+    /// Whether `manifest` is a bundle of this channel. Manifests from servers
+    /// without channels count as stable.
+    fn matches(&self, manifest: &SyncManifest) -> bool {
+        match self {
+            Self::Pinned(version) => manifest.version.trim() == version,
+            channel => manifest.channel.as_deref().unwrap_or("stable") == channel.name(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReleaseChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pinned(version) => write!(f, "pinned:{}", version),
+            channel => f.write_str(channel.name()),
+        }
+    }
+}
+
+impl TryFrom<String> for ReleaseChannel {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        Self::parse(&value)
+    }
+}
+
+impl From<ReleaseChannel> for String {
+    fn from(channel: ReleaseChannel) -> Self {
+        channel.to_string()
+    }
+}
+
+/// The Evergreen bundle a project was saved with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundlePin {
+    pub version: String,
+    /// Hex SHA-256 of the bundle's wasm, which is also its cache key.
+    pub sha256: String,
+}
+
+/// Settings kept per user in `SETTINGS_FILE`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UserSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    channel: Option<ReleaseChannel>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,13 +395,176 @@ pub struct SyncManifest {
     pub signature: String,
     #[serde(default)]
     pub assets: SyncAssets,
+    /// Hex SHA-256 of `engine.wasm`. Checked on download when the server sends
+    /// it, and always present in cached manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Guest ABI the bundle was built against; absent on older servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi_version: Option<u32>,
+    /// Oldest plugin version allowed to load the bundle.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_host_version: Option<String>,
+    /// ID of the key that produced `signature`/`manifest_signature`. When
+    /// absent every trusted key is tried.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    /// Signature over `signed_payload()`, which pins the wasm by `sha256`
+    /// and covers the version and asset URLs. Supersedes `signature`, which
+    /// only covers the wasm bytes and is still accepted from older servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_signature: Option<String>,
+    /// Key rotations and revocations, applied before the bundle is verified.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_statements: Vec<KeyStatement>,
+    /// Release channel the server answered from (`stable`, `beta` or
+    /// `pinned`); absent on servers without channels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// Patches from earlier bundles to this one. Not signed: the patched
+    /// wasm is checked against the signed `sha256` like a full download.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<WasmDelta>,
+}
+
+/// A zstd `--patch-from` diff that turns the wasm hashing to `from_sha256`
+/// into the manifest's wasm.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasmDelta {
+    pub from_sha256: String,
+    pub url: String,
+    /// Patch size in bytes, so the host can skip patches not worth it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+impl SyncManifest {
+    /// The exact bytes covered by `manifest_signature`: one `name=value` line
+    /// per signed field, in a fixed order.
+    pub fn signed_payload(&self) -> Result<Vec<u8>, EvergreenError> {
+        let abi_version = self.abi_version.map(|v| v.to_string()).unwrap_or_default();
+        let sha256 = self
+            .sha256
+            .as_deref()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let pinned_sha256 = |asset: PinnedAsset| {
+            asset
+                .pin(&self.assets)
+                .map(|(_, sha256)| sha256)
+                .unwrap_or_default()
+        };
+        let icons_sha256 = pinned_sha256(PinnedAsset::Icons);
+        let effects_sha256 = pinned_sha256(PinnedAsset::Effects);
+        let fields = [
+            ("version", self.version.trim()),
+            ("wasm_url", self.wasm_url.trim()),
+            ("sha256", sha256.as_str()),
+            ("abi_version", abi_version.as_str()),
+            (
+                "min_host_version",
+                self.min_host_version.as_deref().unwrap_or_default().trim(),
+            ),
+            ("web_ui_url", self.assets.web_ui_url.trim()),
+            ("icons_url", self.assets.icons_url.trim()),
+            ("effects_url", self.assets.effects_url.trim()),
+            ("icons_sha256", icons_sha256.as_str()),
+            ("effects_sha256", effects_sha256.as_str()),
+        ];
+
+        let mut payload = format!("{}\n", MANIFEST_SIGNATURE_TAG);
+        for (name, value) in fields {
+            if value.contains('\n') {
+                return Err(EvergreenError::Validation(format!(
+                    "sync manifest field {} contains a newline",
+                    name
+                )));
+            }
+            payload.push_str(&format!("{}={}\n", name, value));
+        }
+        Ok(payload.into_bytes())
+    }
+
+    /// Rejects bundles this plugin cannot drive before anything is downloaded.
+    fn check_host_compat(&self) -> Result<(), EvergreenError> {
+        if let Some(abi_version) = self.abi_version {
+            check_abi_version(abi_version)?;
+        }
+
+        let min_host_version = self
+            .min_host_version
+            .as_deref()
+            .map(str::trim)
+            .filter(|value| !value.is_empty());
+        if let Some(min_host_version) = min_host_version {
+            match compare_versions(HOST_VERSION, min_host_version) {
+                Some(Ordering::Less) => {
+                    return Err(EvergreenError::Abi(format!(
+                        "bundle {} requires plugin {} or newer (running {})",
+                        self.version, min_host_version, HOST_VERSION
+                    )))
+                }
+                Some(_) => {}
+                None => {
+                    return Err(EvergreenError::Validation(format!(
+                        "sync manifest has invalid min_host_version '{}'",
+                        min_host_version
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a loaded `engine.wasm` reported about itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EngineAbi {
+    pub version: u32,
+    pub capabilities: u32,
+    /// False for bundles that predate the `abi_version`/`capabilities` exports;
+    /// their capabilities are inferred from the exports they do have.
+    pub handshake: bool,
+}
+
+impl EngineAbi {
+    pub fn supports(&self, capability: u32) -> bool {
+        self.capabilities & capability == capability
+    }
 }
 
 pub struct EvergreenEngine {
     cache: CacheManager,
-    runtime: Option<WasmRuntime>,
+    settings_path: PathBuf,
+    /// Channel saved with the project; overrides the user's choice.
+    project_channel: Option<ReleaseChannel>,
+    /// Bundle the project was saved with. Loaded in preference to newer
+    /// ones, which are only offered as an upgrade.
+    session_pin: Option<BundlePin>,
+    pending_upgrade: Option<PreparedUpdate>,
+    engine: Box<dyn ToneEngine>,
     active_manifest: Option<SyncManifest>,
-    last_error: Option<String>,
+    /// Cache key of the running wasm bundle, if it came from the cache.
+    active_bundle: Option<String>,
+    /// Audio processed cleanly by `active_bundle`; `None` once it is known-good.
+    clean_frames: Option<u64>,
+    /// Bundle that reached known-good on the audio thread, not yet recorded.
+    pending_known_good: Option<String>,
+    /// Bundle fault or deadline miss seen on the audio thread, not yet
+    /// recorded, reloaded or rolled back.
+    pending_fault: Option<BundleFault>,
+    /// Engine faulted out on the audio thread, dropped by the health work.
+    retired: Option<Box<dyn ToneEngine>>,
+    /// Health work was queued since the audio thread last asked.
+    health_due: bool,
+    /// Passing audio through in place of a faulted bundle until a fallback,
+    /// the reloaded bundle or native DSP is installed.
+    awaiting_rollback: bool,
+    /// Deadline misses of the last bundle to miss one.
+    deadline_strikes: Option<DeadlineStrikes>,
+    last_chain_json: Option<String>,
+    last_error: Option<EvergreenError>,
     sample_rate: f32,
 }
 
@@ -49,55 +572,232 @@ impl EvergreenEngine {
     pub fn new(data_dir: PathBuf) -> Self {
         Self {
             cache: CacheManager::new(data_dir.join(CACHE_DIR_NAME)),
-            runtime: None,
+            settings_path: data_dir.join(SETTINGS_FILE),
+            project_channel: None,
+            session_pin: None,
+            pending_upgrade: None,
+            engine: Box::new(NullEngine),
             active_manifest: None,
+            active_bundle: None,
+            clean_frames: None,
+            pending_known_good: None,
+            pending_fault: None,
+            retired: None,
+            health_due: false,
+            awaiting_rollback: false,
+            deadline_strikes: None,
+            last_chain_json: None,
             last_error: None,
             sample_rate: 44_100.0,
         }
     }
 
-    pub fn bootstrap(&mut self) -> Result<(), String> {
+    /// Loads the newest usable cached bundle without touching the network, so
+    /// plugin initialization never waits on a download. When nothing is cached
+    /// (or Evergreen is disabled) the native `dsp_core` engine is activated
+    /// instead so audio keeps flowing through the last known chain; the error is
+    /// still returned so the caller can log it. Newer bundles arrive through
+    /// [`EvergreenUpdater`] and [`EvergreenEngine::install_update`].
+    pub fn bootstrap(&mut self) -> Result<(), EvergreenError> {
         if !evergreen_enabled() {
-            self.runtime = None;
             self.active_manifest = None;
             self.last_error = None;
+            self.activate_native();
             return Ok(());
         }
 
-        self.cache.ensure()?;
-        let sync_url = resolve_sync_url();
-
-        match self.try_online_sync(&sync_url) {
-            Ok((manifest, runtime)) => {
-                let runtime = runtime;
-                let _ = runtime.set_sample_rate(self.sample_rate);
-                self.active_manifest = Some(manifest);
-                self.runtime = Some(runtime);
+        if let Err(err) = self.cache.ensure() {
+            self.active_manifest = None;
+            self.last_error = Some(err.clone());
+            self.activate_native();
+            return Err(err);
+        }
+
+        match self.try_cached_sync(None) {
+            Ok(bundle) => {
+                self.activate_bundle(bundle);
                 self.last_error = None;
                 Ok(())
             }
-            Err(online_err) => match self.try_cached_sync() {
-                Ok((manifest, runtime)) => {
-                    let runtime = runtime;
-                    let _ = runtime.set_sample_rate(self.sample_rate);
-                    self.active_manifest = Some(manifest);
-                    self.runtime = Some(runtime);
-                    self.last_error = Some(format!(
-                        "Online Evergreen sync failed, using cache instead: {}",
-                        online_err
-                    ));
-                    Ok(())
-                }
-                Err(cache_err) => {
-                    let err = format!(
-                        "Evergreen sync failed online and from cache. online='{}' cache='{}'",
-                        online_err, cache_err
-                    );
-                    self.last_error = Some(err.clone());
-                    Err(err)
-                }
-            },
+            Err(cache_err) => {
+                let err = cache_err.context(
+                    "No usable cached Evergreen bundle, using native DSP until the update check finishes",
+                );
+                self.active_manifest = None;
+                self.last_error = Some(err.clone());
+                self.activate_native();
+                Err(err)
+            }
+        }
+    }
+
+    /// Snapshot of what the background update check needs, so it can run
+    /// without holding the engine lock. `None` when Evergreen is disabled.
+    pub fn updater(&self) -> Option<EvergreenUpdater> {
+        evergreen_enabled().then(|| EvergreenUpdater {
+            cache: self.cache.clone(),
+            sync_url: resolve_sync_url(),
+            channel: self.channel(),
+            pin: self.session_pin.clone(),
+            current: self.active_bundle.clone(),
+        })
+    }
+
+    /// Watches the local bundle source in dev watch mode
+    /// (`TONELAB_EVERGREEN_WATCH`). `None` unless the sync URL is a
+    /// `file://` URL or a path.
+    pub fn source_watcher(&self) -> Option<SourceWatcher> {
+        if !evergreen_enabled() || !env_bool(ENV_WATCH).unwrap_or(false) {
+            return None;
+        }
+        local_source_path(&resolve_sync_url()).map(|path| SourceWatcher::new(&path))
+    }
+
+    /// Pins the bundle a reopened project was saved with. Call before
+    /// `bootstrap`, which then loads that bundle if it is cached; otherwise
+    /// the update check fetches it.
+    pub fn set_session_pin(&mut self, pin: Option<BundlePin>) {
+        self.session_pin = pin.map(|pin| BundlePin {
+            version: pin.version.trim().to_string(),
+            sha256: pin.sha256.trim().to_ascii_lowercase(),
+        });
+    }
+
+    pub fn session_pin(&self) -> Option<&BundlePin> {
+        self.session_pin.as_ref()
+    }
+
+    /// The running bundle, if it came from the cache.
+    pub fn active_pin(&self) -> Option<BundlePin> {
+        Some(BundlePin {
+            version: self.active_manifest.as_ref()?.version.clone(),
+            sha256: self.active_bundle.clone()?,
+        })
+    }
+
+    /// What the project should record: its pinned bundle, else the running
+    /// one.
+    pub fn project_bundle(&self) -> Option<BundlePin> {
+        self.session_pin.clone().or_else(|| self.active_pin())
+    }
+
+    /// Whether `update` would move a pinned project to a different bundle,
+    /// which needs the user's consent.
+    pub fn is_upgrade(&self, update: &PreparedUpdate) -> bool {
+        self.session_pin
+            .as_ref()
+            .is_some_and(|pin| pin.sha256 != update.sha256())
+    }
+
+    /// Holds `update` until the user accepts it, replacing any older offer.
+    pub fn offer_upgrade(&mut self, update: PreparedUpdate) {
+        self.pending_upgrade = Some(update);
+    }
+
+    /// Version of the upgrade waiting for the user, if any.
+    pub fn pending_upgrade(&self) -> Option<&str> {
+        self.pending_upgrade.as_ref().map(PreparedUpdate::version)
+    }
+
+    /// Installs the offered upgrade and pins the project to it. Returns the
+    /// replaced engine, as `install_update` does.
+    pub fn accept_upgrade(&mut self) -> Option<Box<dyn ToneEngine>> {
+        let update = self.pending_upgrade.take()?;
+        let previous = self.install_update(update);
+        self.session_pin = self.active_pin();
+        Some(previous)
+    }
+
+    pub fn dismiss_upgrade(&mut self) {
+        self.pending_upgrade = None;
+    }
+
+    /// The channel in effect: the project's, else the user's, else
+    /// `TONELAB_EVERGREEN_CHANNEL`, else stable.
+    pub fn channel(&self) -> ReleaseChannel {
+        self.project_channel
+            .clone()
+            .or_else(|| self.read_user_settings().channel)
+            .or_else(|| {
+                read_env_non_empty(ENV_CHANNEL).and_then(|v| ReleaseChannel::parse(&v).ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Sets or clears the project's channel. Takes effect at the next
+    /// bootstrap or update check.
+    pub fn set_project_channel(&mut self, channel: Option<ReleaseChannel>) {
+        self.project_channel = channel;
+    }
+
+    pub fn project_channel(&self) -> Option<&ReleaseChannel> {
+        self.project_channel.as_ref()
+    }
+
+    /// Saves the channel used by every project that does not set its own.
+    pub fn set_user_channel(&self, channel: Option<ReleaseChannel>) -> Result<(), EvergreenError> {
+        // Every plugin instance shares the settings file, like the cache.
+        self.cache.ensure()?;
+        self.cache.with_lock(|| {
+            let mut settings = self.read_user_settings();
+            settings.channel = channel;
+            let bytes = serde_json::to_vec_pretty(&settings).map_err(|e| {
+                EvergreenError::Cache(format!("failed to serialize Evergreen settings: {}", e))
+            })?;
+            write_atomic(&self.settings_path, &bytes).map_err(|e| {
+                EvergreenError::Cache(format!(
+                    "failed to write Evergreen settings '{}': {}",
+                    self.settings_path.display(),
+                    e
+                ))
+            })
+        })
+    }
+
+    fn read_user_settings(&self) -> UserSettings {
+        std::fs::read(&self.settings_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Hot-swaps to a bundle prepared by [`EvergreenUpdater::check`]. Returns
+    /// the replaced engine so the caller can drop it after releasing the lock.
+    pub fn install_update(&mut self, update: PreparedUpdate) -> Box<dyn ToneEngine> {
+        self.last_error = None;
+        self.activate_bundle(update.0)
+    }
+
+    fn activate_bundle(&mut self, bundle: LoadedBundle) -> Box<dyn ToneEngine> {
+        let previous = self.replace_engine(Box::new(bundle.runtime));
+        self.active_manifest = Some(bundle.manifest);
+        self.clean_frames = (!self.cache.is_known_good(&bundle.key)).then_some(0);
+        self.active_bundle = Some(bundle.key);
+        previous
+    }
+
+    fn activate_native(&mut self) {
+        if self.backend() != EngineBackend::Native {
+            self.replace_engine(Box::new(NativeEngine::new()));
+        }
+    }
+
+    /// Swaps in `engine`, bringing it up to the current sample rate and
+    /// replaying the last chain the UI sent. Returns the engine it replaced.
+    pub fn replace_engine(&mut self, mut engine: Box<dyn ToneEngine>) -> Box<dyn ToneEngine> {
+        self.active_bundle = None;
+        self.clean_frames = None;
+        self.awaiting_rollback = false;
+        let _ = engine.set_sample_rate(self.sample_rate);
+        if let Some(chain_json) = self.last_chain_json.as_deref() {
+            if let Err(error) = engine.sync_chain_json(chain_json) {
+                self.last_error = Some(error.context(format!(
+                    "failed to restore chain on {} engine",
+                    engine.backend().as_str()
+                )));
+            }
         }
+        std::mem::replace(&mut self.engine, engine)
     }
 
     pub fn web_ui_url(&self) -> Option<&str> {
@@ -121,6 +821,27 @@ impl EvergreenEngine {
             .filter(|value| !value.trim().is_empty())
     }
 
+    /// Serves verified copies of pinned assets to the editor. An asset is
+    /// pinned if the active manifest pins it or a verified copy was cached.
+    pub fn asset_server(&self) -> AssetServer {
+        let state = self.cache.read_state();
+        let pinned = PinnedAsset::ALL
+            .into_iter()
+            .filter_map(|asset| {
+                let cached = state.assets.get(asset.file_name()).cloned();
+                let pinned_by_manifest = self
+                    .active_manifest
+                    .as_ref()
+                    .is_some_and(|manifest| asset.pin(&manifest.assets).is_some());
+                (cached.is_some() || pinned_by_manifest).then_some((asset, cached))
+            })
+            .collect();
+        AssetServer {
+            cache: self.cache.clone(),
+            pinned,
+        }
+    }
+
     pub fn active_version(&self) -> Option<&str> {
         self.active_manifest
             .as_ref()
@@ -128,482 +849,4483 @@ impl EvergreenEngine {
             .filter(|value| !value.trim().is_empty())
     }
 
+    /// Channel the running bundle was published on, as its manifest says.
+    pub fn active_channel(&self) -> Option<&str> {
+        self.active_manifest
+            .as_ref()
+            .map(|manifest| manifest.channel.as_deref().unwrap_or("stable"))
+    }
+
+    /// True once a real engine (wasm or native) has replaced the initial
+    /// passthrough. Passthrough in place of a faulted bundle counts: the health
+    /// work is about to replace it, and bootstrapping again could reload the
+    /// faulted bundle before it is marked bad.
     pub fn has_runtime(&self) -> bool {
-        self.runtime.is_some()
+        self.backend() != EngineBackend::Null || self.awaiting_rollback
     }
 
-    pub fn last_error(&self) -> Option<&str> {
-        self.last_error.as_deref()
+    pub fn backend(&self) -> EngineBackend {
+        self.engine.backend()
     }
 
-    #[allow(dead_code)]
-    pub fn set_param(&mut self, effect_idx: i32, key: &str, value: f32) -> Result<(), String> {
-        let runtime = self
-            .runtime
-            .as_mut()
-            .ok_or_else(|| "WASM runtime is not loaded".to_string())?;
-        runtime.set_param_json(effect_idx, key, value)
+    /// Last chain successfully applied, with later param changes folded in.
+    pub fn last_chain_json(&self) -> Option<&str> {
+        self.last_chain_json.as_deref()
     }
 
-    pub fn process_frame(&mut self, l: f32, r: f32) -> Result<(f32, f32), String> {
-        let input = [l, r];
-        let mut output = [0.0f32, 0.0f32];
-        self.process_interleaved_stereo(&input, &mut output)?;
-        Ok((output[0], output[1]))
+    /// The chain as the engine itself reports it, live param changes
+    /// included. Falls back to `last_chain_json` when the engine can't report
+    /// one (bypass, or a guest without `CAP_GET_CHAIN_JSON`).
+    pub fn chain_json(&self) -> Result<Option<String>, EvergreenError> {
+        match self.engine.chain_json()? {
+            Some(chain_json) => Ok(Some(chain_json)),
+            None => Ok(self.last_chain_json.clone()),
+        }
+    }
+
+    /// Most recent failure: a load or rollback, or a chain or param change
+    /// the engine refused (with the guest's explanation when it gives one).
+    pub fn last_error(&self) -> Option<&EvergreenError> {
+        self.last_error.as_ref()
+    }
+
+    pub fn set_param(
+        &mut self,
+        effect_idx: i32,
+        key: &str,
+        value: f32,
+    ) -> Result<(), EvergreenError> {
+        self.engine
+            .set_param(effect_idx, key, value)
+            .inspect_err(|error| self.last_error = Some(error.clone()))?;
+        if let Some(chain_json) = self.last_chain_json.as_deref() {
+            if let Some(patched) = patch_chain_param(chain_json, effect_idx, key, value) {
+                self.last_chain_json = Some(patched);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn process_frame(&mut self, l: f32, r: f32) -> Result<(f32, f32), EvergreenError> {
+        let result = self.engine.process_frame(l, r);
+        self.track_health(1, result.as_ref().err());
+        result
     }
 
     pub fn process_interleaved_stereo(
         &mut self,
         input: &[f32],
         output: &mut [f32],
-    ) -> Result<(), String> {
-        let runtime = self
-            .runtime
-            .as_mut()
-            .ok_or_else(|| "WASM runtime is not loaded".to_string())?;
-        runtime.process_interleaved_stereo(input, output)
+    ) -> Result<(), EvergreenError> {
+        let result = self.engine.process_interleaved_stereo(input, output);
+        self.track_health(input.len() as u64 / 2, result.as_ref().err());
+        result
     }
 
     pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate.clamp(8_000.0, 192_000.0);
-        if let Some(runtime) = self.runtime.as_mut() {
-            let _ = runtime.set_sample_rate(self.sample_rate);
-        }
+        let _ = self.engine.set_sample_rate(self.sample_rate);
     }
 
-    pub fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), String> {
-        let runtime = self
-            .runtime
-            .as_mut()
-            .ok_or_else(|| "WASM runtime is not loaded".to_string())?;
-        runtime.set_chain_json(chain_json)
+    pub fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), EvergreenError> {
+        self.engine
+            .sync_chain_json(chain_json)
+            .inspect_err(|error| self.last_error = Some(error.clone()))?;
+        self.last_chain_json = Some(chain_json.to_string());
+        Ok(())
     }
 
-    fn try_online_sync(&self, sync_url: &str) -> Result<(SyncManifest, WasmRuntime), String> {
-        let manifest = fetch_sync_manifest(sync_url)?;
-        if manifest.wasm_url.trim().is_empty() {
-            return Err("sync manifest has empty wasm_url".to_string());
+    /// Notes when the running bundle has processed `KNOWN_GOOD_AFTER_SECS` of
+    /// audio cleanly, and swaps it for passthrough when the bundle itself
+    /// faults or misses its deadline. Runs on the audio thread, so recording
+    /// either in the cache (and loading a bundle) is left to `take_health_work`.
+    fn track_health(&mut self, frames: u64, error: Option<&EvergreenError>) {
+        if let Some(error) = error {
+            match self.active_bundle.take() {
+                Some(key)
+                    if error.is_bundle_fault() || matches!(error, EvergreenError::Deadline(_)) =>
+                {
+                    self.retire_bundle(key, error)
+                }
+                key => {
+                    self.active_bundle = key;
+                    self.last_error = Some(error.clone());
+                }
+            }
+            return;
         }
 
-        let wasm_bytes = download_bytes(&manifest.wasm_url)?;
-        verify_bundle_signature(&wasm_bytes, &manifest.signature)?;
-
-        self.cache.write_manifest(&manifest)?;
-        self.cache.write_wasm(&wasm_bytes)?;
+        let active = self.active_bundle.as_deref();
+        if let Some(strikes) = self
+            .deadline_strikes
+            .as_mut()
+            .filter(|strikes| active == Some(strikes.key.as_str()))
+        {
+            strikes.clean_frames += frames;
+            if strikes.clean_frames >= self.sample_rate as u64 * DEADLINE_STRIKES_RESET_SECS {
+                self.deadline_strikes = None;
+            }
+        }
 
-        let runtime = WasmRuntime::from_bytes(&wasm_bytes)?;
-        runtime.smoke_test()?;
-        Ok((manifest, runtime))
+        let Some(clean_frames) = self.clean_frames.as_mut() else {
+            return;
+        };
+        *clean_frames += frames;
+        if *clean_frames >= self.sample_rate as u64 * KNOWN_GOOD_AFTER_SECS {
+            self.clean_frames = None;
+            self.pending_known_good = self.active_bundle.clone();
+            self.health_due = true;
+        }
     }
 
-    fn try_cached_sync(&self) -> Result<(SyncManifest, WasmRuntime), String> {
-        let manifest = self.cache.read_manifest()?;
-        let wasm_bytes = self.cache.read_wasm()?;
-        verify_bundle_signature(&wasm_bytes, &manifest.signature)?;
-        let runtime = WasmRuntime::from_bytes(&wasm_bytes)?;
-        runtime.smoke_test()?;
-        Ok((manifest, runtime))
+    /// Swaps bundle `key` for passthrough and queues health work. Building
+    /// native DSP replays the chain, which parses JSON and allocates, so on
+    /// the audio thread the engine is only moved aside; `take_health_work`
+    /// decides between a reload and a rollback.
+    fn retire_bundle(&mut self, key: String, error: &EvergreenError) {
+        self.retired = Some(std::mem::replace(&mut self.engine, Box::new(NullEngine)));
+        self.clean_frames = None;
+        self.awaiting_rollback = true;
+        self.pending_fault = Some(BundleFault {
+            key,
+            manifest: self.active_manifest.take(),
+            error: error.clone(),
+        });
+        self.health_due = true;
     }
-}
 
-#[derive(Debug)]
-struct CacheManager {
-    root: PathBuf,
-}
+    /// Counts a deadline miss of bundle `key` and returns how many it has had
+    /// without a clean stretch in between; forgets them at `DEADLINE_STRIKES`.
+    fn strike_deadline(&mut self, key: &str) -> u32 {
+        let misses = match self
+            .deadline_strikes
+            .as_mut()
+            .filter(|strikes| strikes.key == key)
+        {
+            Some(strikes) => {
+                strikes.misses += 1;
+                strikes.clean_frames = 0;
+                strikes.misses
+            }
+            None => {
+                self.deadline_strikes = Some(DeadlineStrikes {
+                    key: key.to_string(),
+                    misses: 1,
+                    clean_frames: 0,
+                });
+                1
+            }
+        };
+        if misses >= DEADLINE_STRIKES {
+            self.deadline_strikes = None;
+        }
+        misses
+    }
 
-impl CacheManager {
-    fn new(root: PathBuf) -> Self {
-        Self { root }
+    /// True once after the audio thread queued health work, so the caller can
+    /// schedule `take_health_work` on a background thread.
+    pub fn take_health_due(&mut self) -> bool {
+        std::mem::take(&mut self.health_due)
     }
 
-    fn ensure(&self) -> Result<(), String> {
-        std::fs::create_dir_all(&self.root).map_err(|e| {
-            format!(
-                "failed to create evergreen cache directory '{}': {}",
-                self.root.display(),
-                e
-            )
+    /// Cache bookkeeping queued by the audio thread: promoting a known-good
+    /// bundle, marking a faulted one bad and loading the next, or reloading
+    /// one that missed its deadline: an interrupted instance can't be entered
+    /// again, but only `DEADLINE_STRIKES` misses count as a fault. `None` when
+    /// nothing is queued.
+    pub fn take_health_work(&mut self) -> Option<HealthWork> {
+        let (fault, reload) = match self.pending_fault.take() {
+            Some(miss) if matches!(miss.error, EvergreenError::Deadline(_)) => {
+                let misses = self.strike_deadline(&miss.key);
+                if misses < DEADLINE_STRIKES {
+                    (None, Some(miss))
+                } else {
+                    let fault = BundleFault {
+                        error: miss.error.context(format!("missed {} deadlines", misses)),
+                        ..miss
+                    };
+                    (Some(fault), None)
+                }
+            }
+            fault => (fault, None),
+        };
+        if self.pending_known_good.is_none() && fault.is_none() && reload.is_none() {
+            return None;
+        }
+        Some(HealthWork {
+            cache: self.cache.clone(),
+            known_good: self.pending_known_good.take(),
+            fault,
+            reload,
+            retired: self.retired.take(),
         })
     }
 
-    fn manifest_path(&self) -> PathBuf {
-        self.root.join(CACHE_MANIFEST_FILE)
+    /// Applies a finished `HealthWork`: switches to the fallback or reloaded
+    /// bundle, or to native DSP when there is none, unless another engine was
+    /// installed meanwhile. Returns the replaced engine, as `install_update`
+    /// does.
+    pub fn finish_health_work(&mut self, outcome: HealthOutcome) -> Option<Box<dyn ToneEngine>> {
+        if let Some(error) = outcome.error {
+            self.last_error = Some(error);
+        }
+        if let Some((miss, reloaded)) = outcome.reload {
+            if !self.awaiting_rollback {
+                return None;
+            }
+            let version = miss.version();
+            return Some(match reloaded {
+                Ok(bundle) => {
+                    self.last_error = Some(miss.error.context(format!(
+                        "Evergreen bundle {} missed its deadline and was reloaded",
+                        version
+                    )));
+                    self.activate_bundle(bundle)
+                }
+                Err(cache_err) => {
+                    self.last_error = Some(miss.error.context(format!(
+                        "Evergreen bundle {} missed its deadline, using native DSP ({})",
+                        version, cache_err
+                    )));
+                    self.replace_engine(Box::new(NativeEngine::new()))
+                }
+            });
+        }
+        let (fault, fallback) = outcome.rollback?;
+        if !self.awaiting_rollback {
+            return None;
+        }
+        let version = fault.version();
+        Some(match fallback {
+            Ok(bundle) => {
+                self.last_error = Some(fault.error.context(format!(
+                    "Evergreen bundle {} failed at runtime, rolled back to {}",
+                    version, bundle.manifest.version
+                )));
+                self.activate_bundle(bundle)
+            }
+            Err(cache_err) => {
+                self.last_error = Some(fault.error.context(format!(
+                    "Evergreen bundle {} failed at runtime, using native DSP ({})",
+                    version, cache_err
+                )));
+                self.replace_engine(Box::new(NativeEngine::new()))
+            }
+        })
     }
 
-    fn wasm_path(&self) -> PathBuf {
-        self.root.join(CACHE_WASM_FILE)
+    /// Loads the newest usable cached bundle, preferring the known-good one
+    /// when `skip` (a bundle that just failed) is given.
+    fn try_cached_sync(&self, skip: Option<&str>) -> Result<LoadedBundle, EvergreenError> {
+        let mut candidates = self.cache.candidates(skip.is_some());
+        if skip.is_none() {
+            // The project's own bundle first, then bundles of the selected
+            // channel; any other cached bundle still beats native DSP until
+            // the update check fetches the right one. Each manifest is read
+            // once, not on every comparison.
+            let pinned = self.session_pin.as_ref().map(|pin| pin.sha256.as_str());
+            let channel = self.channel();
+            candidates.sort_by_cached_key(|key| {
+                let matches_channel = self
+                    .cache
+                    .read_manifest(key)
+                    .is_ok_and(|manifest| channel.matches(&manifest));
+                (pinned != Some(key.as_str()), !matches_channel)
+            });
+        }
+        load_first_bundle(&self.cache, &candidates, skip)
     }
+}
 
-    fn write_manifest(&self, manifest: &SyncManifest) -> Result<(), String> {
-        let bytes = serde_json::to_vec_pretty(manifest)
-            .map_err(|e| format!("failed to serialize sync manifest: {}", e))?;
-        std::fs::write(self.manifest_path(), bytes)
-            .map_err(|e| format!("failed to write sync manifest cache: {}", e))
+/// Loads the first of `candidates` (other than `skip`) that reads, verifies
+/// and compiles.
+fn load_first_bundle(
+    cache: &CacheManager,
+    candidates: &[String],
+    skip: Option<&str>,
+) -> Result<LoadedBundle, EvergreenError> {
+    let mut errors = Vec::new();
+    for key in candidates.iter().filter(|key| Some(key.as_str()) != skip) {
+        match load_cached_bundle(cache, key) {
+            Ok(bundle) => return Ok(bundle),
+            Err(error) => errors.push(error.context(short_key(key))),
+        }
     }
+    Err(join_errors(errors)
+        .unwrap_or_else(|| EvergreenError::Cache("no cached bundle available".to_string())))
+}
 
-    fn read_manifest(&self) -> Result<SyncManifest, String> {
-        let bytes = std::fs::read(self.manifest_path())
-            .map_err(|e| format!("failed to read cached sync manifest: {}", e))?;
-        serde_json::from_slice::<SyncManifest>(&bytes)
-            .map_err(|e| format!("failed to parse cached sync manifest: {}", e))
-    }
+fn load_cached_bundle(cache: &CacheManager, key: &str) -> Result<LoadedBundle, EvergreenError> {
+    let (manifest, wasm_bytes) = cache.read_bundle(key)?;
+    // The cache may have been written by a newer plugin sharing the data dir.
+    manifest.check_host_compat()?;
+    let trust = cache.trust_store(root_trust_store()?);
+    verify_bundle(&manifest, &wasm_bytes, &trust, allow_unsigned())?;
+    let runtime = load_bundle(&manifest, &wasm_bytes, cache)?;
+    Ok(LoadedBundle {
+        key: key.to_string(),
+        manifest,
+        runtime,
+    })
+}
 
-    fn write_wasm(&self, wasm_bytes: &[u8]) -> Result<(), String> {
-        std::fs::write(self.wasm_path(), wasm_bytes)
-            .map_err(|e| format!("failed to write cached wasm bundle: {}", e))
-    }
+struct BundleFault {
+    key: String,
+    /// Taken from the engine along with the bundle, so it is dropped off the
+    /// audio thread too.
+    manifest: Option<SyncManifest>,
+    error: EvergreenError,
+}
 
-    fn read_wasm(&self) -> Result<Vec<u8>, String> {
-        std::fs::read(self.wasm_path()).map_err(|e| format!("failed to read cached wasm: {}", e))
+impl BundleFault {
+    fn version(&self) -> String {
+        self.manifest
+            .as_ref()
+            .map(|manifest| manifest.version.clone())
+            .unwrap_or_else(|| short_key(&self.key).to_string())
     }
 }
 
-struct WasmRuntime {
-    inner: Mutex<WasmRuntimeInner>,
+struct DeadlineStrikes {
+    key: String,
+    misses: u32,
+    /// Audio the bundle processed cleanly since its last miss.
+    clean_frames: u64,
 }
 
-struct WasmRuntimeInner {
-    store: Store<()>,
-    memory: Memory,
-    alloc_samples: TypedFunc<i32, i32>,
-    alloc_bytes: TypedFunc<i32, i32>,
-    process: TypedFunc<(i32, i32, i32), ()>,
-    set_sample_rate: TypedFunc<f32, i32>,
-    set_chain_json: TypedFunc<(i32, i32), i32>,
-    set_param_json: TypedFunc<(i32, i32), i32>,
+/// Health bookkeeping the audio thread deferred, from
+/// [`EvergreenEngine::take_health_work`]. [`HealthWork::run`] takes the cache
+/// lock, reads disk and may compile a fallback bundle, so it belongs on a
+/// background thread without the engine lock held.
+pub struct HealthWork {
+    cache: CacheManager,
+    known_good: Option<String>,
+    fault: Option<BundleFault>,
+    reload: Option<BundleFault>,
+    retired: Option<Box<dyn ToneEngine>>,
 }
 
-impl WasmRuntime {
-    fn from_bytes(wasm_bytes: &[u8]) -> Result<Self, String> {
-        let engine = Engine::default();
-        let module = Module::new(&engine, wasm_bytes)
-            .map_err(|e| format!("wasm module load failed: {}", e))?;
-        let mut store = Store::new(&engine, ());
-        let instance = Instance::new(&mut store, &module, &[])
-            .map_err(|e| format!("wasm instance creation failed: {}", e))?;
+/// Result of [`HealthWork::run`], for [`EvergreenEngine::finish_health_work`].
+pub struct HealthOutcome {
+    rollback: Option<(BundleFault, Result<LoadedBundle, EvergreenError>)>,
+    reload: Option<(BundleFault, Result<LoadedBundle, EvergreenError>)>,
+    error: Option<EvergreenError>,
+}
 
-        let memory = instance
-            .get_memory(&mut store, "memory")
-            .ok_or_else(|| "wasm export 'memory' is missing".to_string())?;
-        let alloc = instance
-            .get_typed_func::<i32, i32>(&mut store, "alloc")
-            .map_err(|e| format!("wasm export 'alloc' is missing or invalid: {}", e))?;
-        let alloc_bytes = instance
-            .get_typed_func::<i32, i32>(&mut store, "alloc_bytes")
-            .unwrap_or_else(|_| alloc.clone());
-        let process = instance
-            .get_typed_func::<(i32, i32, i32), ()>(&mut store, "process")
-            .map_err(|e| format!("wasm export 'process' is missing or invalid: {}", e))?;
-        let set_sample_rate = instance
-            .get_typed_func::<f32, i32>(&mut store, "set_sample_rate")
-            .map_err(|e| format!("wasm export 'set_sample_rate' is missing or invalid: {}", e))?;
-        let set_chain_json = instance
-            .get_typed_func::<(i32, i32), i32>(&mut store, "set_chain_json")
-            .map_err(|e| format!("wasm export 'set_chain_json' is missing or invalid: {}", e))?;
-        let set_param_json = instance
-            .get_typed_func::<(i32, i32), i32>(&mut store, "set_param_json")
-            .map_err(|e| format!("wasm export 'set_param_json' is missing or invalid: {}", e))?;
+impl HealthWork {
+    pub fn run(self) -> HealthOutcome {
+        let mut errors = Vec::new();
+        if let Some(key) = self.known_good.as_deref() {
+            errors.extend(self.cache.mark_known_good(key).err());
+        }
+        let rollback = self.fault.map(|fault| {
+            errors.extend(self.cache.mark_bad(&fault.key).err());
+            let fallback =
+                load_first_bundle(&self.cache, &self.cache.candidates(true), Some(&fault.key));
+            (fault, fallback)
+        });
+        let reload = self.reload.map(|miss| {
+            let reloaded = load_cached_bundle(&self.cache, &miss.key);
+            (miss, reloaded)
+        });
+        // Only now, so a reload finds the retired engine's module compiled.
+        drop(self.retired);
+        HealthOutcome {
+            rollback,
+            reload,
+            error: join_errors(errors),
+        }
+    }
+}
 
-        Ok(Self {
-            inner: Mutex::new(WasmRuntimeInner {
-                store,
-                memory,
-                alloc_samples: alloc,
-                alloc_bytes,
-                process,
-                set_sample_rate,
-                set_chain_json,
-                set_param_json,
-            }),
+struct LoadedBundle {
+    key: String,
+    manifest: SyncManifest,
+    runtime: WasmRuntime,
+}
+
+/// Network side of Evergreen sync, detached from `EvergreenEngine` so the
+/// download, verification and compilation happen off the audio and UI paths.
+pub struct EvergreenUpdater {
+    cache: CacheManager,
+    sync_url: String,
+    channel: ReleaseChannel,
+    /// Bundle the project was saved with, fetched if it is not running.
+    pin: Option<BundlePin>,
+    /// Cache key of the bundle running when the updater was created.
+    current: Option<String>,
+}
+
+/// Answers the editor's `ASSET_PROTOCOL` requests from the asset cache.
+#[derive(Debug, Clone)]
+pub struct AssetServer {
+    cache: CacheManager,
+    /// Pinned assets and the verified copy to serve, if there is one yet.
+    pinned: Vec<(PinnedAsset, Option<CachedAsset>)>,
+}
+
+impl AssetServer {
+    /// Where the UI should load `asset` from: the custom protocol when a
+    /// verified copy exists, `""` when the asset is pinned but nothing
+    /// verified is available. `None` for unpinned assets, which the UI still
+    /// loads from the manifest URL.
+    pub fn url(&self, asset: PinnedAsset) -> Option<String> {
+        let (_, cached) = self.pinned.iter().find(|(pinned, _)| *pinned == asset)?;
+        Some(match cached {
+            Some(_) => asset_protocol_url(asset.file_name()),
+            None => String::new(),
         })
     }
 
-    fn smoke_test(&self) -> Result<(), String> {
-        let input = [0.0f32, 0.0f32, 0.2f32, -0.2f32, -0.4f32, 0.4f32];
-        let mut output = [0.0f32; 6];
-        self.process_interleaved_stereo(&input, &mut output)?;
-        if output.iter().all(|sample| sample.is_finite()) {
-            Ok(())
-        } else {
-            Err("wasm smoke test produced non-finite samples".to_string())
-        }
+    /// Where the editor should open the UI page at `ui_url` from: the custom
+    /// protocol once a copy of that page is cached, so the editor also opens
+    /// offline. `None` until the first refresh, when the page loads from the
+    /// network.
+    pub fn ui_url(&self, ui_url: &str) -> Option<String> {
+        self.cache
+            .cached_ui(ui_url.trim())
+            .map(|_| asset_protocol_url(UI_ENTRY_FILE))
     }
 
-    fn process_interleaved_stereo(&self, input: &[f32], output: &mut [f32]) -> Result<(), String> {
-        if input.len() != output.len() {
-            return Err("wasm runtime expected output length to match input length".to_string());
-        }
-        if input.len() % 2 != 0 {
-            return Err(
-                "wasm runtime expects interleaved stereo input (even sample count)".to_string(),
-            );
+    /// Body and content type for a request to `path` under `ASSET_PROTOCOL`.
+    /// The cached file is re-hashed on every read.
+    pub fn respond(&self, path: &str) -> Option<(Vec<u8>, &'static str)> {
+        let name = path.trim_start_matches('/');
+        if name.is_empty() || name == UI_ENTRY_FILE {
+            // Read at request time: the page may have been refreshed since
+            // this server was created.
+            let ui = self.cache.read_state().ui?;
+            let page = self.cache.read_asset(&ui.sha256).ok()?;
+            return Some((page, "text/html; charset=utf-8"));
         }
+        let (asset, cached) = self
+            .pinned
+            .iter()
+            .find(|(asset, _)| asset.file_name() == name)?;
+        let cached = cached.as_ref()?;
+        let bytes = self.cache.read_asset(&cached.sha256).ok()?;
+        let bytes = match asset {
+            // Icons are referenced relative to the manifest's own URL, which
+            // is no longer where the UI fetched it from.
+            PinnedAsset::Effects => absolutize_icon_urls(&bytes, &cached.url).unwrap_or(bytes),
+            PinnedAsset::Icons => bytes,
+        };
+        Some((bytes, asset.content_type()))
+    }
+}
 
-        let mut inner = self
-            .inner
-            .lock()
-            .map_err(|_| "failed to lock wasm runtime".to_string())?;
-        let frame_count = (input.len() / 2) as i32;
-        let alloc = inner.alloc_samples.clone();
-        let process = inner.process.clone();
-        let memory = inner.memory;
+/// URL of `file_name` under `ASSET_PROTOCOL`. WebView2 only exposes custom
+/// protocols as `http://<scheme>.<host>/`.
+pub fn asset_protocol_url(file_name: &str) -> String {
+    if cfg!(windows) {
+        format!(
+            "http://{}.{}/{}",
+            ASSET_PROTOCOL, ASSET_PROTOCOL_HOST, file_name
+        )
+    } else {
+        format!("{}://{}/{}", ASSET_PROTOCOL, ASSET_PROTOCOL_HOST, file_name)
+    }
+}
 
-        let input_ptr = alloc
-            .call(&mut inner.store, input.len() as i32)
-            .map_err(|e| format!("wasm alloc(input) failed: {}", e))?;
-        let output_ptr = alloc
-            .call(&mut inner.store, output.len() as i32)
-            .map_err(|e| format!("wasm alloc(output) failed: {}", e))?;
+/// Whether `page` is an HTML document that works without its origin: no
+/// external scripts or stylesheets, as `vite build` produces with
+/// vite-plugin-singlefile. Pages from a dev server are not.
+fn is_self_contained_html(page: &[u8]) -> bool {
+    let page = String::from_utf8_lossy(page).to_ascii_lowercase();
+    if !page.contains("<html") {
+        return false;
+    }
+    let tags = |name: &'static str| {
+        page.match_indices(name)
+            .map(|(start, _)| page[start..].split('>').next().unwrap_or_default())
+    };
+    let external_script = tags("<script").any(|tag| tag.contains(" src="));
+    let external_style = tags("<link").any(|tag| {
+        tag.contains(" href=") && (tag.contains("stylesheet") || tag.contains("modulepreload"))
+    });
+    !external_script && !external_style
+}
 
-        {
-            let memory_data = memory.data_mut(&mut inner.store);
-            write_f32_slice(memory_data, input_ptr as usize, input)?;
+/// Rewrites relative `icon_url`s in an effects manifest against `base_url`.
+fn absolutize_icon_urls(manifest: &[u8], base_url: &str) -> Option<Vec<u8>> {
+    let mut manifest = serde_json::from_slice::<serde_json::Value>(manifest).ok()?;
+    let effects: Vec<&mut serde_json::Value> = match manifest.get_mut("effects")? {
+        serde_json::Value::Array(effects) => effects.iter_mut().collect(),
+        serde_json::Value::Object(effects) => effects.values_mut().collect(),
+        _ => return None,
+    };
+    for effect in effects {
+        if let Some(icon_url) = effect.get_mut("icon_url") {
+            if let Some(relative) = icon_url.as_str() {
+                *icon_url = serde_json::Value::String(resolve_url(base_url, relative));
+            }
         }
+    }
+    serde_json::to_vec(&manifest).ok()
+}
 
-        process
-            .call(&mut inner.store, (input_ptr, output_ptr, frame_count))
-            .map_err(|e| format!("wasm process call failed: {}", e))?;
+/// Resolves `reference` against `base` for the forms asset manifests use:
+/// absolute URLs, `/root-relative` and `./`/`../` paths.
+fn resolve_url(base: &str, reference: &str) -> String {
+    let reference = reference.trim();
+    if reference.is_empty() || reference.contains("://") || reference.starts_with("data:") {
+        return reference.to_string();
+    }
+    let Some((scheme, rest)) = base.split_once("://") else {
+        return reference.to_string();
+    };
+    if let Some(network_path) = reference.strip_prefix("//") {
+        return format!("{}://{}", scheme, network_path);
+    }
 
-        {
-            let memory_data = memory.data(&inner.store);
-            read_f32_slice(memory_data, output_ptr as usize, output)?;
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let mut segments: Vec<&str> = if reference.starts_with('/') {
+        Vec::new()
+    } else {
+        let mut segments: Vec<&str> = path.split('/').skip(1).collect();
+        segments.pop();
+        segments
+    };
+    for segment in reference.trim_start_matches('/').split('/') {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
         }
+    }
+    format!("{}://{}/{}", scheme, authority, segments.join("/"))
+}
 
-        Ok(())
+/// A downloaded, verified, compiled and cached bundle waiting to be installed.
+pub struct PreparedUpdate(LoadedBundle);
+
+impl PreparedUpdate {
+    pub fn version(&self) -> &str {
+        &self.0.manifest.version
     }
 
-    fn set_sample_rate(&self, sample_rate: f32) -> Result<(), String> {
-        let mut inner = self
-            .inner
-            .lock()
-            .map_err(|_| "failed to lock wasm runtime".to_string())?;
-        let set_sample_rate = inner.set_sample_rate.clone();
-        let status = set_sample_rate
-            .call(&mut inner.store, sample_rate)
-            .map_err(|e| format!("wasm set_sample_rate failed: {}", e))?;
-        if status == 0 {
-            Ok(())
-        } else {
-            Err(format!("wasm set_sample_rate returned status {}", status))
+    pub fn sha256(&self) -> &str {
+        &self.0.key
+    }
+}
+
+impl EvergreenUpdater {
+    /// Asks the sync endpoint for the current bundle. Returns `Ok(None)` when
+    /// the running bundle is already the latest.
+    ///
+    /// A pinned project whose bundle is not running fetches that exact bundle
+    /// first. If the server no longer has it, the channel's current bundle is
+    /// returned instead, for the caller to offer as an upgrade.
+    pub fn check(&self) -> Result<Option<PreparedUpdate>, EvergreenError> {
+        self.cache.ensure()?;
+        let mut restore_error = None;
+        let missing_pin = self
+            .pin
+            .as_ref()
+            .filter(|pin| self.current.as_deref() != Some(pin.sha256.as_str()));
+        if let Some(pin) = missing_pin {
+            let channel = ReleaseChannel::Pinned(pin.version.clone());
+            match self.check_release(&channel, Some(&pin.sha256)) {
+                Ok(update) => return Ok(update),
+                Err(error) => {
+                    restore_error = Some(error.context(format!("project bundle {}", pin.version)))
+                }
+            }
         }
+        self.check_release(&self.channel, None).map_err(|error| {
+            join_errors(restore_error.into_iter().chain([error]).collect())
+                .expect("at least one error")
+        })
     }
 
-    fn set_chain_json(&self, chain_json: &str) -> Result<(), String> {
-        let mut inner = self
-            .inner
-            .lock()
-            .map_err(|_| "failed to lock wasm runtime".to_string())?;
-        let alloc_bytes = inner.alloc_bytes.clone();
-        let set_chain_json = inner.set_chain_json.clone();
-        let memory = inner.memory;
-        let payload = chain_json.as_bytes();
-        let payload_len_i32 =
-            i32::try_from(payload.len()).map_err(|_| "chain JSON payload too large".to_string())?;
+    /// Fetches the manifest for `channel` and prepares its bundle unless it
+    /// is already running. With `expected_sha256` only that exact bundle is
+    /// accepted.
+    fn check_release(
+        &self,
+        channel: &ReleaseChannel,
+        expected_sha256: Option<&str>,
+    ) -> Result<Option<PreparedUpdate>, EvergreenError> {
+        let manifest =
+            fetch_sync_manifest(&sync_request_url(&self.sync_url, channel), &self.cache)?;
+        if manifest.wasm_url.trim().is_empty() {
+            return Err(EvergreenError::Validation(
+                "sync manifest has empty wasm_url".to_string(),
+            ));
+        }
+        if let ReleaseChannel::Pinned(version) = channel {
+            if !channel.matches(&manifest) {
+                return Err(EvergreenError::Validation(format!(
+                    "asked for pinned version {} but the server offered {}",
+                    version, manifest.version
+                )));
+            }
+        }
+        if let Some(expected) = expected_sha256 {
+            let offered = manifest.sha256.as_deref().unwrap_or_default().trim();
+            if !offered.eq_ignore_ascii_case(expected) {
+                return Err(EvergreenError::Validation(format!(
+                    "the server offers a different build of {}",
+                    manifest.version
+                )));
+            }
+        }
+        manifest.check_host_compat()?;
+        // Learned even when there is nothing to download, so a revocation
+        // takes effect without waiting for the next bundle.
+        self.cache
+            .learn_key_statements(root_trust_store()?, &manifest.key_statements)?;
+        if let (Some(expected), Some(current)) = (manifest.sha256.as_deref(), &self.current) {
+            if expected.trim().eq_ignore_ascii_case(current) {
+                // Same DSP, but the pinned assets may have moved on.
+                let (_, wasm_bytes) = self.cache.read_bundle(current)?;
+                let trust = self.cache.trust_store(root_trust_store()?);
+                verify_bundle(&manifest, &wasm_bytes, &trust, allow_unsigned())?;
+                self.sync_assets(&manifest.assets)?;
+                return Ok(None);
+            }
+        }
 
-        let payload_ptr = alloc_bytes
-            .call(&mut inner.store, payload_len_i32)
-            .map_err(|e| format!("wasm alloc(chain_json) failed: {}", e))?;
+        let wasm_bytes = self.download_wasm(&manifest)?;
+        self.prepare(manifest, &wasm_bytes)
+    }
 
-        {
-            let memory_data = memory.data_mut(&mut inner.store);
-            write_byte_slice(memory_data, payload_ptr as usize, payload)?;
+    /// Downloads the manifest's wasm: as a patch against a cached bundle
+    /// when the manifest offers one, otherwise (or when patching fails) in
+    /// full, resuming an earlier attempt at the same bundle.
+    fn download_wasm(&self, manifest: &SyncManifest) -> Result<Vec<u8>, EvergreenError> {
+        if let Some(wasm_bytes) = self.patch_wasm(manifest) {
+            return Ok(wasm_bytes);
         }
-
-        let status = set_chain_json
-            .call(&mut inner.store, (payload_ptr, payload_len_i32))
-            .map_err(|e| format!("wasm set_chain_json failed: {}", e))?;
-        if status == 0 {
-            Ok(())
-        } else {
-            Err(format!("wasm set_chain_json returned status {}", status))
+        if let Some(path) = self.local_url(&manifest.wasm_url) {
+            return read_local_file(&path, download::MAX_BUNDLE_BYTES);
         }
+        let url = http_url(&manifest.wasm_url)?;
+        // The key becomes a file name, so only a hex digest is taken as is.
+        let partial_key = match manifest.sha256.as_deref().map(str::trim) {
+            Some(sha256) if !sha256.is_empty() && sha256.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                sha256.to_ascii_lowercase()
+            }
+            _ => sha256_hex(url.as_bytes()),
+        };
+        self.cache.with_partial_download(&partial_key, |partial| {
+            // Another instance may have fetched it while this one waited.
+            if let Ok((_, wasm_bytes)) = self.cache.read_bundle(&partial_key) {
+                return Ok(wasm_bytes);
+            }
+            download::get_resumable(
+                url,
+                download::MAX_BUNDLE_BYTES,
+                partial,
+                download::Retry::DEFAULT,
+            )
+            .map_err(EvergreenError::Network)
+        })
     }
 
-    fn set_param_json(&self, effect_idx: i32, key: &str, value: f32) -> Result<(), String> {
-        let payload = serde_json::json!({
-            "index": effect_idx,
-            "param_key": key,
-            "value": value
+    /// Rebuilds the manifest's wasm from a cached bundle and one of its
+    /// deltas, preferring the running bundle. Only a result hashing to the
+    /// manifest's `sha256` is returned; any failure means a full download.
+    fn patch_wasm(&self, manifest: &SyncManifest) -> Option<Vec<u8>> {
+        let expected = manifest.sha256.as_deref()?.trim();
+        let mut deltas: Vec<&WasmDelta> = manifest
+            .deltas
+            .iter()
+            .filter(|delta| {
+                delta
+                    .size
+                    .is_none_or(|size| size <= download::MAX_BUNDLE_BYTES)
+            })
+            .collect();
+        deltas.sort_by_key(|delta| {
+            self.current
+                .as_deref()
+                .is_none_or(|current| !delta.from_sha256.trim().eq_ignore_ascii_case(current))
         });
-        let payload = serde_json::to_vec(&payload)
-            .map_err(|e| format!("failed to serialize wasm param JSON payload: {}", e))?;
-
-        let mut inner = self
-            .inner
-            .lock()
-            .map_err(|_| "failed to lock wasm runtime".to_string())?;
-        let alloc_bytes = inner.alloc_bytes.clone();
-        let set_param_json = inner.set_param_json.clone();
-        let memory = inner.memory;
-        let payload_len_i32 =
-            i32::try_from(payload.len()).map_err(|_| "param JSON payload too large".to_string())?;
+        deltas.into_iter().find_map(|delta| {
+            // The key names a cache directory, so only a digest is looked up.
+            let from = delta.from_sha256.trim();
+            if !is_sha256_hex(from) {
+                return None;
+            }
+            let (_, base) = self.cache.read_bundle(from).ok()?;
+            let patch = self.fetch(&delta.url, download::MAX_BUNDLE_BYTES).ok()?;
+            apply_wasm_delta(&base, &patch, download::MAX_BUNDLE_BYTES)
+                .ok()
+                .filter(|wasm_bytes| bundle_key(wasm_bytes).eq_ignore_ascii_case(expected))
+        })
+    }
 
-        let payload_ptr = alloc_bytes
-            .call(&mut inner.store, payload_len_i32)
-            .map_err(|e| format!("wasm alloc(param_json) failed: {}", e))?;
+    /// Fetches a URL taken from the manifest: a file when `local_url` allows
+    /// one, otherwise over http(s).
+    fn fetch(&self, url: &str, limit: u64) -> Result<Vec<u8>, EvergreenError> {
+        match self.local_url(url) {
+            Some(path) => read_local_file(&path, limit),
+            None => download_bytes(url, limit),
+        }
+    }
 
-        {
-            let memory_data = memory.data_mut(&mut inner.store);
-            write_byte_slice(memory_data, payload_ptr as usize, &payload)?;
+    /// The file a manifest URL names, only when the sync source is itself
+    /// local: a server's manifest (or an unsigned delta entry) must not make
+    /// the host read files. Manifest URLs are signed as written, so relative
+    /// paths are only resolved against the source's directory here.
+    fn local_url(&self, url: &str) -> Option<PathBuf> {
+        let source = local_source_path(&self.sync_url)?;
+        let url = url.trim();
+        if url.starts_with("file://") {
+            return local_source_path(url);
+        }
+        if url.contains("://") {
+            return None;
         }
+        Some(local_source_dir(&source).join(url))
+    }
 
-        let status = set_param_json
-            .call(&mut inner.store, (payload_ptr, payload_len_i32))
-            .map_err(|e| format!("wasm set_param_json failed: {}", e))?;
-        if status == 0 {
-            Ok(())
-        } else {
-            Err(format!("wasm set_param_json returned status {}", status))
+    fn prepare(
+        &self,
+        manifest: SyncManifest,
+        wasm_bytes: &[u8],
+    ) -> Result<Option<PreparedUpdate>, EvergreenError> {
+        let trust = self.cache.trust_store(root_trust_store()?);
+        verify_bundle(&manifest, wasm_bytes, &trust, allow_unsigned())?;
+        let key = bundle_key(wasm_bytes);
+        if let Some(expected) = manifest.sha256.as_deref() {
+            if !expected.trim().eq_ignore_ascii_case(&key) {
+                return Err(EvergreenError::Signature(format!(
+                    "downloaded wasm sha256 {} does not match manifest {}",
+                    key,
+                    expected.trim()
+                )));
+            }
+        }
+        // A manifest with a tampered asset is not trusted for the DSP either.
+        self.sync_assets(&manifest.assets)?;
+        if self.current.as_deref() == Some(key.as_str()) {
+            return Ok(None);
         }
+        if self.cache.is_bad(&key) {
+            return Err(EvergreenError::Validation(format!(
+                "bundle {} was rolled back after failing at runtime",
+                manifest.version
+            )));
+        }
+
+        let runtime = load_bundle(&manifest, wasm_bytes, &self.cache)?;
+        self.cache.store_bundle(&key, &manifest, wasm_bytes)?;
+        Ok(Some(PreparedUpdate(LoadedBundle {
+            key,
+            manifest,
+            runtime,
+        })))
     }
-}
 
-fn write_f32_slice(memory: &mut [u8], ptr: usize, values: &[f32]) -> Result<(), String> {
-    let byte_len = values
-        .len()
-        .checked_mul(std::mem::size_of::<f32>())
-        .ok_or_else(|| "input byte length overflow".to_string())?;
-    let end = ptr
-        .checked_add(byte_len)
-        .ok_or_else(|| "input pointer overflow".to_string())?;
-    if end > memory.len() {
-        return Err("input write out of wasm memory bounds".to_string());
+    /// Downloads the assets the manifest pins that are not cached yet. An
+    /// asset that fails verification is refused and the last verified copy
+    /// stays in use.
+    fn sync_assets(&self, assets: &SyncAssets) -> Result<(), EvergreenError> {
+        let mut errors = Vec::new();
+        for asset in PinnedAsset::ALL {
+            let Some((url, expected)) = asset.pin(assets) else {
+                continue;
+            };
+            if self.cache.has_asset(asset, url, &expected) {
+                continue;
+            }
+            let result = self
+                .fetch(url, download::MAX_ASSET_BYTES)
+                .and_then(|bytes| self.cache.store_asset(asset, url, &expected, &bytes));
+            if let Err(error) = result {
+                errors.push(error.context(asset.file_name()));
+            }
+        }
+        join_errors(errors).map_or(Ok(()), Err)
     }
 
-    for (index, value) in values.iter().enumerate() {
-        let offset = ptr + index * std::mem::size_of::<f32>();
-        memory[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    /// Refreshes the offline copy of the UI page at `ui_url`, which the
+    /// editor opens through `ASSET_PROTOCOL` from then on. Returns whether
+    /// the cached copy changed. Pages that pull in scripts or styles from
+    /// their origin are not cached: they would not work offline.
+    pub fn refresh_ui(&self, ui_url: &str) -> Result<bool, EvergreenError> {
+        let ui_url = ui_url.trim();
+        if !ui_url.starts_with("http://") && !ui_url.starts_with("https://") {
+            return Ok(false);
+        }
+        self.cache.ensure()?;
+        let page = download_bytes(ui_url, download::MAX_ASSET_BYTES)?;
+        if !is_self_contained_html(&page) {
+            return Err(EvergreenError::Validation(format!(
+                "UI at {} is not a self-contained page; not caching it",
+                ui_url
+            )));
+        }
+        let sha256 = sha256_hex(&page);
+        if self
+            .cache
+            .cached_ui(ui_url)
+            .is_some_and(|cached| cached.sha256 == sha256)
+        {
+            return Ok(false);
+        }
+        self.cache.store_ui(ui_url, &page)?;
+        Ok(true)
     }
-    Ok(())
 }
 
-fn write_byte_slice(memory: &mut [u8], ptr: usize, values: &[u8]) -> Result<(), String> {
-    let end = ptr
-        .checked_add(values.len())
-        .ok_or_else(|| "byte payload pointer overflow".to_string())?;
-    if end > memory.len() {
-        return Err("byte payload write out of wasm memory bounds".to_string());
-    }
-    memory[ptr..end].copy_from_slice(values);
-    Ok(())
+/// Notices when a local bundle source changes on disk: its
+/// `sync_manifest.json` and `engine.wasm`.
+pub struct SourceWatcher {
+    files: [PathBuf; 2],
+    stamp: Vec<Option<(Option<SystemTime>, u64)>>,
 }
 
-fn read_f32_slice(memory: &[u8], ptr: usize, output: &mut [f32]) -> Result<(), String> {
-    let byte_len = output
-        .len()
-        .checked_mul(std::mem::size_of::<f32>())
-        .ok_or_else(|| "output byte length overflow".to_string())?;
-    let end = ptr
-        .checked_add(byte_len)
-        .ok_or_else(|| "output pointer overflow".to_string())?;
-    if end > memory.len() {
-        return Err("output read out of wasm memory bounds".to_string());
+impl SourceWatcher {
+    fn new(source: &Path) -> Self {
+        let dir = local_source_dir(source);
+        let manifest = if source.is_dir() {
+            dir.join(CACHE_MANIFEST_FILE)
+        } else {
+            source.to_path_buf()
+        };
+        let mut watcher = Self {
+            files: [manifest, dir.join(CACHE_WASM_FILE)],
+            stamp: Vec::new(),
+        };
+        watcher.stamp = watcher.read_stamp();
+        watcher
     }
 
-    for (index, slot) in output.iter_mut().enumerate() {
-        let offset = ptr + index * std::mem::size_of::<f32>();
-        let bytes = [
-            memory[offset],
-            memory[offset + 1],
-            memory[offset + 2],
-            memory[offset + 3],
-        ];
-        *slot = f32::from_le_bytes(bytes);
+    fn read_stamp(&self) -> Vec<Option<(Option<SystemTime>, u64)>> {
+        self.files
+            .iter()
+            .map(|file| {
+                let metadata = std::fs::metadata(file).ok()?;
+                Some((metadata.modified().ok(), metadata.len()))
+            })
+            .collect()
     }
-    Ok(())
-}
 
-fn fetch_sync_manifest(sync_url: &str) -> Result<SyncManifest, String> {
-    let bytes = download_bytes(sync_url)?;
-    let manifest = serde_json::from_slice::<SyncManifest>(&bytes)
-        .map_err(|e| format!("failed to parse sync manifest JSON: {}", e))?;
-    if manifest.version.trim().is_empty() {
-        return Err("sync manifest has empty version".to_string());
+    /// Whether the watched files changed since the last poll. A file caught
+    /// mid-write shows up again once the writer finishes.
+    pub fn poll(&mut self) -> bool {
+        let stamp = self.read_stamp();
+        let changed = stamp != self.stamp;
+        self.stamp = stamp;
+        changed
     }
-    Ok(manifest)
 }
 
-fn download_bytes(url: &str) -> Result<Vec<u8>, String> {
-    let response = ureq::get(url)
-        .timeout(Duration::from_secs(8))
-        .call()
-        .map_err(map_ureq_error)?;
+/// Content address of a bundle: hex SHA-256 of its wasm bytes.
+fn bundle_key(wasm_bytes: &[u8]) -> String {
+    sha256_hex(wasm_bytes)
+}
 
-    let mut reader = response.into_reader();
-    let mut buffer = Vec::new();
-    reader
-        .read_to_end(&mut buffer)
-        .map_err(|e| format!("failed to read response body from '{}': {}", url, e))?;
-    Ok(buffer)
+fn sha256_hex(bytes: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
-fn map_ureq_error(error: ureq::Error) -> String {
-    match error {
-        ureq::Error::Status(code, _) => format!("HTTP status {}", code),
-        ureq::Error::Transport(err) => format!("transport error: {}", err),
-    }
+/// Whether `value` is a digest as `sha256_hex` writes it: 64 lowercase hex
+/// characters.
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
 }
 
-fn verify_bundle_signature(bundle_bytes: &[u8], signature_b64: &str) -> Result<(), String> {
-    let allow_unsigned = env_bool(ENV_ALLOW_UNSIGNED).unwrap_or(cfg!(debug_assertions));
-    let public_key_b64 = resolve_public_key_b64();
-    let signature = signature_b64.trim();
+fn short_key(key: &str) -> &str {
+    &key[..key.len().min(12)]
+}
 
-    if public_key_b64.is_empty() || signature.is_empty() {
-        if allow_unsigned {
-            return Ok(());
-        }
-        return Err("missing Ed25519 key/signature and unsigned bundles are disabled".to_string());
-    }
+/// Which bundles are cached and how they have behaved, newest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct CacheState {
+    #[serde(default)]
+    bundles: Vec<String>,
+    #[serde(default)]
+    known_good: Option<String>,
+    #[serde(default)]
+    bad: Vec<String>,
+    /// Key statements that took effect, replayed over the root keys on load.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    key_statements: Vec<KeyStatement>,
+    /// Latest verified copy of each pinned asset, by file name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    assets: BTreeMap<String, CachedAsset>,
+    /// Last copy of the web UI page, also stored under `assets/`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ui: Option<CachedAsset>,
+}
 
-    let public_key = base64::engine::general_purpose::STANDARD
-        .decode(public_key_b64.as_bytes())
-        .map_err(|e| format!("invalid base64 public key: {}", e))?;
-    let signature = base64::engine::general_purpose::STANDARD
-        .decode(signature.as_bytes())
-        .map_err(|e| format!("invalid base64 signature: {}", e))?;
+/// The last sync manifest served over HTTP, with the validators to
+/// revalidate it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedSyncResponse {
+    url: String,
+    #[serde(flatten)]
+    validators: Validators,
+    body: String,
+}
 
-    let verifier = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
-    verifier
-        .verify(bundle_bytes, &signature)
-        .map_err(|_| "Ed25519 signature verification failed".to_string())
+/// A file stored as `assets/<sha256>`, with the URL it was downloaded from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedAsset {
+    url: String,
+    sha256: String,
 }
 
-fn resolve_sync_url() -> String {
-    read_env_non_empty(ENV_SYNC_URL).unwrap_or_else(|| DEFAULT_SYNC_URL.to_string())
+/// Content-addressed bundle store: `bundles/<sha256>/{engine.wasm,sync_manifest.json}`
+/// plus `cache_state.json` tracking their order, the known-good bundle and
+/// bundles that were rolled back.
+#[derive(Debug, Clone)]
+struct CacheManager {
+    root: PathBuf,
 }
 
-fn resolve_public_key_b64() -> String {
-    read_env_non_empty(ENV_PUBLIC_KEY_B64)
-        .or_else(|| {
-            EMBEDDED_PUBLIC_KEY_B64
-                .map(|value| value.trim().to_string())
-                .filter(|value| !value.is_empty())
+impl CacheManager {
+    fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn ensure(&self) -> Result<(), EvergreenError> {
+        std::fs::create_dir_all(self.root.join(CACHE_BUNDLES_DIR)).map_err(|e| {
+            EvergreenError::Cache(format!(
+                "failed to create evergreen cache directory '{}': {}",
+                self.root.display(),
+                e
+            ))
+        })?;
+        self.with_lock(|| self.migrate_legacy())
+    }
+
+    fn bundle_dir(&self, key: &str) -> PathBuf {
+        self.root.join(CACHE_BUNDLES_DIR).join(key)
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.root.join(CACHE_STATE_FILE)
+    }
+
+    /// Runs `f` holding an exclusive lock on `.lock`, serialising cache writes
+    /// across plugin instances (and processes) sharing the data dir. The OS
+    /// drops the lock if the process dies.
+    fn with_lock<T>(
+        &self,
+        f: impl FnOnce() -> Result<T, EvergreenError>,
+    ) -> Result<T, EvergreenError> {
+        with_file_lock(&self.root.join(CACHE_LOCK_FILE), "evergreen cache", f)
+    }
+
+    fn read_state(&self) -> CacheState {
+        std::fs::read(self.state_path())
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Caller holds the cache lock.
+    fn update_state(&self, update: impl FnOnce(&mut CacheState)) -> Result<(), EvergreenError> {
+        let mut state = self.read_state();
+        update(&mut state);
+        let bytes = serde_json::to_vec_pretty(&state).map_err(|e| {
+            EvergreenError::Cache(format!("failed to serialize evergreen cache state: {}", e))
+        })?;
+        write_atomic(&self.state_path(), &bytes).map_err(|e| {
+            EvergreenError::Cache(format!("failed to write evergreen cache state: {}", e))
         })
-        .unwrap_or_default()
-}
+    }
 
-fn evergreen_enabled() -> bool {
-    env_bool(ENV_ENABLED).unwrap_or(true)
-}
+    /// Imports the single-bundle layout used before the cache was versioned.
+    /// Caller holds the cache lock.
+    fn migrate_legacy(&self) -> Result<(), EvergreenError> {
+        let manifest_path = self.root.join(CACHE_MANIFEST_FILE);
+        let wasm_path = self.root.join(CACHE_WASM_FILE);
+        if !manifest_path.exists() || !wasm_path.exists() {
+            return Ok(());
+        }
 
-fn read_env_non_empty(name: &str) -> Option<String> {
-    std::env::var(name)
-        .ok()
-        .map(|value| value.trim().to_string())
-        .filter(|value| !value.is_empty())
-}
+        let manifest = std::fs::read(&manifest_path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<SyncManifest>(&bytes).ok());
+        let wasm_bytes = std::fs::read(&wasm_path).ok();
+        if let (Some(manifest), Some(wasm_bytes)) = (manifest, wasm_bytes) {
+            // The legacy pair was not written atomically; only import it if the
+            // manifest still describes this wasm.
+            let key = bundle_key(&wasm_bytes);
+            if manifest.sha256.as_deref().is_none_or(|sha| sha == key) {
+                self.store_bundle_locked(&key, &manifest, &wasm_bytes)?;
+            }
+        }
+        let _ = std::fs::remove_file(manifest_path);
+        let _ = std::fs::remove_file(wasm_path);
+        Ok(())
+    }
 
-fn env_bool(name: &str) -> Option<bool> {
-    let value = read_env_non_empty(name)?;
-    match value.to_ascii_lowercase().as_str() {
-        "1" | "true" | "yes" | "on" => Some(true),
-        "0" | "false" | "no" | "off" => Some(false),
-        _ => None,
+    /// Stores a bundle as the newest entry and garbage collects old ones.
+    fn store_bundle(
+        &self,
+        key: &str,
+        manifest: &SyncManifest,
+        wasm_bytes: &[u8],
+    ) -> Result<(), EvergreenError> {
+        self.with_lock(|| self.store_bundle_locked(key, manifest, wasm_bytes))
     }
-}
 
-#[allow(dead_code)]
-pub fn cache_root_for(data_dir: &Path) -> PathBuf {
-    data_dir.join(CACHE_DIR_NAME)
+    /// Writes the bundle into a temp dir and renames it into place, so a
+    /// bundle dir is either complete or absent. The cached manifest always
+    /// carries the wasm sha256 so `read_bundle` can check the pair.
+    fn store_bundle_locked(
+        &self,
+        key: &str,
+        manifest: &SyncManifest,
+        wasm_bytes: &[u8],
+    ) -> Result<(), EvergreenError> {
+        let dir = self.bundle_dir(key);
+        if !dir.join(CACHE_MANIFEST_FILE).exists() {
+            let staging = self.root.join(CACHE_BUNDLES_DIR).join(format!(
+                ".{}.{}.tmp",
+                key,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&staging);
+            let staged = std::fs::create_dir_all(&staging)
+                .and_then(|_| write_synced(&staging.join(CACHE_WASM_FILE), wasm_bytes))
+                .and_then(|_| {
+                    let manifest = SyncManifest {
+                        sha256: Some(key.to_string()),
+                        // Persisted once in the cache state, not per bundle.
+                        key_statements: Vec::new(),
+                        deltas: Vec::new(),
+                        ..manifest.clone()
+                    };
+                    let bytes = serde_json::to_vec_pretty(&manifest)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                    write_synced(&staging.join(CACHE_MANIFEST_FILE), &bytes)
+                })
+                .and_then(|_| {
+                    // A half-written dir left by a crash predates the lock holder.
+                    let _ = std::fs::remove_dir_all(&dir);
+                    std::fs::rename(&staging, &dir)
+                });
+            if let Err(e) = staged {
+                let _ = std::fs::remove_dir_all(&staging);
+                return Err(EvergreenError::Cache(format!(
+                    "failed to write cached bundle '{}': {}",
+                    short_key(key),
+                    e
+                )));
+            }
+        }
+
+        self.update_state(|state| {
+            state.bundles.retain(|existing| existing != key);
+            state.bundles.insert(0, key.to_string());
+        })?;
+        self.collect_garbage()
+    }
+
+    /// The cached manifest alone, unverified; see `read_bundle`.
+    fn read_manifest(&self, key: &str) -> Result<SyncManifest, EvergreenError> {
+        let manifest_bytes = std::fs::read(self.bundle_dir(key).join(CACHE_MANIFEST_FILE))
+            .map_err(|e| {
+                EvergreenError::Cache(format!("failed to read cached sync manifest: {}", e))
+            })?;
+        serde_json::from_slice::<SyncManifest>(&manifest_bytes).map_err(|e| {
+            EvergreenError::Cache(format!("failed to parse cached sync manifest: {}", e))
+        })
+    }
+
+    /// Reads a bundle and checks the wasm against both its content address and
+    /// the sha256 recorded in its manifest.
+    fn read_bundle(&self, key: &str) -> Result<(SyncManifest, Vec<u8>), EvergreenError> {
+        let manifest = self.read_manifest(key)?;
+        let wasm_bytes = std::fs::read(self.bundle_dir(key).join(CACHE_WASM_FILE))
+            .map_err(|e| EvergreenError::Cache(format!("failed to read cached wasm: {}", e)))?;
+
+        let actual = bundle_key(&wasm_bytes);
+        if actual != key {
+            return Err(EvergreenError::Cache(
+                "cached wasm does not match its content hash".to_string(),
+            ));
+        }
+        match manifest.sha256.as_deref() {
+            Some(expected) if expected.eq_ignore_ascii_case(&actual) => {}
+            Some(_) => {
+                return Err(EvergreenError::Cache(
+                    "cached manifest sha256 does not match cached wasm".to_string(),
+                ))
+            }
+            None => {
+                return Err(EvergreenError::Cache(
+                    "cached manifest has no sha256".to_string(),
+                ))
+            }
+        }
+        Ok((manifest, wasm_bytes))
+    }
+
+    fn compiled_dir(&self) -> PathBuf {
+        self.root.join(CACHE_COMPILED_DIR)
+    }
+
+    /// Loads a module compiled by an earlier run. Artifacts are native code
+    /// deserialized without validation. The sidecar sha256 next to them only
+    /// catches a torn or corrupted write: it lives in the same directory, so
+    /// anyone who can replace the artifact can replace it too. Anything that
+    /// fails is deleted and the caller recompiles.
+    fn read_precompiled(&self, engine: &Engine, name: &str) -> Option<Module> {
+        let path = self.compiled_dir().join(name);
+        let sidecar = path.with_extension(format!("{}.sha256", PRECOMPILED_EXTENSION));
+        let bytes = std::fs::read(&path).ok()?;
+        let expected = std::fs::read_to_string(&sidecar).ok();
+
+        let module = expected
+            .filter(|expected| expected.trim() == bundle_key(&bytes))
+            // Safety: the cache directory is trusted like the plugin binary
+            // itself: only this user's Tonelab writes it, via `Module::serialize`,
+            // and the checksum rules out corruption since. wasmtime rejects
+            // incompatible versions and settings itself.
+            .and_then(|_| unsafe { Module::deserialize(engine, &bytes) }.ok());
+        if module.is_none() {
+            let _ = std::fs::remove_file(&path);
+            let _ = std::fs::remove_file(&sidecar);
+        }
+        module
+    }
+
+    fn store_precompiled(&self, name: &str, serialized: &[u8]) -> Result<(), EvergreenError> {
+        let dir = self.compiled_dir();
+        let path = dir.join(name);
+        let sidecar = path.with_extension(format!("{}.sha256", PRECOMPILED_EXTENSION));
+        self.with_lock(|| {
+            std::fs::create_dir_all(&dir)
+                .and_then(|_| write_atomic(&path, serialized))
+                .and_then(|_| write_atomic(&sidecar, bundle_key(serialized).as_bytes()))
+                .map_err(|e| {
+                    EvergreenError::Cache(format!("failed to write precompiled module: {}", e))
+                })
+        })
+    }
+
+    fn asset_path(&self, sha256: &str) -> PathBuf {
+        self.root.join(CACHE_ASSETS_DIR).join(sha256)
+    }
+
+    fn has_asset(&self, asset: PinnedAsset, url: &str, sha256: &str) -> bool {
+        let current = CachedAsset {
+            url: url.to_string(),
+            sha256: sha256.to_string(),
+        };
+        self.read_state().assets.get(asset.file_name()) == Some(&current)
+            && self.asset_path(sha256).is_file()
+    }
+
+    /// Caches `bytes` as the current copy of `asset` if they hash to
+    /// `expected`; otherwise refuses them and keeps the previous copy.
+    fn store_asset(
+        &self,
+        asset: PinnedAsset,
+        url: &str,
+        expected: &str,
+        bytes: &[u8],
+    ) -> Result<(), EvergreenError> {
+        let actual = sha256_hex(bytes);
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(EvergreenError::Signature(format!(
+                "sha256 {} does not match manifest {}; keeping the last verified copy",
+                actual, expected
+            )));
+        }
+
+        self.with_lock(|| {
+            self.write_asset(&actual, bytes).map_err(|e| {
+                EvergreenError::Cache(format!("failed to cache {}: {}", asset.file_name(), e))
+            })?;
+            self.update_state(|state| {
+                state.assets.insert(
+                    asset.file_name().to_string(),
+                    CachedAsset {
+                        url: url.to_string(),
+                        sha256: actual.clone(),
+                    },
+                );
+            })?;
+            self.collect_garbage()
+        })
+    }
+
+    fn write_asset(&self, sha256: &str, bytes: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(self.root.join(CACHE_ASSETS_DIR))?;
+        write_atomic(&self.asset_path(sha256), bytes)
+    }
+
+    /// Runs `f` with the file the download of bundle `key` is kept in until
+    /// it completes. Instances sharing the data dir take turns under a lock
+    /// of the partial directory's own, held for the whole download so none
+    /// deletes or rewrites a file another is writing; the cache lock is not
+    /// held that long. Partial downloads of other bundles are dropped.
+    fn with_partial_download<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&Path) -> Result<T, EvergreenError>,
+    ) -> Result<T, EvergreenError> {
+        let dir = self.root.join(CACHE_PARTIAL_DIR);
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            EvergreenError::Cache(format!("failed to create '{}': {}", dir.display(), e))
+        })?;
+        with_file_lock(&dir.join(CACHE_LOCK_FILE), "partial downloads", || {
+            let file_name = format!("{}.part", key);
+            if let Ok(entries) = std::fs::read_dir(&dir) {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if name != CACHE_LOCK_FILE && !name.starts_with(&file_name) {
+                        let _ = std::fs::remove_file(entry.path());
+                    }
+                }
+            }
+            f(&dir.join(file_name))
+        })
+    }
+
+    /// The last manifest fetched from `url`, if the server sent validators.
+    fn cached_sync_response(&self, url: &str) -> Option<CachedSyncResponse> {
+        std::fs::read(self.root.join(CACHE_SYNC_RESPONSE_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CachedSyncResponse>(&bytes).ok())
+            .filter(|cached| cached.url == url)
+    }
+
+    fn store_sync_response(&self, response: &CachedSyncResponse) -> Result<(), EvergreenError> {
+        let bytes = serde_json::to_vec_pretty(response).map_err(|e| {
+            EvergreenError::Cache(format!("failed to serialize sync response: {}", e))
+        })?;
+        self.with_lock(|| {
+            write_atomic(&self.root.join(CACHE_SYNC_RESPONSE_FILE), &bytes)
+                .map_err(|e| EvergreenError::Cache(format!("failed to cache sync response: {}", e)))
+        })
+    }
+
+    /// The cached UI page, if it was downloaded from `url`.
+    fn cached_ui(&self, url: &str) -> Option<CachedAsset> {
+        self.read_state()
+            .ui
+            .filter(|ui| ui.url == url && self.asset_path(&ui.sha256).is_file())
+    }
+
+    /// Caches `page` as the offline copy of the UI at `url`, replacing the
+    /// previous one.
+    fn store_ui(&self, url: &str, page: &[u8]) -> Result<(), EvergreenError> {
+        let sha256 = sha256_hex(page);
+        self.with_lock(|| {
+            self.write_asset(&sha256, page)
+                .map_err(|e| EvergreenError::Cache(format!("failed to cache UI page: {}", e)))?;
+            self.update_state(|state| {
+                state.ui = Some(CachedAsset {
+                    url: url.to_string(),
+                    sha256: sha256.clone(),
+                });
+            })?;
+            self.collect_garbage()
+        })
+    }
+
+    fn read_asset(&self, sha256: &str) -> Result<Vec<u8>, EvergreenError> {
+        let bytes = std::fs::read(self.asset_path(sha256))
+            .map_err(|e| EvergreenError::Cache(format!("failed to read cached asset: {}", e)))?;
+        if sha256_hex(&bytes) != sha256 {
+            return Err(EvergreenError::Cache(format!(
+                "cached asset {} is corrupt",
+                short_key(sha256)
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// Cached bundles worth trying, newest first, skipping bad ones. With
+    /// `known_good_first` the known-good bundle leads, as after a rollback.
+    fn candidates(&self, known_good_first: bool) -> Vec<String> {
+        let state = self.read_state();
+        let mut candidates: Vec<String> = state
+            .bundles
+            .iter()
+            .filter(|key| !state.bad.contains(key))
+            .cloned()
+            .collect();
+        if known_good_first {
+            if let Some(known_good) = state.known_good.as_ref() {
+                if let Some(pos) = candidates.iter().position(|key| key == known_good) {
+                    let key = candidates.remove(pos);
+                    candidates.insert(0, key);
+                }
+            }
+        }
+        candidates
+    }
+
+    fn is_known_good(&self, key: &str) -> bool {
+        self.read_state().known_good.as_deref() == Some(key)
+    }
+
+    fn mark_known_good(&self, key: &str) -> Result<(), EvergreenError> {
+        self.with_lock(|| {
+            self.update_state(|state| {
+                if !state.bad.iter().any(|bad| bad == key) {
+                    state.known_good = Some(key.to_string());
+                }
+            })?;
+            self.collect_garbage()
+        })
+    }
+
+    fn is_bad(&self, key: &str) -> bool {
+        self.read_state().bad.iter().any(|bad| bad == key)
+    }
+
+    fn mark_bad(&self, key: &str) -> Result<(), EvergreenError> {
+        self.with_lock(|| {
+            self.update_state(|state| {
+                if !state.bad.iter().any(|bad| bad == key) {
+                    state.bad.insert(0, key.to_string());
+                    state.bad.truncate(CACHE_KEEP_BAD);
+                }
+                if state.known_good.as_deref() == Some(key) {
+                    state.known_good = None;
+                }
+            })?;
+            self.collect_garbage()
+        })
+    }
+
+    /// `roots` plus every key statement learned so far.
+    fn trust_store(&self, mut roots: TrustStore) -> TrustStore {
+        roots.apply(&self.read_state().key_statements);
+        roots
+    }
+
+    /// Applies new key statements from the server and persists the ones that
+    /// took effect, so rotations and revocations survive going offline.
+    fn learn_key_statements(
+        &self,
+        roots: TrustStore,
+        statements: &[KeyStatement],
+    ) -> Result<TrustStore, EvergreenError> {
+        if statements.is_empty() {
+            return Ok(self.trust_store(roots));
+        }
+        self.with_lock(|| {
+            let mut trust = self.trust_store(roots);
+            let applied = trust.apply(statements);
+            if !applied.is_empty() {
+                self.update_state(|state| state.key_statements.extend(applied))?;
+            }
+            Ok(trust)
+        })
+    }
+
+    /// Keeps the newest `CACHE_KEEP_BUNDLES` bundles not marked bad, plus the
+    /// known-good one, and deletes every other bundle directory (including
+    /// staging dirs left behind by a crash) and compiled module. Caller holds
+    /// the cache lock.
+    fn collect_garbage(&self) -> Result<(), EvergreenError> {
+        let mut kept = 0usize;
+        self.update_state(|state| {
+            state.bundles.retain(|key| {
+                if state.known_good.as_ref() == Some(key) {
+                    return true;
+                }
+                let keep = !state.bad.contains(key) && kept < CACHE_KEEP_BUNDLES;
+                kept += usize::from(keep);
+                keep
+            });
+        })?;
+        let state = self.read_state();
+
+        let bundles_dir = self.root.join(CACHE_BUNDLES_DIR);
+        let entries = match std::fs::read_dir(&bundles_dir) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !state.bundles.contains(&name) {
+                let _ = std::fs::remove_dir_all(entry.path());
+            }
+        }
+
+        if let Ok(entries) = std::fs::read_dir(self.root.join(CACHE_ASSETS_DIR)) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let referenced = state
+                    .assets
+                    .values()
+                    .chain(state.ui.as_ref())
+                    .any(|asset| asset.sha256 == name);
+                if !referenced {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+
+        // Compiled modules are named `<bundle key>-...`; drop those whose bundle
+        // is gone and those written by other plugin versions.
+        let entries = match std::fs::read_dir(self.compiled_dir()) {
+            Ok(entries) => entries,
+            Err(_) => return Ok(()),
+        };
+        let current_suffix = format!("-{}-", HOST_VERSION);
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let live = name.split_once('-').is_some_and(|(key, rest)| {
+                state.bundles.iter().any(|bundle| bundle == key)
+                    && format!("-{}", rest).starts_with(&current_suffix)
+            });
+            if !live {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// File name for a compiled module: wasm hash, plugin version (which pins the
+/// wasmtime version) and wasmtime's hash of the compiler settings and CPU
+/// features. Any change picks a new name; stale files are collected.
+fn precompiled_name(engine: &Engine, wasm_bytes: &[u8]) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!(
+        "{}-{}-{:016x}.{}",
+        bundle_key(wasm_bytes),
+        HOST_VERSION,
+        hasher.finish(),
+        PRECOMPILED_EXTENSION
+    )
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// Writes `bytes` next to `path` and renames over it, so readers see either
+/// the old or the new contents, never a partial file.
+/// Runs `f` holding an exclusive lock on the file at `path`, which other
+/// processes and plugin instances take too. `what` names it in errors.
+fn with_file_lock<T>(
+    path: &Path,
+    what: &str,
+    f: impl FnOnce() -> Result<T, EvergreenError>,
+) -> Result<T, EvergreenError> {
+    let lock_file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| EvergreenError::Cache(format!("failed to open {} lock: {}", what, e)))?;
+    lock_file
+        .lock()
+        .map_err(|e| EvergreenError::Cache(format!("failed to lock {}: {}", what, e)))?;
+    let result = f();
+    let _ = lock_file.unlock();
+    result
+}
+
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Plugin instances in one host process share the pid, so each write
+    // gets its own temp file.
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+    let temp = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        WRITES.fetch_add(1, AtomicOrdering::Relaxed)
+    ));
+    let result = write_synced(&temp, bytes).and_then(|_| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Instantiates a verified bundle and checks it matches what its manifest declared.
+fn load_bundle(
+    manifest: &SyncManifest,
+    wasm_bytes: &[u8],
+    cache: &CacheManager,
+) -> Result<WasmRuntime, EvergreenError> {
+    let runtime = WasmRuntime::from_bytes_cached(wasm_bytes, cache)?;
+    if let Some(declared) = manifest.abi_version {
+        if declared != runtime.abi().version {
+            return Err(EvergreenError::Abi(format!(
+                "sync manifest declares engine ABI v{} but engine.wasm reports v{}",
+                declared,
+                runtime.abi().version
+            )));
+        }
+    }
+    runtime.smoke_test()?;
+    Ok(runtime)
+}
+
+fn check_abi_version(abi_version: u32) -> Result<(), EvergreenError> {
+    if (MIN_GUEST_ABI_VERSION..=MAX_GUEST_ABI_VERSION).contains(&abi_version) {
+        Ok(())
+    } else {
+        Err(EvergreenError::Abi(format!(
+            "engine ABI v{} is not supported by plugin {} (supports v{}..=v{})",
+            abi_version, HOST_VERSION, MIN_GUEST_ABI_VERSION, MAX_GUEST_ABI_VERSION
+        )))
+    }
+}
+
+/// `engine.wasm` instantiated under wasmtime, driven through the guest exports.
+pub struct WasmRuntime {
+    inner: Mutex<WasmRuntimeInner>,
+    abi: EngineAbi,
+    /// Keeps the shared compiled code registered while this instance runs.
+    _module: Arc<Module>,
+    /// Keeps the epoch ticker running while this instance can be called.
+    _ticker: EpochTickerGuard,
+}
+
+/// Resource caps for a downloaded engine. The bundle is remote code running
+/// on the audio thread, so it must not be able to hang it or exhaust RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxLimits {
+    /// Cap on the guest's linear memory, including its initial size.
+    pub max_memory_bytes: usize,
+    /// Wall-clock budget for one `process` call before it is interrupted.
+    pub process_deadline: Duration,
+    /// Budget for instantiation and the chain/param/sample-rate calls.
+    pub control_deadline: Duration,
+}
+
+impl Default for SandboxLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_bytes: DEFAULT_MAX_MEMORY_MB << 20,
+            process_deadline: Duration::from_millis(DEFAULT_PROCESS_DEADLINE_MS),
+            control_deadline: Duration::from_millis(CONTROL_DEADLINE_MS),
+        }
+    }
+}
+
+impl SandboxLimits {
+    /// Defaults, overridden by `TONELAB_EVERGREEN_MAX_MEMORY_MB` and
+    /// `TONELAB_EVERGREEN_PROCESS_DEADLINE_MS` when they parse.
+    pub fn from_env() -> Self {
+        let mut limits = Self::default();
+        if let Some(mb) =
+            read_env_non_empty(ENV_MAX_MEMORY_MB).and_then(|v| v.parse::<usize>().ok())
+        {
+            limits.max_memory_bytes = mb.max(1) << 20;
+        }
+        if let Some(ms) =
+            read_env_non_empty(ENV_PROCESS_DEADLINE_MS).and_then(|v| v.parse::<u64>().ok())
+        {
+            limits.process_deadline = Duration::from_millis(ms.max(1));
+        }
+        limits
+    }
+}
+
+/// Converts a deadline into epoch ticks, rounding up and adding one so a call
+/// started just before a tick still gets its whole budget.
+fn deadline_ticks(deadline: Duration) -> u64 {
+    let tick = EPOCH_TICK.as_nanos().max(1);
+    (deadline.as_nanos().div_ceil(tick) as u64).saturating_add(1)
+}
+
+/// One wasmtime `Engine` per process, so compiled modules can be shared by
+/// every plugin instance. Epoch interruption is what enforces
+/// `SandboxLimits` deadlines.
+fn shared_engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut config = Config::new();
+        config.epoch_interruption(true);
+        Engine::new(&config).expect("default wasmtime config with epoch interruption is valid")
+    })
+}
+
+/// Number of live runtimes and whether the ticker thread is running. The
+/// thread exits when the count drops to zero, so an idle (or unloading)
+/// plugin leaves no thread behind.
+fn epoch_ticker() -> &'static Mutex<(usize, bool)> {
+    static TICKER: OnceLock<Mutex<(usize, bool)>> = OnceLock::new();
+    TICKER.get_or_init(|| Mutex::new((0, false)))
+}
+
+/// Registers a runtime with the epoch ticker for as long as it lives.
+struct EpochTickerGuard;
+
+impl EpochTickerGuard {
+    fn acquire() -> Result<Self, EvergreenError> {
+        let mut ticker = epoch_ticker()
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm epoch ticker".to_string()))?;
+        ticker.0 += 1;
+        if !ticker.1 {
+            std::thread::Builder::new()
+                .name("tonelab-wasm-epoch".to_string())
+                .spawn(run_epoch_ticker)
+                .map_err(|e| {
+                    ticker.0 -= 1;
+                    EvergreenError::Host(format!("failed to start wasm epoch ticker: {}", e))
+                })?;
+            ticker.1 = true;
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for EpochTickerGuard {
+    fn drop(&mut self) {
+        if let Ok(mut ticker) = epoch_ticker().lock() {
+            ticker.0 = ticker.0.saturating_sub(1);
+        }
+    }
+}
+
+fn run_epoch_ticker() {
+    let engine = shared_engine();
+    loop {
+        std::thread::sleep(EPOCH_TICK);
+        engine.increment_epoch();
+        let Ok(mut ticker) = epoch_ticker().lock() else {
+            return;
+        };
+        if ticker.0 == 0 {
+            ticker.1 = false;
+            return;
+        }
+    }
+}
+
+/// Compiled modules by bundle key. Entries are weak: a module's code is freed
+/// once the last runtime using it is dropped.
+fn module_registry() -> &'static Mutex<HashMap<String, Weak<Module>>> {
+    static MODULES: OnceLock<Mutex<HashMap<String, Weak<Module>>>> = OnceLock::new();
+    MODULES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Returns the process-wide module for `wasm_bytes`, compiling it (or loading
+/// it from `cache`'s precompiled modules) only if no live instance holds it.
+/// The registry stays locked while compiling so concurrent loads of the same
+/// bundle compile once.
+fn shared_module(
+    wasm_bytes: &[u8],
+    cache: Option<&CacheManager>,
+) -> Result<Arc<Module>, EvergreenError> {
+    let key = bundle_key(wasm_bytes);
+    let mut modules = module_registry()
+        .lock()
+        .map_err(|_| EvergreenError::Host("failed to lock wasm module registry".to_string()))?;
+    modules.retain(|_, module| module.strong_count() > 0);
+    if let Some(module) = modules.get(&key).and_then(Weak::upgrade) {
+        return Ok(module);
+    }
+
+    let engine = shared_engine();
+    let precompiled = cache.map(|cache| (cache, precompiled_name(engine, wasm_bytes)));
+    let cached = precompiled
+        .as_ref()
+        .and_then(|(cache, name)| cache.read_precompiled(engine, name));
+    let module = match cached {
+        Some(module) => module,
+        None => {
+            let module = Module::new(engine, wasm_bytes)
+                .map_err(|e| EvergreenError::Abi(format!("wasm module load failed: {}", e)))?;
+            if let Some((cache, name)) = precompiled.as_ref() {
+                // Best effort: a missing artifact only costs the next load a compile.
+                if let Ok(serialized) = module.serialize() {
+                    let _ = cache.store_precompiled(name, &serialized);
+                }
+            }
+            module
+        }
+    };
+
+    let module = Arc::new(module);
+    modules.insert(key, Arc::downgrade(&module));
+    Ok(module)
+}
+
+struct WasmRuntimeInner {
+    store: Store<StoreLimits>,
+    limits: SandboxLimits,
+    /// Deadline of the call in flight, for error messages.
+    armed: Duration,
+    memory: Memory,
+    alloc_samples: TypedFunc<i32, i32>,
+    alloc_bytes: TypedFunc<i32, i32>,
+    process: TypedFunc<(i32, i32, i32), ()>,
+    set_sample_rate: TypedFunc<f32, i32>,
+    set_chain_json: TypedFunc<(i32, i32), i32>,
+    /// `None` when the guest does not advertise `CAP_SET_PARAM_JSON`.
+    set_param_json: Option<TypedFunc<(i32, i32), i32>>,
+    /// `None` when the guest does not advertise `CAP_LAST_ERROR`.
+    last_error: Option<TypedFunc<(), i64>>,
+    /// `None` when the guest does not advertise `CAP_GET_CHAIN_JSON`.
+    get_chain_json: Option<TypedFunc<(), i64>>,
+    /// Guest input/output buffers reused by every `process` call, as
+    /// `(input_ptr, output_ptr, samples)`.
+    sample_buffers: Option<(i32, i32, usize)>,
+    /// Guest buffer reused for JSON payloads, as `(ptr, bytes)`.
+    byte_buffer: Option<(i32, usize)>,
+    /// Set once a call was interrupted. The guest may have been stopped
+    /// halfway through updating its own state, so it is not entered again.
+    interrupted: bool,
+}
+
+impl WasmRuntime {
+    /// Loads with `SandboxLimits::from_env()`.
+    pub fn from_bytes(wasm_bytes: &[u8]) -> Result<Self, EvergreenError> {
+        Self::with_limits(wasm_bytes, SandboxLimits::from_env())
+    }
+
+    pub fn with_limits(wasm_bytes: &[u8], limits: SandboxLimits) -> Result<Self, EvergreenError> {
+        Self::instantiate(shared_module(wasm_bytes, None)?, limits)
+    }
+
+    /// Like `from_bytes`, but reuses machine code compiled by an earlier load
+    /// from the cache's `compiled/` dir, and stores it there after compiling.
+    fn from_bytes_cached(wasm_bytes: &[u8], cache: &CacheManager) -> Result<Self, EvergreenError> {
+        Self::instantiate(
+            shared_module(wasm_bytes, Some(cache))?,
+            SandboxLimits::from_env(),
+        )
+    }
+
+    /// Gives this runtime its own `Store`/`Instance` (and so its own linear
+    /// memory and DSP state) over a module that may be shared.
+    fn instantiate(module: Arc<Module>, limits: SandboxLimits) -> Result<Self, EvergreenError> {
+        let ticker = EpochTickerGuard::acquire()?;
+        let store_limits = StoreLimitsBuilder::new()
+            .memory_size(limits.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(module.engine(), store_limits);
+        store.limiter(|limits| limits);
+        store.set_epoch_deadline(deadline_ticks(limits.control_deadline));
+        let instance = Instance::new(&mut store, &module, &[])
+            .map_err(|e| EvergreenError::Abi(format!("wasm instance creation failed: {}", e)))?;
+
+        let abi = probe_abi(&mut store, &instance)?;
+        check_abi_version(abi.version)?;
+
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| EvergreenError::Abi("wasm export 'memory' is missing".to_string()))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&mut store, "alloc")
+            .map_err(|e| {
+                EvergreenError::Abi(format!("wasm export 'alloc' is missing or invalid: {}", e))
+            })?;
+        let alloc_bytes = match instance.get_typed_func::<i32, i32>(&mut store, "alloc_bytes") {
+            Ok(alloc_bytes) => alloc_bytes,
+            // Pre-handshake bundles sometimes only had `alloc`, which over-allocates
+            // for byte payloads but is otherwise compatible.
+            Err(_) if !abi.handshake => alloc.clone(),
+            Err(e) => {
+                return Err(EvergreenError::Abi(format!(
+                    "wasm export 'alloc_bytes' is missing or invalid: {}",
+                    e
+                )))
+            }
+        };
+        let process = instance
+            .get_typed_func::<(i32, i32, i32), ()>(&mut store, "process")
+            .map_err(|e| {
+                EvergreenError::Abi(format!(
+                    "wasm export 'process' is missing or invalid: {}",
+                    e
+                ))
+            })?;
+        let set_sample_rate = instance
+            .get_typed_func::<f32, i32>(&mut store, "set_sample_rate")
+            .map_err(|e| {
+                EvergreenError::Abi(format!(
+                    "wasm export 'set_sample_rate' is missing or invalid: {}",
+                    e
+                ))
+            })?;
+        let set_chain_json = instance
+            .get_typed_func::<(i32, i32), i32>(&mut store, "set_chain_json")
+            .map_err(|e| {
+                EvergreenError::Abi(format!(
+                    "wasm export 'set_chain_json' is missing or invalid: {}",
+                    e
+                ))
+            })?;
+        let set_param_json = if abi.supports(CAP_SET_PARAM_JSON) {
+            Some(
+                instance
+                    .get_typed_func::<(i32, i32), i32>(&mut store, "set_param_json")
+                    .map_err(|e| {
+                        EvergreenError::Abi(format!(
+                            "wasm export 'set_param_json' is missing or invalid: {}",
+                            e
+                        ))
+                    })?,
+            )
+        } else {
+            None
+        };
+        let last_error = if abi.supports(CAP_LAST_ERROR) {
+            Some(
+                instance
+                    .get_typed_func::<(), i64>(&mut store, "last_error")
+                    .map_err(|e| {
+                        EvergreenError::Abi(format!(
+                            "wasm export 'last_error' is missing or invalid: {}",
+                            e
+                        ))
+                    })?,
+            )
+        } else {
+            None
+        };
+        let get_chain_json = if abi.supports(CAP_GET_CHAIN_JSON) {
+            Some(
+                instance
+                    .get_typed_func::<(), i64>(&mut store, "get_chain_json")
+                    .map_err(|e| {
+                        EvergreenError::Abi(format!(
+                            "wasm export 'get_chain_json' is missing or invalid: {}",
+                            e
+                        ))
+                    })?,
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            abi,
+            _module: module,
+            _ticker: ticker,
+            inner: Mutex::new(WasmRuntimeInner {
+                store,
+                limits,
+                armed: limits.control_deadline,
+                memory,
+                alloc_samples: alloc,
+                alloc_bytes,
+                process,
+                set_sample_rate,
+                set_chain_json,
+                set_param_json,
+                last_error,
+                get_chain_json,
+                sample_buffers: None,
+                byte_buffer: None,
+                interrupted: false,
+            }),
+        })
+    }
+
+    pub fn abi(&self) -> EngineAbi {
+        self.abi
+    }
+
+    fn smoke_test(&self) -> Result<(), EvergreenError> {
+        let input = [0.0f32, 0.0f32, 0.2f32, -0.2f32, -0.4f32, 0.4f32];
+        let mut output = [0.0f32; 6];
+        self.process_interleaved_stereo(&input, &mut output)?;
+        if output.iter().all(|sample| sample.is_finite()) {
+            Ok(())
+        } else {
+            Err(EvergreenError::Trap(
+                "wasm smoke test produced non-finite samples".to_string(),
+            ))
+        }
+    }
+
+    fn process_interleaved_stereo(
+        &self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), EvergreenError> {
+        if input.len() != output.len() {
+            return Err(EvergreenError::Validation(
+                "wasm runtime expected output length to match input length".to_string(),
+            ));
+        }
+        if !input.len().is_multiple_of(2) {
+            return Err(EvergreenError::Validation(
+                "wasm runtime expects interleaved stereo input (even sample count)".to_string(),
+            ));
+        }
+
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm runtime".to_string()))?;
+        let result = inner.process(input, output);
+        if result.is_err() {
+            // Whatever the guest left in `output` is garbage: pass the dry
+            // signal through instead.
+            output.copy_from_slice(input);
+        }
+        result
+    }
+
+    fn set_sample_rate(&self, sample_rate: f32) -> Result<(), EvergreenError> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm runtime".to_string()))?;
+        let control_deadline = inner.limits.control_deadline;
+        inner.arm(control_deadline)?;
+        let set_sample_rate = inner.set_sample_rate.clone();
+        let status = set_sample_rate
+            .call(&mut inner.store, sample_rate)
+            .map_err(|e| inner.call_error("set_sample_rate", e))?;
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(inner.status_error("set_sample_rate", status))
+        }
+    }
+
+    fn set_chain_json(&self, chain_json: &str) -> Result<(), EvergreenError> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm runtime".to_string()))?;
+        let control_deadline = inner.limits.control_deadline;
+        inner.arm(control_deadline)?;
+        let set_chain_json = inner.set_chain_json.clone();
+        let memory = inner.memory;
+        let payload = chain_json.as_bytes();
+        let payload_len_i32 = i32::try_from(payload.len())
+            .map_err(|_| EvergreenError::Validation("chain JSON payload too large".to_string()))?;
+
+        let payload_ptr = inner.byte_buffer(payload.len(), "alloc(chain_json)")?;
+
+        {
+            let memory_data = memory.data_mut(&mut inner.store);
+            write_byte_slice(memory_data, payload_ptr as usize, payload)?;
+        }
+
+        let status = set_chain_json
+            .call(&mut inner.store, (payload_ptr, payload_len_i32))
+            .map_err(|e| inner.call_error("set_chain_json", e))?;
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(inner.status_error("set_chain_json", status))
+        }
+    }
+
+    fn set_param_json(&self, effect_idx: i32, key: &str, value: f32) -> Result<(), EvergreenError> {
+        let payload = serde_json::json!({
+            "index": effect_idx,
+            "param_key": key,
+            "value": value
+        });
+        let payload = serde_json::to_vec(&payload).map_err(|e| {
+            EvergreenError::Validation(format!(
+                "failed to serialize wasm param JSON payload: {}",
+                e
+            ))
+        })?;
+
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm runtime".to_string()))?;
+        let control_deadline = inner.limits.control_deadline;
+        inner.arm(control_deadline)?;
+        let set_param_json = inner.set_param_json.clone().ok_or_else(|| {
+            EvergreenError::Abi(
+                "engine.wasm does not support live param changes (no set_param_json)".to_string(),
+            )
+        })?;
+        let memory = inner.memory;
+        let payload_len_i32 = i32::try_from(payload.len())
+            .map_err(|_| EvergreenError::Validation("param JSON payload too large".to_string()))?;
+
+        let payload_ptr = inner.byte_buffer(payload.len(), "alloc(param_json)")?;
+
+        {
+            let memory_data = memory.data_mut(&mut inner.store);
+            write_byte_slice(memory_data, payload_ptr as usize, &payload)?;
+        }
+
+        let status = set_param_json
+            .call(&mut inner.store, (payload_ptr, payload_len_i32))
+            .map_err(|e| inner.call_error("set_param_json", e))?;
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(inner.status_error("set_param_json", status))
+        }
+    }
+
+    fn get_chain_json(&self) -> Result<String, EvergreenError> {
+        let mut inner = self
+            .inner
+            .lock()
+            .map_err(|_| EvergreenError::Host("failed to lock wasm runtime".to_string()))?;
+        let control_deadline = inner.limits.control_deadline;
+        inner.arm(control_deadline)?;
+        let get_chain_json = inner.get_chain_json.clone().ok_or_else(|| {
+            EvergreenError::Abi(
+                "engine.wasm cannot report its chain (no get_chain_json)".to_string(),
+            )
+        })?;
+
+        let packed = get_chain_json
+            .call(&mut inner.store, ())
+            .map_err(|e| inner.call_error("get_chain_json", e))? as u64;
+        if packed == 0 {
+            let reason = inner
+                .guest_message()?
+                .unwrap_or_else(|| "no reason given".to_string());
+            return Err(EvergreenError::Validation(format!(
+                "wasm get_chain_json returned no chain: {}",
+                reason
+            )));
+        }
+        let ptr = (packed >> 32) as usize;
+        let len = (packed & 0xffff_ffff) as usize;
+        if len > MAX_GUEST_CHAIN_JSON_BYTES {
+            return Err(EvergreenError::Validation(format!(
+                "wasm chain JSON is {} bytes, over the {} byte limit",
+                len, MAX_GUEST_CHAIN_JSON_BYTES
+            )));
+        }
+        let bytes = ptr
+            .checked_add(len)
+            .and_then(|end| inner.memory.data(&inner.store).get(ptr..end))
+            .ok_or_else(|| {
+                EvergreenError::Trap("wasm get_chain_json pointed outside guest memory".to_string())
+            })?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| {
+            EvergreenError::Validation("wasm chain JSON is not valid UTF-8".to_string())
+        })
+    }
+}
+
+impl WasmRuntimeInner {
+    /// Starts the deadline for the next guest call(s). Fails once an earlier
+    /// call was interrupted: the instance has to be reloaded.
+    fn arm(&mut self, deadline: Duration) -> Result<(), EvergreenError> {
+        if self.interrupted {
+            return Err(EvergreenError::Deadline(
+                "wasm engine was interrupted earlier and must be reloaded".to_string(),
+            ));
+        }
+        self.armed = deadline;
+        self.store.set_epoch_deadline(deadline_ticks(deadline));
+        Ok(())
+    }
+
+    fn call_error(&mut self, call: &str, error: wasmtime::Error) -> EvergreenError {
+        if error.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+            self.interrupted = true;
+            EvergreenError::Deadline(format!(
+                "wasm {} exceeded its {} ms deadline and was interrupted",
+                call,
+                self.armed.as_millis()
+            ))
+        } else {
+            EvergreenError::Trap(format!("wasm {} failed: {}", call, error))
+        }
+    }
+
+    /// Error for a non-zero `status` from `call`, with the guest's message
+    /// when it exports `last_error`. A `last_error` that traps or overruns
+    /// its deadline is reported instead.
+    fn status_error(&mut self, call: &'static str, status: i32) -> EvergreenError {
+        match self.guest_message() {
+            Ok(message) => EvergreenError::GuestStatus {
+                call,
+                status,
+                message,
+            },
+            Err(error) => error,
+        }
+    }
+
+    /// Reads `last_error` out of guest memory under the control deadline.
+    /// A message that is missing or out of bounds is `None`, so the status
+    /// is still reported; a trap or interrupt is an error like any call's.
+    fn guest_message(&mut self) -> Result<Option<String>, EvergreenError> {
+        let Some(last_error) = self.last_error.clone() else {
+            return Ok(None);
+        };
+        self.arm(self.limits.control_deadline)?;
+        let packed = last_error
+            .call(&mut self.store, ())
+            .map_err(|e| self.call_error("last_error", e))? as u64;
+        let ptr = (packed >> 32) as usize;
+        let len = ((packed & 0xffff_ffff) as usize).min(MAX_GUEST_ERROR_BYTES);
+        let message = ptr
+            .checked_add(len)
+            .and_then(|end| self.memory.data(&self.store).get(ptr..end))
+            .map(|bytes| String::from_utf8_lossy(bytes).trim().to_string());
+        Ok(message.filter(|message| !message.is_empty()))
+    }
+
+    /// Guest input/output buffers for `samples` samples each. The guest's
+    /// `alloc` has no matching free, so they are allocated once per instance
+    /// and only regrown (to the next power of two) for a larger block.
+    fn sample_buffers(&mut self, samples: usize) -> Result<(i32, i32), EvergreenError> {
+        if let Some((input_ptr, output_ptr, capacity)) = self.sample_buffers {
+            if capacity >= samples {
+                return Ok((input_ptr, output_ptr));
+            }
+        }
+        let capacity = samples.next_power_of_two();
+        let capacity_i32 = i32::try_from(capacity)
+            .map_err(|_| EvergreenError::Validation("audio block too large".to_string()))?;
+        let alloc = self.alloc_samples.clone();
+        let input_ptr = alloc
+            .call(&mut self.store, capacity_i32)
+            .map_err(|e| self.call_error("alloc(input)", e))?;
+        let output_ptr = alloc
+            .call(&mut self.store, capacity_i32)
+            .map_err(|e| self.call_error("alloc(output)", e))?;
+        self.sample_buffers = Some((input_ptr, output_ptr, capacity));
+        Ok((input_ptr, output_ptr))
+    }
+
+    /// Guest buffer for a `bytes` long payload, reused like `sample_buffers`.
+    fn byte_buffer(&mut self, bytes: usize, call: &str) -> Result<i32, EvergreenError> {
+        if let Some((ptr, capacity)) = self.byte_buffer {
+            if capacity >= bytes {
+                return Ok(ptr);
+            }
+        }
+        let capacity = bytes.next_power_of_two();
+        let capacity_i32 = i32::try_from(capacity)
+            .map_err(|_| EvergreenError::Validation("JSON payload too large".to_string()))?;
+        let alloc_bytes = self.alloc_bytes.clone();
+        let ptr = alloc_bytes
+            .call(&mut self.store, capacity_i32)
+            .map_err(|e| self.call_error(call, e))?;
+        self.byte_buffer = Some((ptr, capacity));
+        Ok(ptr)
+    }
+
+    fn process(&mut self, input: &[f32], output: &mut [f32]) -> Result<(), EvergreenError> {
+        self.arm(self.limits.process_deadline)?;
+        let frame_count = (input.len() / 2) as i32;
+        let process = self.process.clone();
+        let memory = self.memory;
+
+        let (input_ptr, output_ptr) = self.sample_buffers(input.len())?;
+
+        {
+            let memory_data = memory.data_mut(&mut self.store);
+            write_f32_slice(memory_data, input_ptr as usize, input)?;
+        }
+
+        process
+            .call(&mut self.store, (input_ptr, output_ptr, frame_count))
+            .map_err(|e| self.call_error("process call", e))?;
+
+        {
+            let memory_data = memory.data(&self.store);
+            read_f32_slice(memory_data, output_ptr as usize, output)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ToneEngine for WasmRuntime {
+    fn backend(&self) -> EngineBackend {
+        EngineBackend::Wasm
+    }
+
+    fn process_interleaved_stereo(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), EvergreenError> {
+        WasmRuntime::process_interleaved_stereo(self, input, output)
+    }
+
+    fn set_param(&mut self, effect_idx: i32, key: &str, value: f32) -> Result<(), EvergreenError> {
+        self.set_param_json(effect_idx, key, value)
+    }
+
+    fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), EvergreenError> {
+        self.set_chain_json(chain_json)
+    }
+
+    fn chain_json(&self) -> Result<Option<String>, EvergreenError> {
+        if !self.abi.supports(CAP_GET_CHAIN_JSON) {
+            return Ok(None);
+        }
+        self.get_chain_json().map(Some)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), EvergreenError> {
+        WasmRuntime::set_sample_rate(self, sample_rate)
+    }
+}
+
+/// Reads `abi_version`/`capabilities`, or infers them for bundles built before
+/// the handshake existed.
+fn probe_abi(
+    store: &mut Store<StoreLimits>,
+    instance: &Instance,
+) -> Result<EngineAbi, EvergreenError> {
+    let Ok(abi_version) = instance.get_typed_func::<(), i32>(&mut *store, "abi_version") else {
+        let mut capabilities = 0;
+        if instance
+            .get_typed_func::<(i32, i32), i32>(&mut *store, "set_param_json")
+            .is_ok()
+        {
+            capabilities |= CAP_SET_PARAM_JSON;
+        }
+        if instance
+            .get_typed_func::<(), i64>(&mut *store, "last_error")
+            .is_ok()
+        {
+            capabilities |= CAP_LAST_ERROR;
+        }
+        if instance
+            .get_typed_func::<(), i64>(&mut *store, "get_chain_json")
+            .is_ok()
+        {
+            capabilities |= CAP_GET_CHAIN_JSON;
+        }
+        return Ok(EngineAbi {
+            version: MIN_GUEST_ABI_VERSION,
+            capabilities,
+            handshake: false,
+        });
+    };
+
+    let version = abi_version
+        .call(&mut *store, ())
+        .map_err(|e| EvergreenError::Abi(format!("wasm abi_version failed: {}", e)))?;
+    let capabilities = instance
+        .get_typed_func::<(), i32>(&mut *store, "capabilities")
+        .map_err(|e| {
+            EvergreenError::Abi(format!(
+                "wasm export 'capabilities' is missing or invalid: {}",
+                e
+            ))
+        })?
+        .call(&mut *store, ())
+        .map_err(|e| EvergreenError::Abi(format!("wasm capabilities failed: {}", e)))?;
+    Ok(EngineAbi {
+        version: u32::try_from(version)
+            .map_err(|_| EvergreenError::Abi(format!("wasm abi_version returned {}", version)))?,
+        capabilities: capabilities as u32,
+        handshake: true,
+    })
+}
+
+/// Folds a param change into a chain JSON payload (legacy array or
+/// `{ "effects": [...] }` form) so the remembered chain stays current.
+fn patch_chain_param(chain_json: &str, effect_idx: i32, key: &str, value: f32) -> Option<String> {
+    let mut chain = serde_json::from_str::<serde_json::Value>(chain_json).ok()?;
+    let effects = if chain.is_array() {
+        &mut chain
+    } else {
+        chain.get_mut("effects")?
+    };
+    let params = effects
+        .get_mut(usize::try_from(effect_idx).ok()?)?
+        .get_mut("params")?
+        .as_object_mut()?;
+    params.insert(key.to_string(), serde_json::json!(value));
+    serde_json::to_string(&chain).ok()
+}
+
+fn write_f32_slice(memory: &mut [u8], ptr: usize, values: &[f32]) -> Result<(), EvergreenError> {
+    let byte_len = values
+        .len()
+        .checked_mul(std::mem::size_of::<f32>())
+        .ok_or_else(|| EvergreenError::Trap("input byte length overflow".to_string()))?;
+    let end = ptr
+        .checked_add(byte_len)
+        .ok_or_else(|| EvergreenError::Trap("input pointer overflow".to_string()))?;
+    if end > memory.len() {
+        return Err(EvergreenError::Trap(
+            "input write out of wasm memory bounds".to_string(),
+        ));
+    }
+
+    for (index, value) in values.iter().enumerate() {
+        let offset = ptr + index * std::mem::size_of::<f32>();
+        memory[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn write_byte_slice(memory: &mut [u8], ptr: usize, values: &[u8]) -> Result<(), EvergreenError> {
+    let end = ptr
+        .checked_add(values.len())
+        .ok_or_else(|| EvergreenError::Trap("byte payload pointer overflow".to_string()))?;
+    if end > memory.len() {
+        return Err(EvergreenError::Trap(
+            "byte payload write out of wasm memory bounds".to_string(),
+        ));
+    }
+    memory[ptr..end].copy_from_slice(values);
+    Ok(())
+}
+
+fn read_f32_slice(memory: &[u8], ptr: usize, output: &mut [f32]) -> Result<(), EvergreenError> {
+    let byte_len = output
+        .len()
+        .checked_mul(std::mem::size_of::<f32>())
+        .ok_or_else(|| EvergreenError::Trap("output byte length overflow".to_string()))?;
+    let end = ptr
+        .checked_add(byte_len)
+        .ok_or_else(|| EvergreenError::Trap("output pointer overflow".to_string()))?;
+    if end > memory.len() {
+        return Err(EvergreenError::Trap(
+            "output read out of wasm memory bounds".to_string(),
+        ));
+    }
+
+    for (index, slot) in output.iter_mut().enumerate() {
+        let offset = ptr + index * std::mem::size_of::<f32>();
+        let bytes = [
+            memory[offset],
+            memory[offset + 1],
+            memory[offset + 2],
+            memory[offset + 3],
+        ];
+        *slot = f32::from_le_bytes(bytes);
+    }
+    Ok(())
+}
+
+/// Tells the sync endpoint what this plugin can drive, so it can withhold
+/// bundles that would be refused anyway.
+fn sync_request_url(sync_url: &str, channel: &ReleaseChannel) -> String {
+    if local_source_path(sync_url).is_some() {
+        return sync_url.to_string();
+    }
+    let query = sync_url.split_once('?').map(|(_, query)| query);
+    let separator = if query.is_some() { '&' } else { '?' };
+    // A channel in the configured URL wins over the selected one.
+    let channel_in_url =
+        query.is_some_and(|query| query.split('&').any(|param| param.starts_with("channel=")));
+    let channel = if channel_in_url {
+        String::new()
+    } else {
+        format!("&{}", channel.query())
+    };
+    format!(
+        "{}{}host_version={}&abi={}{}",
+        sync_url, separator, HOST_VERSION, MAX_GUEST_ABI_VERSION, channel
+    )
+}
+
+/// Compares dotted numeric versions (`1.2`, `v1.2.3`, `1.2.3-beta.1`) by
+/// semver precedence: a pre-release sorts below its release, and build
+/// metadata (`+...`) is ignored. `None` if either side is not a version.
+fn compare_versions(left: &str, right: &str) -> Option<Ordering> {
+    fn parse(version: &str) -> Option<(Vec<u64>, Vec<&str>)> {
+        let version = version.trim();
+        let version = version.strip_prefix('v').unwrap_or(version);
+        let version = version.split('+').next()?;
+        let (core, pre) = match version.split_once('-') {
+            Some((core, pre)) => (core, pre.split('.').collect::<Vec<_>>()),
+            None => (version, Vec::new()),
+        };
+        if pre.iter().any(|identifier| identifier.is_empty()) {
+            return None;
+        }
+        let core = core
+            .split('.')
+            .map(|part| part.parse::<u64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        Some((core, pre))
+    }
+
+    /// Numeric identifiers compare numerically and sort below alphanumeric
+    /// ones, which compare as ASCII.
+    fn compare_identifiers(left: &str, right: &str) -> Ordering {
+        match (left.parse::<u64>(), right.parse::<u64>()) {
+            (Ok(left), Ok(right)) => left.cmp(&right),
+            (Ok(_), Err(_)) => Ordering::Less,
+            (Err(_), Ok(_)) => Ordering::Greater,
+            (Err(_), Err(_)) => left.cmp(right),
+        }
+    }
+
+    let (mut left_core, left_pre) = parse(left)?;
+    let (mut right_core, right_pre) = parse(right)?;
+    let len = left_core.len().max(right_core.len());
+    left_core.resize(len, 0);
+    right_core.resize(len, 0);
+    let ordering = left_core.cmp(&right_core).then_with(|| {
+        match (left_pre.is_empty(), right_pre.is_empty()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => left_pre
+                .iter()
+                .zip(&right_pre)
+                .map(|(left, right)| compare_identifiers(left, right))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| left_pre.len().cmp(&right_pre.len())),
+        }
+    });
+    Some(ordering)
+}
+
+fn fetch_sync_manifest(
+    sync_url: &str,
+    cache: &CacheManager,
+) -> Result<SyncManifest, EvergreenError> {
+    let manifest = match local_source_path(sync_url) {
+        Some(source) => read_local_manifest(&source)?,
+        None => serde_json::from_slice::<SyncManifest>(&fetch_sync_response(sync_url, cache)?)
+            .map_err(|e| {
+                EvergreenError::Validation(format!("failed to parse sync manifest JSON: {}", e))
+            })?,
+    };
+    if manifest.version.trim().is_empty() {
+        return Err(EvergreenError::Validation(
+            "sync manifest has empty version".to_string(),
+        ));
+    }
+    Ok(manifest)
+}
+
+/// GETs the sync manifest, revalidating the copy from the last check so an
+/// unchanged manifest is answered with a `304`.
+fn fetch_sync_response(sync_url: &str, cache: &CacheManager) -> Result<Vec<u8>, EvergreenError> {
+    let cached = cache.cached_sync_response(sync_url);
+    let response = download::get_conditional(
+        sync_url,
+        download::MAX_MANIFEST_BYTES,
+        cached.as_ref().map(|cached| &cached.validators),
+        download::Retry::DEFAULT,
+    )
+    .map_err(EvergreenError::Network)?;
+    match (response, cached) {
+        (Conditional::NotModified, Some(cached)) => Ok(cached.body.into_bytes()),
+        (Conditional::NotModified, None) => Err(EvergreenError::Network(
+            "unexpected 304 for the sync manifest".to_string(),
+        )),
+        (Conditional::Modified { body, validators }, _) => {
+            if let (false, Ok(text)) = (validators.is_empty(), std::str::from_utf8(&body)) {
+                // Only an optimisation; the manifest is still good without it.
+                let _ = cache.store_sync_response(&CachedSyncResponse {
+                    url: sync_url.to_string(),
+                    validators,
+                    body: text.to_string(),
+                });
+            }
+            Ok(body)
+        }
+    }
+}
+
+/// Reads the manifest of a local source: a manifest file, or a directory
+/// with `sync_manifest.json` next to `engine.wasm`. A directory without a
+/// manifest serves its `engine.wasm` as an unsigned dev build.
+fn read_local_manifest(source: &Path) -> Result<SyncManifest, EvergreenError> {
+    let manifest_path = if source.is_dir() {
+        source.join(CACHE_MANIFEST_FILE)
+    } else {
+        source.to_path_buf()
+    };
+    if source.is_dir() && !manifest_path.exists() {
+        let wasm_bytes =
+            read_local_file(&source.join(CACHE_WASM_FILE), download::MAX_BUNDLE_BYTES)?;
+        return Ok(SyncManifest {
+            version: format!("dev-{}", short_key(&bundle_key(&wasm_bytes))),
+            wasm_url: CACHE_WASM_FILE.to_string(),
+            signature: String::new(),
+            assets: SyncAssets::default(),
+            sha256: None,
+            abi_version: None,
+            min_host_version: None,
+            key_id: None,
+            manifest_signature: None,
+            key_statements: Vec::new(),
+            channel: None,
+            deltas: Vec::new(),
+        });
+    }
+    let bytes = read_local_file(&manifest_path, download::MAX_MANIFEST_BYTES)?;
+    serde_json::from_slice::<SyncManifest>(&bytes).map_err(|e| {
+        EvergreenError::Validation(format!(
+            "failed to parse sync manifest '{}': {}",
+            manifest_path.display(),
+            e
+        ))
+    })
+}
+
+/// Filesystem path behind a `file://` URL or a plain path; `None` for
+/// network URLs.
+fn local_source_path(url: &str) -> Option<PathBuf> {
+    let url = url.trim();
+    let Some(rest) = url.strip_prefix("file://") else {
+        return (!url.is_empty() && !url.contains("://")).then(|| PathBuf::from(url));
+    };
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let path = percent_decode(rest);
+    // `file:///C:/bundles` names a Windows drive, not `/C:/bundles`.
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+/// Directory relative manifest paths resolve against.
+fn local_source_dir(source: &Path) -> PathBuf {
+    if source.is_dir() {
+        source.to_path_buf()
+    } else {
+        source.parent().map(Path::to_path_buf).unwrap_or_default()
+    }
+}
+
+/// Escapes everything but RFC 3986 unreserved characters, so a value such as
+/// `1.0.0+build.1` survives as a query parameter.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Applies a zstd `--patch-from` patch to `base`, refusing output over
+/// `limit` bytes.
+fn apply_wasm_delta(base: &[u8], patch: &[u8], limit: u64) -> Result<Vec<u8>, EvergreenError> {
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch, base)
+        .and_then(|mut decoder| {
+            decoder.window_log_max(DELTA_WINDOW_LOG_MAX)?;
+            Ok(decoder)
+        })
+        .map_err(|e| EvergreenError::Validation(format!("failed to open wasm delta: {}", e)))?;
+    let mut wasm_bytes = Vec::new();
+    (&mut decoder)
+        .take(limit + 1)
+        .read_to_end(&mut wasm_bytes)
+        .map_err(|e| EvergreenError::Validation(format!("failed to apply wasm delta: {}", e)))?;
+    if wasm_bytes.len() as u64 > limit {
+        return Err(EvergreenError::Validation(format!(
+            "patched wasm exceeds {} bytes",
+            limit
+        )));
+    }
+    Ok(wasm_bytes)
+}
+
+/// GETs an http(s) URL, refusing bodies over `limit` bytes.
+fn download_bytes(url: &str, limit: u64) -> Result<Vec<u8>, EvergreenError> {
+    download::get(http_url(url)?, limit, download::Retry::DEFAULT).map_err(EvergreenError::Network)
+}
+
+/// `url` trimmed, if it is http(s); anything else is refused.
+fn http_url(url: &str) -> Result<&str, EvergreenError> {
+    let url = url.trim();
+    let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+    if scheme.is_some_and(|scheme| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    }) {
+        Ok(url)
+    } else {
+        Err(EvergreenError::Validation(format!(
+            "refusing to fetch '{}': only http(s) URLs are allowed",
+            url
+        )))
+    }
+}
+
+/// Reads a local file, refusing more than `limit` bytes. The size is not
+/// taken from metadata, which reports 0 for devices such as `/dev/zero`.
+fn read_local_file(path: &Path, limit: u64) -> Result<Vec<u8>, EvergreenError> {
+    let read_error = |e: std::io::Error| {
+        EvergreenError::Network(format!("failed to read '{}': {}", path.display(), e))
+    };
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|file| file.take(limit + 1).read_to_end(&mut bytes))
+        .map_err(read_error)?;
+    if bytes.len() as u64 > limit {
+        return Err(EvergreenError::Network(format!(
+            "'{}' exceeds {} bytes",
+            path.display(),
+            limit
+        )));
+    }
+    Ok(bytes)
+}
+
+/// Checks `wasm_bytes` and `manifest` against `trust`: the manifest
+/// signature when there is one, otherwise the legacy wasm-only signature.
+fn verify_bundle(
+    manifest: &SyncManifest,
+    wasm_bytes: &[u8],
+    trust: &TrustStore,
+    allow_unsigned: bool,
+) -> Result<(), EvergreenError> {
+    let manifest_signature = manifest
+        .manifest_signature
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    let signature = manifest.signature.trim();
+
+    if trust.is_empty() || (manifest_signature.is_none() && signature.is_empty()) {
+        if allow_unsigned {
+            return Ok(());
+        }
+        return Err(EvergreenError::Signature(
+            "missing Ed25519 key/signature and unsigned bundles are disabled".to_string(),
+        ));
+    }
+
+    let key_id = manifest.key_id.as_deref();
+    let Some(manifest_signature) = manifest_signature else {
+        return trust
+            .verify(key_id, wasm_bytes, signature)
+            .map(|_| ())
+            .map_err(EvergreenError::Signature);
+    };
+
+    // The payload pins the wasm by hash, so the hash must match what we have.
+    let key = bundle_key(wasm_bytes);
+    match manifest.sha256.as_deref().map(str::trim) {
+        Some(expected) if expected.eq_ignore_ascii_case(&key) => {}
+        Some(expected) => {
+            return Err(EvergreenError::Signature(format!(
+                "wasm sha256 {} does not match signed manifest {}",
+                key, expected
+            )))
+        }
+        None => {
+            return Err(EvergreenError::Signature(
+                "signed sync manifest has no sha256".to_string(),
+            ))
+        }
+    }
+    trust
+        .verify(key_id, &manifest.signed_payload()?, manifest_signature)
+        .map(|_| ())
+        .map_err(EvergreenError::Signature)
+}
+
+fn allow_unsigned() -> bool {
+    env_bool(ENV_ALLOW_UNSIGNED).unwrap_or(cfg!(debug_assertions))
+}
+
+fn resolve_sync_url() -> String {
+    read_env_non_empty(ENV_SYNC_URL).unwrap_or_else(|| DEFAULT_SYNC_URL.to_string())
+}
+
+/// Root keys from the environment, else the ones embedded at build time.
+/// Either may list several base64 keys separated by commas.
+fn root_trust_store() -> Result<TrustStore, EvergreenError> {
+    let roots = read_env_non_empty(ENV_PUBLIC_KEY_B64)
+        .or_else(|| {
+            EMBEDDED_PUBLIC_KEY_B64
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        })
+        .unwrap_or_default();
+    TrustStore::from_roots(
+        roots
+            .split(',')
+            .map(str::trim)
+            .filter(|root| !root.is_empty()),
+    )
+    .map_err(EvergreenError::Signature)
+}
+
+fn evergreen_enabled() -> bool {
+    env_bool(ENV_ENABLED).unwrap_or(true)
+}
+
+fn read_env_non_empty(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn env_bool(name: &str) -> Option<bool> {
+    let value = read_env_non_empty(name)?;
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+#[allow(dead_code)]
+pub fn cache_root_for(data_dir: &Path) -> PathBuf {
+    data_dir.join(CACHE_DIR_NAME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trust::test_keys::TestKey;
+    use crate::trust::KeyAction;
+
+    fn overdrive_chain_json(drive: f32) -> String {
+        serde_json::json!([
+            {
+                "type": "Overdrive",
+                "params": { "drive": drive, "mix": 1.0, "output_gain": 1.0 }
+            }
+        ])
+        .to_string()
+    }
+
+    #[test]
+    fn native_fallback_processes_chain() {
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        engine.activate_native();
+        assert_eq!(engine.backend(), EngineBackend::Native);
+
+        engine
+            .sync_chain_json(&overdrive_chain_json(1.0))
+            .expect("chain should apply to native engine");
+        let (l, r) = engine.process_frame(0.5, 0.5).expect("native process");
+        assert!(l.is_finite() && r.is_finite());
+        assert_ne!((l, r), (0.5, 0.5), "overdrive should colour the signal");
+    }
+
+    #[test]
+    fn activate_replays_last_chain_with_param_changes() {
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        engine.activate_native();
+        engine
+            .sync_chain_json(&overdrive_chain_json(0.1))
+            .expect("chain should apply");
+        engine
+            .set_param(0, "drive", 0.9)
+            .expect("param should apply");
+        let expected = engine.process_frame(0.4, -0.4).expect("process");
+
+        engine.replace_engine(Box::new(NativeEngine::new()));
+        let replayed = engine.process_frame(0.4, -0.4).expect("process");
+        assert_eq!(expected, replayed);
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        sample_rate: Option<f32>,
+        chains: Vec<String>,
+        params: Vec<(i32, String, f32)>,
+    }
+
+    struct MockEngine(std::sync::Arc<Mutex<MockState>>);
+
+    impl ToneEngine for MockEngine {
+        fn backend(&self) -> EngineBackend {
+            EngineBackend::Wasm
+        }
+
+        fn process_interleaved_stereo(
+            &mut self,
+            input: &[f32],
+            output: &mut [f32],
+        ) -> Result<(), EvergreenError> {
+            for (out, sample) in output.iter_mut().zip(input) {
+                *out = sample * 2.0;
+            }
+            Ok(())
+        }
+
+        fn set_param(
+            &mut self,
+            effect_idx: i32,
+            key: &str,
+            value: f32,
+        ) -> Result<(), EvergreenError> {
+            let mut state = self.0.lock().unwrap();
+            state.params.push((effect_idx, key.to_string(), value));
+            Ok(())
+        }
+
+        fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), EvergreenError> {
+            if chain_json == "reject" {
+                return Err(EvergreenError::Validation(
+                    "mock rejected chain".to_string(),
+                ));
+            }
+            self.0.lock().unwrap().chains.push(chain_json.to_string());
+            Ok(())
+        }
+
+        fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), EvergreenError> {
+            self.0.lock().unwrap().sample_rate = Some(sample_rate);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn starts_as_passthrough_and_remembers_chain_for_later_engine() {
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        assert_eq!(engine.backend(), EngineBackend::Null);
+        assert!(!engine.has_runtime());
+        assert_eq!(engine.process_frame(0.1, 0.2), Ok((0.1, 0.2)));
+
+        let chain = overdrive_chain_json(0.3);
+        engine.set_sample_rate(48_000.0);
+        engine
+            .sync_chain_json(&chain)
+            .expect("null engine accepts chain");
+
+        let state = std::sync::Arc::new(Mutex::new(MockState::default()));
+        let previous = engine.replace_engine(Box::new(MockEngine(state.clone())));
+        assert_eq!(previous.backend(), EngineBackend::Null);
+        assert!(engine.has_runtime());
+
+        let recorded = state.lock().unwrap();
+        assert_eq!(recorded.sample_rate, Some(48_000.0));
+        assert_eq!(recorded.chains, vec![chain]);
+    }
+
+    #[test]
+    fn injected_engine_receives_updates_and_audio() {
+        let state = std::sync::Arc::new(Mutex::new(MockState::default()));
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        engine.replace_engine(Box::new(MockEngine(state.clone())));
+
+        engine
+            .set_param(2, "mix", 0.5)
+            .expect("mock accepts params");
+        assert_eq!(engine.process_frame(0.25, -0.5), Ok((0.5, -1.0)));
+        assert!(engine.sync_chain_json("reject").is_err());
+        assert_eq!(engine.last_chain_json(), None);
+        assert_eq!(
+            state.lock().unwrap().params,
+            vec![(2, "mix".to_string(), 0.5)]
+        );
+    }
+
+    #[test]
+    fn chain_json_prefers_the_engine_and_falls_back_to_the_last_chain() {
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        engine.activate_native();
+        engine
+            .sync_chain_json(&overdrive_chain_json(0.1))
+            .expect("chain should apply");
+        engine
+            .set_param(0, "drive", 0.9)
+            .expect("param should apply");
+        let reported: serde_json::Value = serde_json::from_str(
+            &engine
+                .chain_json()
+                .expect("native reports its chain")
+                .expect("native has a chain"),
+        )
+        .expect("chain JSON");
+        let drive = reported["effects"][0]["params"]["drive"]
+            .as_f64()
+            .expect("drive is reported");
+        assert!((drive - 0.9).abs() < 1e-6);
+
+        let state = std::sync::Arc::new(Mutex::new(MockState::default()));
+        engine.replace_engine(Box::new(MockEngine(state)));
+        assert_eq!(
+            engine.chain_json().expect("mock cannot report"),
+            engine.last_chain_json().map(str::to_string)
+        );
+    }
+
+    #[test]
+    fn patch_chain_param_handles_array_and_object_forms() {
+        let array = patch_chain_param(&overdrive_chain_json(0.1), 0, "drive", 0.5)
+            .expect("array form should patch");
+        assert!(array.contains("\"drive\":0.5"));
+
+        let object = serde_json::json!({
+            "effects": [{ "type": "Delay", "params": { "mix": 0.1 } }],
+            "token": null
+        })
+        .to_string();
+        let patched = patch_chain_param(&object, 0, "mix", 0.25).expect("object form should patch");
+        assert!(patched.contains("\"mix\":0.25"));
+
+        assert!(patch_chain_param(&object, 3, "mix", 0.25).is_none());
+        assert!(patch_chain_param(&object, -1, "mix", 0.25).is_none());
+    }
+
+    /// Minimal guest: bump allocator, passthrough-free `process`, and whatever
+    /// extra exports the test needs.
+    fn guest_wat(extra: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (func $alloc (export "alloc") (param $size i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next
+                        (i32.add (global.get $next) (i32.mul (local.get $size) (i32.const 4))))
+                    (local.get $ptr))
+                (func (export "process") (param i32 i32 i32))
+                (func (export "set_sample_rate") (param f32) (result i32) (i32.const 0))
+                (func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 0))
+                {extra})"#
+        )
+    }
+
+    fn manifest(abi_version: Option<u32>, min_host_version: Option<&str>) -> SyncManifest {
+        SyncManifest {
+            version: "test".to_string(),
+            wasm_url: "https://example.invalid/engine.wasm".to_string(),
+            signature: String::new(),
+            assets: SyncAssets::default(),
+            sha256: None,
+            abi_version,
+            min_host_version: min_host_version.map(str::to_string),
+            key_id: None,
+            manifest_signature: None,
+            key_statements: Vec::new(),
+            channel: None,
+            deltas: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn legacy_guest_without_handshake_is_adapted() {
+        let mut runtime =
+            WasmRuntime::from_bytes(guest_wat("").as_bytes()).expect("legacy guest loads");
+        assert_eq!(
+            runtime.abi(),
+            EngineAbi {
+                version: MIN_GUEST_ABI_VERSION,
+                capabilities: 0,
+                handshake: false,
+            }
+        );
+        runtime
+            .sync_chain_json("[]")
+            .expect("alloc stands in for alloc_bytes");
+        let error = ToneEngine::set_param(&mut runtime, 0, "drive", 0.5)
+            .expect_err("no set_param_json capability");
+        assert!(
+            matches!(&error, EvergreenError::Abi(message) if message.contains("set_param_json")),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn handshake_guest_must_export_what_it_advertises() {
+        let handshake = format!(
+            r#"(func (export "abi_version") (result i32) (i32.const {ABI_VERSION}))
+               (func (export "capabilities") (result i32) (i32.const {CAP_SET_PARAM_JSON}))"#
+        );
+        let error = WasmRuntime::from_bytes(guest_wat(&handshake).as_bytes())
+            .err()
+            .expect("alloc_bytes is required once the guest has a handshake");
+        assert!(
+            matches!(&error, EvergreenError::Abi(message) if message.contains("alloc_bytes")),
+            "{error:?}"
+        );
+
+        let complete = format!(
+            r#"{handshake}
+               (func (export "alloc_bytes") (param i32) (result i32) (call $alloc (local.get 0)))
+               (func (export "set_param_json") (param i32 i32) (result i32) (i32.const 0))"#
+        );
+        let mut runtime =
+            WasmRuntime::from_bytes(guest_wat(&complete).as_bytes()).expect("guest loads");
+        assert!(runtime.abi().handshake);
+        assert!(runtime.abi().supports(CAP_SET_PARAM_JSON));
+        ToneEngine::set_param(&mut runtime, 0, "drive", 0.5).expect("param applies");
+    }
+
+    #[test]
+    fn refuses_guest_with_unsupported_abi() {
+        let future = guest_wat(&format!(
+            r#"(func (export "abi_version") (result i32) (i32.const {}))
+               (func (export "capabilities") (result i32) (i32.const 0))"#,
+            MAX_GUEST_ABI_VERSION + 1
+        ));
+        let error = WasmRuntime::from_bytes(future.as_bytes())
+            .err()
+            .expect("newer ABI must be refused");
+        assert!(
+            matches!(&error, EvergreenError::Abi(message) if message.contains("not supported")),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn manifest_compat_checks_abi_and_host_version() {
+        assert!(manifest(None, None).check_host_compat().is_ok());
+        assert!(manifest(Some(ABI_VERSION), Some("0.1"))
+            .check_host_compat()
+            .is_ok());
+        assert!(manifest(Some(ABI_VERSION), Some(HOST_VERSION))
+            .check_host_compat()
+            .is_ok());
+        assert!(manifest(Some(MAX_GUEST_ABI_VERSION + 1), None)
+            .check_host_compat()
+            .is_err());
+        assert!(manifest(None, Some("999.0.0")).check_host_compat().is_err());
+        assert!(manifest(None, Some("soon")).check_host_compat().is_err());
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert_eq!(compare_versions("0.10.0", "0.9.9"), Some(Ordering::Greater));
+        assert_eq!(compare_versions("v1.2", "1.2.0"), Some(Ordering::Equal));
+        assert_eq!(
+            compare_versions("1.2.3+build.7", "1.2.3"),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(compare_versions("1.x", "1.0"), None);
+        assert_eq!(compare_versions("1.2.3-", "1.2.3"), None);
+    }
+
+    #[test]
+    fn pre_releases_sort_below_their_release() {
+        assert_eq!(
+            compare_versions("1.2.3-beta.1", "1.2.3"),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            compare_versions("1.2.3", "1.2.3-rc.1"),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            compare_versions("1.2.4-alpha", "1.2.3"),
+            Some(Ordering::Greater)
+        );
+        // Semver's own precedence example, in ascending order.
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(
+                compare_versions(pair[0], pair[1]),
+                Some(Ordering::Less),
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn release_host_meets_its_own_pre_release() {
+        assert!(manifest(None, Some(&format!("{HOST_VERSION}-rc.1")))
+            .check_host_compat()
+            .is_ok());
+    }
+
+    #[test]
+    fn sync_request_url_advertises_host() {
+        let url = sync_request_url("https://sync.example/vst/sync", &ReleaseChannel::Stable);
+        assert_eq!(
+            url,
+            format!(
+                "https://sync.example/vst/sync?host_version={}&abi={}&channel=stable",
+                HOST_VERSION, MAX_GUEST_ABI_VERSION
+            )
+        );
+        let pinned = ReleaseChannel::Pinned("1.4.2".to_string());
+        assert!(sync_request_url("https://sync.example/vst/sync", &pinned)
+            .ends_with("&channel=pinned&version=1.4.2"));
+        // `+` would reach the server as a space.
+        let pinned = ReleaseChannel::parse("pinned:1.5.0-rc.1+build.7").expect("pinned version");
+        let url = sync_request_url("https://sync.example/vst/sync", &pinned);
+        assert!(url.ends_with("&version=1.5.0-rc.1%2Bbuild.7"), "{url}");
+        assert_eq!(percent_decode(&percent_encode("1.0 & co/é")), "1.0 & co/é");
+        let url = sync_request_url("https://sync.example/vst/sync?channel=beta", &pinned);
+        assert!(url.starts_with("https://sync.example/vst/sync?channel=beta&host_version="));
+        assert!(!url.contains("pinned"), "{url}");
+    }
+
+    #[test]
+    fn parses_release_channels() {
+        assert_eq!(ReleaseChannel::parse(" BETA "), Ok(ReleaseChannel::Beta));
+        assert_eq!(ReleaseChannel::parse("stable"), Ok(ReleaseChannel::Stable));
+        let pinned = ReleaseChannel::Pinned("2.1.0".to_string());
+        assert_eq!(ReleaseChannel::parse("pinned:2.1.0"), Ok(pinned.clone()));
+        assert_eq!(ReleaseChannel::parse("2.1.0"), Ok(pinned.clone()));
+        assert!(ReleaseChannel::parse("nightly").is_err());
+        assert!(ReleaseChannel::parse("pinned:").is_err());
+
+        let json = serde_json::to_string(&pinned).unwrap();
+        assert_eq!(json, r#""pinned:2.1.0""#);
+        assert_eq!(
+            serde_json::from_str::<ReleaseChannel>(&json).unwrap(),
+            pinned
+        );
+    }
+
+    fn temp_data_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tonelab_evergreen_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn versioned(version: &str) -> SyncManifest {
+        SyncManifest {
+            version: version.to_string(),
+            ..manifest(None, None)
+        }
+    }
+
+    #[test]
+    fn cache_keeps_recent_bundles_and_known_good() {
+        let cache = CacheManager::new(temp_data_dir("gc"));
+        cache.ensure().expect("cache dir");
+
+        let keys: Vec<String> = (0..5u8)
+            .map(|idx| {
+                let bytes = [idx; 8];
+                let key = bundle_key(&bytes);
+                cache
+                    .store_bundle(&key, &versioned(&idx.to_string()), &bytes)
+                    .expect("store bundle");
+                if idx == 0 {
+                    cache.mark_known_good(&key).expect("promote");
+                }
+                key
+            })
+            .collect();
+
+        let state = cache.read_state();
+        assert_eq!(
+            state.bundles,
+            vec![
+                keys[4].clone(),
+                keys[3].clone(),
+                keys[2].clone(),
+                keys[0].clone()
+            ]
+        );
+        assert!(!cache.bundle_dir(&keys[1]).exists());
+        assert!(cache.bundle_dir(&keys[0]).exists());
+        assert_eq!(cache.candidates(true)[0], keys[0]);
+
+        let (manifest, bytes) = cache.read_bundle(&keys[3]).expect("read bundle");
+        assert_eq!(manifest.version, "3");
+        assert_eq!(bytes, vec![3u8; 8]);
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn bad_bundles_are_skipped_and_lose_known_good() {
+        let cache = CacheManager::new(temp_data_dir("bad"));
+        cache.ensure().expect("cache dir");
+        let key = bundle_key(b"bundle");
+        cache
+            .store_bundle(&key, &versioned("1"), b"bundle")
+            .expect("store bundle");
+        cache.mark_known_good(&key).expect("promote");
+
+        cache.mark_bad(&key).expect("mark bad");
+        assert!(cache.is_bad(&key));
+        assert!(!cache.is_known_good(&key));
+        assert!(cache.candidates(false).is_empty());
+        assert!(!cache.bundle_dir(&key).exists());
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn legacy_single_bundle_cache_is_migrated() {
+        let cache = CacheManager::new(temp_data_dir("legacy"));
+        std::fs::create_dir_all(&cache.root).expect("cache dir");
+        std::fs::write(
+            cache.root.join(CACHE_MANIFEST_FILE),
+            serde_json::to_vec(&versioned("legacy")).unwrap(),
+        )
+        .expect("write legacy manifest");
+        std::fs::write(cache.root.join(CACHE_WASM_FILE), b"legacy wasm").expect("write wasm");
+
+        cache.ensure().expect("migrate");
+        let key = bundle_key(b"legacy wasm");
+        assert_eq!(cache.candidates(false), vec![key.clone()]);
+        assert_eq!(cache.read_bundle(&key).expect("read").0.version, "legacy");
+        assert!(!cache.root.join(CACHE_WASM_FILE).exists());
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    /// What the plugin's background health task does.
+    fn run_health_work(engine: &mut EvergreenEngine) {
+        let work = engine.take_health_work().expect("health work is queued");
+        let outcome = work.run();
+        engine.finish_health_work(outcome);
+    }
+
+    #[test]
+    fn runtime_failure_rolls_back_to_known_good_bundle() {
+        let data_dir = temp_data_dir("rollback");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+
+        let good = guest_wat("");
+        // Passes the 3-frame smoke test, then traps on real blocks.
+        let bad = good.replace(
+            r#"(func (export "process") (param i32 i32 i32))"#,
+            r#"(func (export "process") (param i32 i32 i32)
+                (if (i32.gt_s (local.get 2) (i32.const 3)) (then unreachable)))"#,
+        );
+        let good_key = bundle_key(good.as_bytes());
+        let bad_key = bundle_key(bad.as_bytes());
+        engine
+            .cache
+            .store_bundle(&good_key, &versioned("good"), good.as_bytes())
+            .expect("store good");
+        engine.cache.mark_known_good(&good_key).expect("promote");
+        engine
+            .cache
+            .store_bundle(&bad_key, &versioned("bad"), bad.as_bytes())
+            .expect("store bad");
+
+        let newest = engine.try_cached_sync(None).expect("newest bundle loads");
+        engine.activate_bundle(newest);
+        assert_eq!(engine.active_version(), Some("bad"));
+
+        let input = [0.1f32; 16];
+        let mut output = [0.0f32; 16];
+        assert!(engine
+            .process_interleaved_stereo(&input, &mut output)
+            .is_err());
+        // The audio thread only steps aside to passthrough; building native
+        // DSP and the cache are left to the health work.
+        assert_eq!(engine.backend(), EngineBackend::Null);
+        assert!(engine.has_runtime());
+        assert!(!engine.cache.is_bad(&bad_key));
+        assert!(engine.take_health_due());
+
+        run_health_work(&mut engine);
+        assert_eq!(engine.active_version(), Some("good"));
+        assert_eq!(engine.backend(), EngineBackend::Wasm);
+        assert!(engine.cache.is_bad(&bad_key));
+        assert!(engine
+            .process_interleaved_stereo(&input, &mut output)
+            .is_ok());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn deadline_misses_reload_the_bundle_until_they_add_up() {
+        let data_dir = temp_data_dir("deadline");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+
+        // Spins past the deadline whenever a block starts above full scale.
+        let slow = guest_wat("").replace(
+            r#"(func (export "process") (param i32 i32 i32))"#,
+            r#"(func (export "process") (param i32 i32 i32)
+                (if (f32.gt (f32.load (local.get 0)) (f32.const 1))
+                    (then (loop $spin (br $spin)))))"#,
+        );
+        let slow_key = bundle_key(slow.as_bytes());
+        engine
+            .cache
+            .store_bundle(&slow_key, &versioned("slow"), slow.as_bytes())
+            .expect("store slow");
+        let bundle = engine.try_cached_sync(None).expect("bundle loads");
+        engine.activate_bundle(bundle);
+
+        let mut output = [0.0f32; 16];
+        let mut miss = |engine: &mut EvergreenEngine| {
+            let error = engine
+                .process_interleaved_stereo(&[2.0; 16], &mut output)
+                .expect_err("spins past the deadline");
+            assert_eq!(error.kind(), "deadline");
+            assert_eq!(engine.backend(), EngineBackend::Null);
+            assert!(engine.take_health_due());
+            run_health_work(engine);
+        };
+        let clean = |engine: &mut EvergreenEngine, frames: usize| {
+            let input = vec![0.1f32; frames * 2];
+            let mut output = vec![0.0f32; frames * 2];
+            engine
+                .process_interleaved_stereo(&input, &mut output)
+                .expect("clean block");
+        };
+
+        for _ in 1..DEADLINE_STRIKES {
+            miss(&mut engine);
+            assert_eq!(engine.active_version(), Some("slow"));
+            assert_eq!(engine.backend(), EngineBackend::Wasm);
+            assert!(!engine.cache.is_bad(&slow_key));
+            clean(&mut engine, 16);
+        }
+
+        // A long enough clean stretch forgets the earlier misses.
+        let reset_frames = engine.sample_rate as usize * DEADLINE_STRIKES_RESET_SECS as usize;
+        for _ in 0..reset_frames.div_ceil(1024) {
+            clean(&mut engine, 1024);
+        }
+        miss(&mut engine);
+        assert_eq!(engine.active_version(), Some("slow"));
+        assert!(!engine.cache.is_bad(&slow_key));
+
+        for _ in 1..DEADLINE_STRIKES {
+            miss(&mut engine);
+        }
+        assert!(engine.cache.is_bad(&slow_key));
+        assert_eq!(engine.backend(), EngineBackend::Native);
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn poisoned_runtime_lock_is_a_host_error() {
+        let runtime = WasmRuntime::from_bytes(guest_wat("").as_bytes()).expect("guest loads");
+        std::thread::scope(|scope| {
+            let poisoner = scope.spawn(|| {
+                let _inner = runtime.inner.lock().unwrap();
+                panic!("poison the runtime lock");
+            });
+            assert!(poisoner.join().is_err());
+        });
+        let error = runtime
+            .process_interleaved_stereo(&[0.1; 4], &mut [0.0; 4])
+            .expect_err("lock is poisoned");
+        assert_eq!(error.kind(), "host");
+        assert!(!error.is_bundle_fault());
+    }
+
+    #[test]
+    fn guest_buffers_are_reused_across_calls() {
+        // `guest_wat` bump-allocates out of one 64 KiB page and never frees,
+        // so allocating per call would run off its memory within a few
+        // thousand frames.
+        let mut runtime = WasmRuntime::from_bytes(guest_wat("").as_bytes()).expect("guest loads");
+        for _ in 0..48_000 {
+            runtime.process_frame(0.1, -0.1).expect("process frame");
+        }
+        let mut output = [0.0f32; 1024];
+        for _ in 0..1_000 {
+            runtime
+                .process_interleaved_stereo(&[0.1; 1024], &mut output)
+                .expect("process block");
+        }
+        for _ in 0..10_000 {
+            runtime.sync_chain_json("[]").expect("chain applies");
+        }
+    }
+
+    #[test]
+    fn audio_thread_never_takes_the_cache_lock() {
+        let data_dir = temp_data_dir("audio-lock");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        // Passes single frames and the smoke test, then traps on real blocks.
+        let wasm = guest_wat("").replace(
+            r#"(func (export "process") (param i32 i32 i32))"#,
+            r#"(func (export "process") (param i32 i32 i32)
+                (if (i32.gt_s (local.get 2) (i32.const 3)) (then unreachable)))"#,
+        );
+        let key = bundle_key(wasm.as_bytes());
+        engine
+            .cache
+            .store_bundle(&key, &versioned("flaky"), wasm.as_bytes())
+            .expect("store bundle");
+        let bundle = engine.try_cached_sync(None).expect("bundle loads");
+        engine.activate_bundle(bundle);
+
+        // Another plugin instance or process is mid-sync.
+        let lock = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(engine.cache.root.join(CACHE_LOCK_FILE))
+            .expect("open cache lock");
+        lock.lock().expect("hold cache lock");
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let audio = std::thread::spawn(move || {
+            engine.clean_frames = Some(engine.sample_rate as u64 * KNOWN_GOOD_AFTER_SECS - 1);
+            engine
+                .process_frame(0.0, 0.0)
+                .expect("known-good milestone");
+            assert!(engine
+                .process_interleaved_stereo(&[0.1; 16], &mut [0.0; 16])
+                .is_err());
+            let _ = done.send(());
+            engine
+        });
+        finished
+            .recv_timeout(Duration::from_secs(10))
+            .expect("audio thread blocked on the cache lock");
+        let mut engine = audio.join().expect("audio thread");
+        assert_eq!(engine.backend(), EngineBackend::Null);
+        assert!(engine.take_health_due());
+
+        lock.unlock().expect("release cache lock");
+        run_health_work(&mut engine);
+        assert_eq!(engine.backend(), EngineBackend::Native);
+        assert!(engine.cache.is_bad(&key));
+        assert!(!engine.cache.is_known_good(&key));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn refused_input_does_not_roll_back() {
+        let data_dir = temp_data_dir("refused-input");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+
+        // Refuses every chain, like an engine whose chain validation fails.
+        let picky = guest_wat("").replace(
+            r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 0))"#,
+            r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 3))"#,
+        );
+        let key = bundle_key(picky.as_bytes());
+        engine
+            .cache
+            .store_bundle(&key, &versioned("picky"), picky.as_bytes())
+            .expect("store picky");
+        let bundle = engine.try_cached_sync(None).expect("bundle loads");
+        engine.activate_bundle(bundle);
+
+        let error = engine
+            .sync_chain_json("[]")
+            .expect_err("guest refuses the chain");
+        assert_eq!(
+            error,
+            EvergreenError::GuestStatus {
+                call: "set_chain_json",
+                status: 3,
+                message: None,
+            }
+        );
+        assert!(!error.is_bundle_fault() && !error.is_transient());
+
+        let error = engine
+            .process_interleaved_stereo(&[0.0; 4], &mut [0.0; 3])
+            .expect_err("mismatched buffers");
+        assert_eq!(error.kind(), "validation");
+        assert_eq!(engine.last_error(), Some(&error));
+        assert_eq!(engine.active_version(), Some("picky"));
+        assert!(engine.take_health_work().is_none());
+        assert!(!engine.cache.is_bad(&key));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn guest_status_carries_the_guest_message() {
+        // The message sits at 64, so `last_error` answers `64 << 32 | len`.
+        let explained = guest_wat(
+            r#"(data (i32.const 64) "Delay 'feedback' must be below 1.0")
+               (func (export "last_error") (result i64) (i64.const 274877906978))"#,
+        )
+        .replace(
+            r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 0))"#,
+            r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 3))"#,
+        );
+        let runtime = WasmRuntime::from_bytes(explained.as_bytes()).expect("guest loads");
+        assert!(runtime.abi().supports(CAP_LAST_ERROR));
+
+        let mut engine = EvergreenEngine::new(std::env::temp_dir());
+        engine.replace_engine(Box::new(runtime));
+        let error = engine
+            .sync_chain_json("[]")
+            .expect_err("guest refuses the chain");
+        assert_eq!(
+            error.to_string(),
+            "wasm set_chain_json returned status 3: Delay 'feedback' must be below 1.0"
+        );
+        assert_eq!(engine.last_error(), Some(&error));
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "kind": "guest_status",
+                "call": "set_chain_json",
+                "status": 3,
+                "message": "Delay 'feedback' must be below 1.0",
+            })
+        );
+    }
+
+    #[test]
+    fn chain_json_is_read_back_from_guest_memory() {
+        // The chain sits at 64, so `get_chain_json` answers `64 << 32 | len`.
+        let reporting = guest_wat(
+            r#"(data (i32.const 64) "{\"effects\":[],\"token\":null}")
+               (func (export "get_chain_json") (result i64) (i64.const 274877906971))"#,
+        );
+        let runtime = WasmRuntime::from_bytes(reporting.as_bytes()).expect("guest loads");
+        assert!(runtime.abi().supports(CAP_GET_CHAIN_JSON));
+        assert_eq!(
+            runtime.chain_json(),
+            Ok(Some(r#"{"effects":[],"token":null}"#.to_string()))
+        );
+
+        // Past the end of the single 64 KiB page.
+        let stray = guest_wat(
+            r#"(func (export "get_chain_json") (result i64) (i64.const 300647710720004))"#,
+        );
+        let runtime = WasmRuntime::from_bytes(stray.as_bytes()).expect("guest loads");
+        let error = runtime.chain_json().expect_err("pointer is out of bounds");
+        assert_eq!(error.kind(), "trap");
+
+        let silent = WasmRuntime::from_bytes(guest_wat("").as_bytes()).expect("guest loads");
+        assert_eq!(silent.chain_json(), Ok(None));
+    }
+
+    #[test]
+    fn cached_bundle_of_the_selected_channel_is_preferred() {
+        let data_dir = temp_data_dir("channel");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+
+        let stable = guest_wat("");
+        let beta = format!("{} ;; beta", stable);
+        let store = |wasm: &str, manifest: SyncManifest| {
+            engine
+                .cache
+                .store_bundle(&bundle_key(wasm.as_bytes()), &manifest, wasm.as_bytes())
+                .expect("store bundle");
+        };
+        // A manifest from a server without channels counts as stable.
+        store(&stable, versioned("1.0.0"));
+        store(
+            &beta,
+            SyncManifest {
+                channel: Some("beta".to_string()),
+                ..versioned("1.1.0-beta")
+            },
+        );
+
+        let loaded = |engine: &EvergreenEngine| {
+            let bundle = engine.try_cached_sync(None).expect("cached bundle");
+            (bundle.manifest.version, bundle.manifest.channel)
+        };
+        assert_eq!(engine.channel(), ReleaseChannel::Stable);
+        assert_eq!(loaded(&engine), ("1.0.0".to_string(), None));
+
+        engine
+            .set_user_channel(Some(ReleaseChannel::Beta))
+            .expect("save user channel");
+        assert_eq!(
+            EvergreenEngine::new(data_dir.clone()).channel(),
+            ReleaseChannel::Beta
+        );
+        let bundle = engine.try_cached_sync(None).expect("beta bundle");
+        engine.activate_bundle(bundle);
+        assert_eq!(engine.active_version(), Some("1.1.0-beta"));
+        assert_eq!(engine.active_channel(), Some("beta"));
+
+        // The project's choice wins over the user's.
+        engine.set_project_channel(Some(ReleaseChannel::Pinned("1.0.0".to_string())));
+        assert_eq!(loaded(&engine).0, "1.0.0");
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn instances_save_the_user_channel_concurrently() {
+        let data_dir = temp_data_dir("settings-race");
+        let channels = [ReleaseChannel::Stable, ReleaseChannel::Beta];
+        std::thread::scope(|scope| {
+            for channel in &channels {
+                let engine = EvergreenEngine::new(data_dir.clone());
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        engine
+                            .set_user_channel(Some(channel.clone()))
+                            .expect("save user channel");
+                    }
+                });
+            }
+        });
+        assert!(channels.contains(&EvergreenEngine::new(data_dir.clone()).channel()));
+        let leftovers = std::fs::read_dir(&data_dir)
+            .expect("data dir")
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn pinned_project_keeps_its_bundle_until_upgrade_is_accepted() {
+        let data_dir = temp_data_dir("pin");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+
+        let old = guest_wat("");
+        let new = format!("{} ;; new", old);
+        for (wasm, version) in [(&old, "1.0.0"), (&new, "1.1.0")] {
+            engine
+                .cache
+                .store_bundle(
+                    &bundle_key(wasm.as_bytes()),
+                    &versioned(version),
+                    wasm.as_bytes(),
+                )
+                .expect("store bundle");
+        }
+        engine.set_session_pin(Some(BundlePin {
+            version: "1.0.0".to_string(),
+            sha256: bundle_key(old.as_bytes()).to_ascii_uppercase(),
+        }));
+        engine.bootstrap().expect("bootstrap");
+        assert_eq!(engine.active_version(), Some("1.0.0"));
+        assert_eq!(engine.project_bundle(), engine.active_pin());
+
+        let newer = PreparedUpdate(
+            engine
+                .try_cached_sync(engine.active_bundle.clone().as_deref())
+                .expect("newer bundle"),
+        );
+        assert_eq!(newer.version(), "1.1.0");
+        assert!(engine.is_upgrade(&newer));
+        engine.offer_upgrade(newer);
+        assert_eq!(engine.pending_upgrade(), Some("1.1.0"));
+        assert_eq!(engine.active_version(), Some("1.0.0"));
+
+        assert!(engine.accept_upgrade().is_some());
+        assert_eq!(engine.active_version(), Some("1.1.0"));
+        assert_eq!(engine.pending_upgrade(), None);
+        assert_eq!(
+            engine.project_bundle().map(|pin| pin.sha256),
+            Some(bundle_key(new.as_bytes()))
+        );
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn clean_audio_promotes_bundle_to_known_good() {
+        let data_dir = temp_data_dir("promote");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        let wasm = guest_wat("");
+        let key = bundle_key(wasm.as_bytes());
+        engine
+            .cache
+            .store_bundle(&key, &versioned("fresh"), wasm.as_bytes())
+            .expect("store bundle");
+        let bundle = engine.try_cached_sync(None).expect("bundle loads");
+        engine.activate_bundle(bundle);
+        assert!(!engine.cache.is_known_good(&key));
+
+        engine.clean_frames = Some(engine.sample_rate as u64 * KNOWN_GOOD_AFTER_SECS - 1);
+        engine.process_frame(0.0, 0.0).expect("process");
+        assert_eq!(engine.clean_frames, None);
+        assert!(!engine.cache.is_known_good(&key));
+        assert!(engine.take_health_due());
+
+        run_health_work(&mut engine);
+        assert!(engine.cache.is_known_good(&key));
+        assert!(engine.take_health_work().is_none());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn read_bundle_rejects_manifest_that_does_not_match_wasm() {
+        let cache = CacheManager::new(temp_data_dir("sha"));
+        cache.ensure().expect("cache dir");
+        let key = bundle_key(b"wasm v1");
+        cache
+            .store_bundle(&key, &versioned("1"), b"wasm v1")
+            .expect("store bundle");
+        let (manifest, _) = cache.read_bundle(&key).expect("intact bundle reads");
+        assert_eq!(manifest.sha256.as_deref(), Some(key.as_str()));
+
+        let tampered = SyncManifest {
+            sha256: Some(bundle_key(b"wasm v2")),
+            ..manifest
+        };
+        write_atomic(
+            &cache.bundle_dir(&key).join(CACHE_MANIFEST_FILE),
+            &serde_json::to_vec(&tampered).unwrap(),
+        )
+        .expect("rewrite manifest");
+        let error = cache.read_bundle(&key).expect_err("mismatch detected");
+        assert!(
+            matches!(&error, EvergreenError::Cache(message) if message.contains("sha256")),
+            "{error:?}"
+        );
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn concurrent_instances_leave_a_consistent_cache() {
+        let root = temp_data_dir("concurrent");
+        CacheManager::new(root.clone()).ensure().expect("cache dir");
+
+        let writers: Vec<_> = (0..8u8)
+            .map(|idx| {
+                let root = root.clone();
+                std::thread::spawn(move || {
+                    let cache = CacheManager::new(root);
+                    let bytes = vec![idx; 4096];
+                    let key = bundle_key(&bytes);
+                    cache
+                        .store_bundle(&key, &versioned(&idx.to_string()), &bytes)
+                        .expect("store bundle");
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().expect("writer thread");
+        }
+
+        let cache = CacheManager::new(root.clone());
+        let bundles = cache.read_state().bundles;
+        assert_eq!(bundles.len(), CACHE_KEEP_BUNDLES);
+        for key in &bundles {
+            cache
+                .read_bundle(key)
+                .expect("every listed bundle is intact");
+        }
+        let leftovers = std::fs::read_dir(root.join(CACHE_BUNDLES_DIR))
+            .expect("bundles dir")
+            .count();
+        assert_eq!(leftovers, CACHE_KEEP_BUNDLES, "no stale or staging dirs");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn partial_downloads_take_turns() {
+        let root = temp_data_dir("partial");
+        let cache = CacheManager::new(root.clone());
+        let (entered, wait) = std::sync::mpsc::channel();
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let first = {
+            let root = root.clone();
+            std::thread::spawn(move || {
+                CacheManager::new(root).with_partial_download("aa", |partial| {
+                    std::fs::write(partial, b"half").expect("write partial");
+                    entered.send(()).expect("signal");
+                    released.recv().expect("release");
+                    Ok(())
+                })
+            })
+        };
+        wait.recv().expect("first download started");
+
+        let (done, finished) = std::sync::mpsc::channel();
+        let second = std::thread::spawn(move || {
+            let kept = cache.with_partial_download("bb", |_| Ok(()));
+            done.send(()).expect("signal");
+            kept
+        });
+        assert!(
+            finished.recv_timeout(Duration::from_millis(200)).is_err(),
+            "the second download waits for the first"
+        );
+        release.send(()).expect("release");
+        first.join().expect("first thread").expect("first download");
+        second
+            .join()
+            .expect("second thread")
+            .expect("second download");
+        assert!(
+            !root.join(CACHE_PARTIAL_DIR).join("aa.part").exists(),
+            "the other bundle's partial file is dropped once it is free"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn parses_local_sync_sources() {
+        assert_eq!(local_source_path("https://example.com/vst/sync"), None);
+        assert_eq!(local_source_path(""), None);
+        assert_eq!(
+            local_source_path("file:///opt/tonelab/My%20Bundles"),
+            Some(PathBuf::from("/opt/tonelab/My Bundles"))
+        );
+        assert_eq!(
+            local_source_path("file://localhost/opt/bundles?host_version=1"),
+            Some(PathBuf::from("/opt/bundles"))
+        );
+        assert_eq!(
+            local_source_path("file:///C:/Tonelab/bundles"),
+            Some(PathBuf::from("C:/Tonelab/bundles"))
+        );
+        assert_eq!(
+            local_source_path("/opt/bundles"),
+            Some(PathBuf::from("/opt/bundles"))
+        );
+        assert_eq!(
+            sync_request_url("file:///opt/bundles", &ReleaseChannel::Beta),
+            "file:///opt/bundles"
+        );
+    }
+
+    #[test]
+    fn local_directory_source_serves_and_watches_its_bundle() {
+        let data_dir = temp_data_dir("local");
+        let source = data_dir.join("source");
+        std::fs::create_dir_all(&source).expect("source dir");
+        let mut watcher = SourceWatcher::new(&source);
+        let first = guest_wat("");
+        std::fs::write(source.join(CACHE_WASM_FILE), &first).expect("write wasm");
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+
+        let engine = EvergreenEngine::new(data_dir.clone());
+        let updater = EvergreenUpdater {
+            cache: engine.cache.clone(),
+            sync_url: format!("file://{}", source.display()),
+            channel: ReleaseChannel::Stable,
+            pin: None,
+            current: None,
+        };
+        // Without a manifest the directory's wasm is an unsigned dev build.
+        let update = updater
+            .check()
+            .expect("local bundle prepares")
+            .expect("local bundle is an update");
+        assert_eq!(update.sha256(), bundle_key(first.as_bytes()));
+        assert!(update.version().starts_with("dev-"));
+
+        // A manifest next to it may name the wasm by relative path.
+        let second = guest_wat(r#"(func (export "unused"))"#);
+        std::fs::create_dir_all(source.join("build")).expect("build dir");
+        std::fs::write(source.join("build").join("engine.wasm"), &second).expect("write wasm");
+        let manifest = SyncManifest {
+            wasm_url: "build/engine.wasm".to_string(),
+            ..versioned("2.0.0")
+        };
+        std::fs::write(
+            source.join(CACHE_MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .expect("write manifest");
+        assert!(watcher.poll());
+        let update = updater
+            .check()
+            .expect("manifest bundle prepares")
+            .expect("manifest bundle is an update");
+        assert_eq!(update.version(), "2.0.0");
+        assert_eq!(update.sha256(), bundle_key(second.as_bytes()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn only_a_local_source_may_name_local_files() {
+        let data_dir = temp_data_dir("local-urls");
+        std::fs::create_dir_all(&data_dir).expect("data dir");
+        let secret = data_dir.join("secret.txt");
+        std::fs::write(&secret, "not for the server").expect("write secret");
+        let engine = EvergreenEngine::new(data_dir.clone());
+        let updater = |sync_url: String| EvergreenUpdater {
+            cache: engine.cache.clone(),
+            sync_url,
+            channel: ReleaseChannel::Stable,
+            pin: None,
+            current: None,
+        };
+
+        let remote = updater("https://sync.example/vst/sync".to_string());
+        for url in [
+            secret.display().to_string(),
+            format!("file://{}", secret.display()),
+            "secret.txt".to_string(),
+            "ftp://sync.example/engine.wasm".to_string(),
+        ] {
+            assert_eq!(remote.local_url(&url), None, "{url}");
+            let error = remote
+                .fetch(&url, 1024)
+                .expect_err("only http(s) is fetched");
+            assert_eq!(error.kind(), "validation", "{url}");
+        }
+
+        let local = updater(data_dir.display().to_string());
+        assert_eq!(
+            local.fetch("secret.txt", 1024),
+            Ok(b"not for the server".to_vec())
+        );
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_reads_stop_at_the_limit() {
+        // Reports a size of 0 but never ends.
+        let error = read_local_file(Path::new("/dev/zero"), 1024).expect_err("capped");
+        assert!(error.to_string().contains("exceeds 1024 bytes"), "{error}");
+    }
+
+    #[test]
+    fn only_digests_name_delta_bases() {
+        let key = bundle_key(b"wasm");
+        assert!(is_sha256_hex(&key));
+        assert!(!is_sha256_hex(&key.to_ascii_uppercase()));
+        assert!(!is_sha256_hex(&key[1..]));
+        assert!(!is_sha256_hex(&format!("{}0", key)));
+        assert!(!is_sha256_hex(&format!("../{}", &key[3..])));
+        assert!(!is_sha256_hex(""));
+    }
+
+    #[test]
+    fn delta_patches_cached_bundle_and_falls_back_to_full_download() {
+        let data_dir = temp_data_dir("delta");
+        let source = data_dir.join("source");
+        std::fs::create_dir_all(source.join("deltas")).expect("source dir");
+        let engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        let old = guest_wat("");
+        let old_key = bundle_key(old.as_bytes());
+        engine
+            .cache
+            .store_bundle(&old_key, &versioned("1.0.0"), old.as_bytes())
+            .expect("store old");
+
+        let new = guest_wat(r#"(func (export "unused"))"#);
+        let mut patch = Vec::new();
+        let mut encoder =
+            zstd::stream::write::Encoder::with_ref_prefix(&mut patch, 19, old.as_bytes())
+                .expect("encoder");
+        std::io::Write::write_all(&mut encoder, new.as_bytes()).expect("encode");
+        encoder.finish().expect("finish patch");
+        let patch_file = format!("deltas/{}.zst", old_key);
+        std::fs::write(source.join(&patch_file), &patch).expect("write patch");
+        let manifest = SyncManifest {
+            // Not on disk: only the patch can produce the bundle.
+            wasm_url: "missing/engine.wasm".to_string(),
+            sha256: Some(bundle_key(new.as_bytes())),
+            deltas: vec![WasmDelta {
+                from_sha256: old_key.clone(),
+                url: patch_file.clone(),
+                size: Some(patch.len() as u64),
+            }],
+            ..versioned("2.0.0")
+        };
+        std::fs::write(
+            source.join(CACHE_MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .expect("write manifest");
+
+        let updater = EvergreenUpdater {
+            cache: engine.cache.clone(),
+            sync_url: source.display().to_string(),
+            channel: ReleaseChannel::Stable,
+            pin: None,
+            current: Some(old_key.clone()),
+        };
+        let update = updater
+            .check()
+            .expect("patched bundle prepares")
+            .expect("patched bundle is an update");
+        assert_eq!(update.sha256(), bundle_key(new.as_bytes()));
+        assert!(engine
+            .cache
+            .read_manifest(update.sha256())
+            .unwrap()
+            .deltas
+            .is_empty());
+
+        // A patch that does not produce the signed hash is dropped for the
+        // full wasm.
+        let third = guest_wat(r#"(func (export "other"))"#);
+        std::fs::write(source.join(CACHE_WASM_FILE), &third).expect("write wasm");
+        let manifest = SyncManifest {
+            wasm_url: CACHE_WASM_FILE.to_string(),
+            sha256: Some(bundle_key(third.as_bytes())),
+            ..manifest
+        };
+        std::fs::write(
+            source.join(CACHE_MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .expect("write manifest");
+        let update = updater
+            .check()
+            .expect("full download prepares")
+            .expect("full download is an update");
+        assert_eq!(update.sha256(), bundle_key(third.as_bytes()));
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn prepared_update_hot_swaps_and_keeps_chain() {
+        let data_dir = temp_data_dir("update");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        let old = guest_wat("");
+        let old_key = bundle_key(old.as_bytes());
+        engine
+            .cache
+            .store_bundle(&old_key, &versioned("old"), old.as_bytes())
+            .expect("store old");
+        let cached = engine.try_cached_sync(None).expect("cached bundle");
+        engine.activate_bundle(cached);
+        engine.sync_chain_json("[]").expect("chain applies");
+
+        let updater = EvergreenUpdater {
+            cache: engine.cache.clone(),
+            sync_url: String::new(),
+            channel: ReleaseChannel::Stable,
+            pin: None,
+            current: engine.active_bundle.clone(),
+        };
+        assert!(updater
+            .prepare(versioned("old"), old.as_bytes())
+            .expect("same bundle")
+            .is_none());
+
+        let new = guest_wat(r#"(func (export "unused"))"#);
+        let update = updater
+            .prepare(versioned("new"), new.as_bytes())
+            .expect("new bundle prepares")
+            .expect("new bundle is an update");
+        assert_eq!(update.version(), "new");
+
+        let previous = engine.install_update(update);
+        assert_eq!(previous.backend(), EngineBackend::Wasm);
+        assert_eq!(engine.active_version(), Some("new"));
+        assert_eq!(engine.last_chain_json(), Some("[]"));
+        assert_eq!(
+            engine.cache.candidates(false)[0],
+            bundle_key(new.as_bytes()),
+            "update is cached for the next offline start"
+        );
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn compiled_modules_are_reused_and_invalidated() {
+        let cache = CacheManager::new(temp_data_dir("compiled"));
+        cache.ensure().expect("cache dir");
+        // Unique bytes, so no other test holds this module in the registry.
+        let wasm = guest_wat(r#"(func (export "compiled_cache_test"))"#);
+        let name = precompiled_name(shared_engine(), wasm.as_bytes());
+        let path = cache.compiled_dir().join(&name);
+
+        WasmRuntime::from_bytes_cached(wasm.as_bytes(), &cache).expect("first load compiles");
+        assert!(path.exists(), "compiled module is stored");
+        assert!(cache.read_precompiled(shared_engine(), &name).is_some());
+
+        std::fs::write(&path, b"not a module").expect("corrupt artifact");
+        assert!(cache.read_precompiled(shared_engine(), &name).is_none());
+        assert!(!path.exists(), "corrupt artifact is deleted");
+        let mut runtime =
+            WasmRuntime::from_bytes_cached(wasm.as_bytes(), &cache).expect("recompiles");
+        assert!(runtime.process_frame(0.1, 0.1).is_ok());
+        assert!(path.exists(), "artifact is rewritten");
+
+        let key = bundle_key(wasm.as_bytes());
+        cache
+            .store_bundle(&key, &versioned("1"), wasm.as_bytes())
+            .expect("store bundle");
+        assert!(path.exists(), "artifact of a cached bundle survives gc");
+        cache.mark_bad(&key).expect("mark bad");
+        assert!(!path.exists(), "artifact of an evicted bundle is collected");
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn instances_share_one_module_but_not_state() {
+        let wasm = guest_wat(r#"(func (export "shared_module_test"))"#);
+        let key = bundle_key(wasm.as_bytes());
+        let mut first = WasmRuntime::from_bytes(wasm.as_bytes()).expect("first instance");
+        let second = WasmRuntime::from_bytes(wasm.as_bytes()).expect("second instance");
+        assert!(Arc::ptr_eq(&first._module, &second._module));
+
+        // Allocations in one instance's linear memory don't move the other's.
+        first.process_frame(0.1, 0.1).expect("process");
+        let probe = |runtime: &WasmRuntime| {
+            let mut inner = runtime.inner.lock().unwrap();
+            let alloc = inner.alloc_samples.clone();
+            alloc.call(&mut inner.store, 1).unwrap()
+        };
+        assert_ne!(probe(&first), probe(&second));
+
+        drop(first);
+        drop(second);
+        let registry = module_registry().lock().unwrap();
+        assert!(registry.get(&key).and_then(Weak::upgrade).is_none());
+    }
+
+    const NOOP_PROCESS: &str = r#"(func (export "process") (param i32 i32 i32))"#;
+
+    fn sandbox_limits(max_memory_bytes: usize, process_deadline_ms: u64) -> SandboxLimits {
+        SandboxLimits {
+            max_memory_bytes,
+            process_deadline: Duration::from_millis(process_deadline_ms),
+            ..SandboxLimits::default()
+        }
+    }
+
+    #[test]
+    fn runaway_process_is_interrupted_and_bypassed() {
+        let wasm = guest_wat("").replace(
+            NOOP_PROCESS,
+            r#"(func (export "process") (param i32 i32 i32) (loop $spin (br $spin)))"#,
+        );
+        let runtime = WasmRuntime::with_limits(wasm.as_bytes(), sandbox_limits(1 << 20, 20))
+            .expect("spinning guest loads");
+
+        let input = [0.25f32, -0.25, 0.5, -0.5];
+        let mut output = [0.0f32; 4];
+        let started = std::time::Instant::now();
+        let error = runtime
+            .process_interleaved_stereo(&input, &mut output)
+            .expect_err("infinite loop must not hang the caller");
+        assert!(
+            matches!(&error, EvergreenError::Deadline(message) if message.contains("deadline")),
+            "{error:?}"
+        );
+        assert!(!error.is_bundle_fault());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(output, input);
+
+        // The guest may have been stopped mid-update, so it is not entered again.
+        assert_eq!(
+            runtime
+                .set_sample_rate(48_000.0)
+                .map_err(|error| error.kind()),
+            Err("deadline")
+        );
+    }
+
+    #[test]
+    fn failing_last_error_is_reported_like_any_call() {
+        let refusing = |last_error: &str| {
+            guest_wat(last_error).replace(
+                r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 0))"#,
+                r#"(func (export "set_chain_json") (param i32 i32) (result i32) (i32.const 3))"#,
+            )
+        };
+        let limits = SandboxLimits {
+            control_deadline: Duration::from_millis(20),
+            ..SandboxLimits::default()
+        };
+
+        let trapping = refusing(r#"(func (export "last_error") (result i64) unreachable)"#);
+        let runtime =
+            WasmRuntime::with_limits(trapping.as_bytes(), limits).expect("trapping guest loads");
+        let error = runtime.set_chain_json("[]").expect_err("guest refuses");
+        assert!(
+            matches!(&error, EvergreenError::Trap(message) if message.contains("last_error")),
+            "{error:?}"
+        );
+
+        let spinning = refusing(
+            r#"(func (export "last_error") (result i64) (loop $spin (br $spin)) (i64.const 0))"#,
+        );
+        let runtime =
+            WasmRuntime::with_limits(spinning.as_bytes(), limits).expect("spinning guest loads");
+        let started = std::time::Instant::now();
+        let error = runtime.set_chain_json("[]").expect_err("guest refuses");
+        assert_eq!(error.kind(), "deadline", "{error:?}");
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            runtime
+                .set_sample_rate(48_000.0)
+                .map_err(|error| error.kind()),
+            Err("deadline")
+        );
+    }
+
+    #[test]
+    fn memory_bomb_is_capped() {
+        let cap = 2 << 20;
+        // Grows until refused, then traps like Rust's allocation error handler.
+        let wasm = guest_wat("").replace(
+            NOOP_PROCESS,
+            r#"(func (export "process") (param i32 i32 i32)
+                (loop $grow
+                    (br_if $grow (i32.ne (memory.grow (i32.const 1)) (i32.const -1))))
+                unreachable)"#,
+        );
+        let runtime = WasmRuntime::with_limits(wasm.as_bytes(), sandbox_limits(cap, 1_000))
+            .expect("bomb loads");
+
+        let input = [0.1f32; 4];
+        let mut output = [0.0f32; 4];
+        assert!(runtime
+            .process_interleaved_stereo(&input, &mut output)
+            .is_err());
+        assert_eq!(output, input);
+        let inner = runtime.inner.lock().unwrap();
+        assert!(inner.memory.data_size(&inner.store) <= cap);
+        drop(inner);
+
+        let oversized = guest_wat(r#"(func (export "memory_bomb_oversized"))"#).replace(
+            r#"(memory (export "memory") 1)"#,
+            r#"(memory (export "memory") 64)"#,
+        );
+        assert!(
+            WasmRuntime::with_limits(oversized.as_bytes(), sandbox_limits(cap, 1_000)).is_err()
+        );
+    }
+
+    #[test]
+    fn manifest_signature_covers_fields_and_wasm() {
+        let signer = TestKey::generate();
+        let trust = TrustStore::from_roots([signer.public_b64().as_str()]).expect("root");
+        let wasm = b"signed wasm";
+        let mut signed = SyncManifest {
+            sha256: Some(bundle_key(wasm)),
+            key_id: Some(signer.id()),
+            ..versioned("2.0.0")
+        };
+        signed.manifest_signature =
+            Some(signer.sign_b64(&signed.signed_payload().expect("payload")));
+        assert_eq!(verify_bundle(&signed, wasm, &trust, false), Ok(()));
+
+        let tampered = [
+            SyncManifest {
+                version: "2.0.1".to_string(),
+                ..signed.clone()
+            },
+            SyncManifest {
+                wasm_url: "https://evil.invalid/engine.wasm".to_string(),
+                ..signed.clone()
+            },
+            SyncManifest {
+                assets: SyncAssets {
+                    web_ui_url: "https://evil.invalid/".to_string(),
+                    ..SyncAssets::default()
+                },
+                ..signed.clone()
+            },
+        ];
+        for manifest in &tampered {
+            assert!(verify_bundle(manifest, wasm, &trust, false).is_err());
+        }
+        let error = verify_bundle(&signed, b"other wasm", &trust, false).unwrap_err();
+        assert!(
+            matches!(&error, EvergreenError::Signature(message) if message.contains("sha256")),
+            "{error:?}"
+        );
+
+        // Servers that only sign the wasm are still accepted.
+        let legacy = SyncManifest {
+            signature: signer.sign_b64(wasm),
+            ..versioned("1.0.0")
+        };
+        assert_eq!(verify_bundle(&legacy, wasm, &trust, false), Ok(()));
+        assert!(verify_bundle(&versioned("1.0.0"), wasm, &trust, false).is_err());
+        assert_eq!(
+            verify_bundle(&versioned("1.0.0"), wasm, &trust, true),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn cache_remembers_key_rotation() {
+        let cache = CacheManager::new(temp_data_dir("trust"));
+        cache.ensure().expect("cache dir");
+        let old = TestKey::generate();
+        let new = TestKey::generate();
+        let roots = || TrustStore::from_roots([old.public_b64().as_str()]).expect("root");
+
+        let statements = [
+            old.statement(KeyAction::Trust, &new),
+            new.statement(KeyAction::Revoke, &old),
+        ];
+        let trust = cache
+            .learn_key_statements(roots(), &statements)
+            .expect("learn statements");
+        assert!(trust.is_trusted(&new.id()));
+
+        // A later sync without the statements (or a restart) keeps the rotation.
+        let trust = cache.learn_key_statements(roots(), &[]).expect("reload");
+        assert!(trust.is_trusted(&new.id()));
+        assert!(!trust.is_trusted(&old.id()));
+        assert_eq!(cache.read_state().key_statements.len(), 2);
+
+        // Replaying the same statements does not duplicate them.
+        cache
+            .learn_key_statements(roots(), &statements)
+            .expect("learn again");
+        assert_eq!(cache.read_state().key_statements.len(), 2);
+        let _ = std::fs::remove_dir_all(&cache.root);
+    }
+
+    #[test]
+    fn pinned_assets_are_served_only_when_verified() {
+        let data_dir = temp_data_dir("assets");
+        let mut engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        let effects = br#"{"effects":[{"id":"Delay","icon_url":"./icons/delay.svg"}]}"#;
+        let effects_url = "https://cdn.example/assets/effects_manifest.json";
+        engine.active_manifest = Some(SyncManifest {
+            assets: SyncAssets {
+                effects_url: effects_url.to_string(),
+                effects_sha256: Some(sha256_hex(effects)),
+                ..SyncAssets::default()
+            },
+            ..versioned("1")
+        });
+
+        // Pinned but nothing verified yet: the UI gets nothing to load.
+        let server = engine.asset_server();
+        assert_eq!(server.url(PinnedAsset::Effects), Some(String::new()));
+        assert_eq!(server.url(PinnedAsset::Icons), None);
+        assert!(server.respond("/effects_manifest.json").is_none());
+
+        let tampered = br#"{"effects":[{"id":"Delay","icon_url":"https://evil.invalid/x.svg"}]}"#;
+        let error = engine
+            .cache
+            .store_asset(
+                PinnedAsset::Effects,
+                effects_url,
+                &sha256_hex(effects),
+                tampered,
+            )
+            .expect_err("tampered asset refused");
+        assert!(
+            matches!(&error, EvergreenError::Signature(message) if message.contains("sha256")),
+            "{error:?}"
+        );
+        assert!(engine
+            .asset_server()
+            .respond("effects_manifest.json")
+            .is_none());
+
+        engine
+            .cache
+            .store_asset(
+                PinnedAsset::Effects,
+                effects_url,
+                &sha256_hex(effects),
+                effects,
+            )
+            .expect("verified asset stored");
+        let server = engine.asset_server();
+        assert_eq!(
+            server.url(PinnedAsset::Effects),
+            Some(asset_protocol_url("effects_manifest.json"))
+        );
+        let (body, content_type) = server
+            .respond("/effects_manifest.json")
+            .expect("served from cache");
+        assert_eq!(content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body["effects"][0]["icon_url"],
+            "https://cdn.example/assets/icons/delay.svg"
+        );
+
+        // A cached file modified on disk is not served.
+        std::fs::write(engine.cache.asset_path(&sha256_hex(effects)), tampered).unwrap();
+        assert!(server.respond("/effects_manifest.json").is_none());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn cached_ui_page_is_served_offline() {
+        let data_dir = temp_data_dir("ui");
+        let engine = EvergreenEngine::new(data_dir.clone());
+        engine.cache.ensure().expect("cache dir");
+        let ui_url = "https://app.example/";
+        let page = b"<!doctype html><html><head><style>body{}</style></head>\
+            <body><script type=\"module\">start()</script></body></html>";
+
+        let server = engine.asset_server();
+        assert_eq!(server.ui_url(ui_url), None);
+        assert!(server.respond("/").is_none());
+
+        engine.cache.store_ui(ui_url, page).expect("store UI");
+        assert_eq!(
+            server.ui_url(ui_url),
+            Some(asset_protocol_url("index.html"))
+        );
+        // A different UI URL is not answered with another page's copy.
+        assert_eq!(server.ui_url("https://beta.app.example/"), None);
+        let (body, content_type) = server.respond("/index.html").expect("cached page");
+        assert_eq!(body, page);
+        assert!(content_type.starts_with("text/html"));
+
+        std::fs::write(engine.cache.asset_path(&sha256_hex(page)), b"<html>x").unwrap();
+        assert!(server.respond("/").is_none());
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[test]
+    fn only_self_contained_ui_pages_are_cached() {
+        assert!(is_self_contained_html(
+            b"<!DOCTYPE html><html><link rel=\"icon\" href=\"data:,\"><script>1</script></html>"
+        ));
+        // The Vite dev server page loads its modules from the server.
+        assert!(!is_self_contained_html(
+            b"<html><script type=\"module\" src=\"/src/main.jsx\"></script></html>"
+        ));
+        assert!(!is_self_contained_html(
+            b"<html><LINK REL=\"stylesheet\" HREF=\"/assets/index.css\"></html>"
+        ));
+        assert!(!is_self_contained_html(b"{\"error\":\"not found\"}"));
+    }
+
+    #[test]
+    fn resolves_relative_asset_urls() {
+        let base = "https://cdn.example/v1/assets/effects_manifest.json?x=1";
+        assert_eq!(
+            resolve_url(base, "./icons/a.svg"),
+            "https://cdn.example/v1/assets/icons/a.svg"
+        );
+        assert_eq!(
+            resolve_url(base, "../b.svg"),
+            "https://cdn.example/v1/b.svg"
+        );
+        assert_eq!(resolve_url(base, "/c.svg"), "https://cdn.example/c.svg");
+        assert_eq!(
+            resolve_url(base, "//other.example/d.svg"),
+            "https://other.example/d.svg"
+        );
+        assert_eq!(
+            resolve_url(base, "https://other.example/e.svg"),
+            "https://other.example/e.svg"
+        );
+        assert_eq!(
+            resolve_url("http://localhost:8080", "icons/f.svg"),
+            "http://localhost:8080/icons/f.svg"
+        );
+    }
 }
@@ -3,13 +3,15 @@
 use nih_plug::prelude::*;
 use raw_window_handle::{HandleError, HasWindowHandle, RawWindowHandle, WindowHandle};
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashSet;
+use std::rc::Rc;
 use std::sync::{
     atomic::{AtomicBool, AtomicU32, Ordering},
-    Arc, Mutex,
+    Arc, Mutex, Weak,
 };
 use wry::{
-    http::{Request, Uri},
+    http::{Request, Response, Uri},
     WebView, WebViewBuilder,
 };
 
@@ -74,8 +76,16 @@ impl<'a> HasWindowHandle for ViewWrapper<'a> {
 }
 
 pub mod device;
+mod download;
 pub mod evergreen;
-use evergreen::EvergreenEngine;
+pub mod render;
+pub mod tone_engine;
+pub mod trust;
+use evergreen::{
+    AssetServer, BundlePin, EvergreenEngine, EvergreenError, PinnedAsset, ReleaseChannel,
+    SourceWatcher, ASSET_PROTOCOL, WATCH_INTERVAL,
+};
+use tone_engine::EngineBackend;
 
 const PLUGIN_VENDOR_URL: &str = match option_env!("TONELAB_VENDOR_URL") {
     Some(value) => value,
@@ -323,6 +333,24 @@ pub struct TonelabPlugin {
     params: Arc<TonelabParams>,
     evergreen_engine: Arc<Mutex<EvergreenEngine>>,
     sample_rate: f32,
+    /// Cleared by the first `process` call, which queues one update check per
+    /// instance; only the process context can hand work to the background thread.
+    update_check_pending: bool,
+    /// Set once the dev watch thread for a local bundle source is running.
+    source_watch_started: bool,
+    /// Interleaved stereo scratch for handing whole blocks to the engine, sized
+    /// in `initialize` so `process` doesn't allocate.
+    block_input: Vec<f32>,
+    block_output: Vec<f32>,
+}
+
+/// Work run on nih_plug's background thread.
+pub enum TonelabTask {
+    /// Fetch the latest Evergreen bundle and hot-swap to it once verified.
+    CheckForEvergreenUpdate,
+    /// Record the running bundle's health in the cache, and roll back to the
+    /// next cached bundle after a fault.
+    RecordEvergreenHealth,
 }
 
 const EDITOR_WIDTH: u32 = 800;
@@ -332,6 +360,21 @@ const EDITOR_HEIGHT: u32 = 600;
 struct TonelabParams {
     #[id = "gain"]
     pub gain: FloatParam,
+
+    /// Last chain applied from the UI. Restored into the engine on load so the
+    /// project sounds the same even when the UI can't be reached.
+    #[persist = "chain-json"]
+    pub chain_json: Mutex<String>,
+
+    /// Evergreen release channel chosen for this project; empty to follow the
+    /// user's channel.
+    #[persist = "evergreen-channel"]
+    pub evergreen_channel: Mutex<String>,
+
+    /// Evergreen bundle the project was last saved with. Reopening loads it
+    /// again; newer bundles wait for the user to accept them.
+    #[persist = "evergreen-bundle"]
+    pub evergreen_bundle: Mutex<Option<BundlePin>>,
 }
 
 impl Default for TonelabParams {
@@ -342,6 +385,9 @@ impl Default for TonelabParams {
                 1.0,
                 FloatRange::Linear { min: 0.0, max: 2.0 },
             ),
+            chain_json: Mutex::new(String::new()),
+            evergreen_channel: Mutex::new(String::new()),
+            evergreen_bundle: Mutex::new(None),
         }
     }
 }
@@ -352,6 +398,10 @@ impl Default for TonelabPlugin {
             params: Arc::new(TonelabParams::default()),
             evergreen_engine: Arc::new(Mutex::new(EvergreenEngine::new(get_data_dir()))),
             sample_rate: 44100.0,
+            update_check_pending: true,
+            source_watch_started: false,
+            block_input: Vec::new(),
+            block_output: Vec::new(),
         }
     }
 }
@@ -362,6 +412,193 @@ impl TonelabPlugin {
     }
 }
 
+/// Downloads, verifies and compiles the latest bundle without holding the
+/// engine lock, then takes it only for the swap itself. A project pinned to
+/// another bundle gets the update offered instead of installed.
+fn check_for_evergreen_update(evergreen_engine: &Mutex<EvergreenEngine>, params: &TonelabParams) {
+    let Some(mut updater) = evergreen_engine
+        .lock()
+        .ok()
+        .and_then(|engine| engine.updater())
+    else {
+        return;
+    };
+
+    loop {
+        match updater.check() {
+            Ok(Some(update)) => {
+                let version = update.version().to_string();
+                let Ok(mut engine) = evergreen_engine.lock() else {
+                    log_to_file("Evergreen update: failed to lock evergreen engine");
+                    return;
+                };
+                if engine.is_upgrade(&update) {
+                    engine.offer_upgrade(update);
+                    log_to_file(&format!(
+                        "Evergreen bundle {} available; project stays on its saved bundle until the upgrade is accepted",
+                        version
+                    ));
+                } else {
+                    let restored = engine.session_pin().is_some();
+                    let previous = engine.install_update(update);
+                    params.remember_bundle(&engine);
+                    let next = engine.updater();
+                    drop(engine);
+                    drop(previous);
+                    log_to_file(&format!("Evergreen bundle updated to {}", version));
+                    // Restoring the project's bundle says nothing about newer
+                    // ones; check the channel again so one can be offered.
+                    if let Some(next) = next.filter(|_| restored) {
+                        updater = next;
+                        continue;
+                    }
+                }
+            }
+            Ok(None) => log_to_file("Evergreen bundle is up to date"),
+            Err(error) => log_to_file(&format!(
+                "Evergreen update check failed ({}), keeping current engine: {}",
+                error.kind(),
+                error
+            )),
+        }
+        break;
+    }
+
+    // The editor opens the cached UI page; the network copy only refreshes it.
+    let ui_url = match evergreen_engine.lock() {
+        Ok(engine) => resolve_evergreen_ui_url(engine.web_ui_url()),
+        Err(_) => return,
+    };
+    match updater.refresh_ui(&ui_url) {
+        Ok(true) => log_to_file(&format!("Evergreen UI cached from {}", ui_url)),
+        Ok(false) => {}
+        Err(error) => log_to_file(&format!(
+            "Evergreen UI refresh failed, keeping cached copy: {}",
+            error
+        )),
+    }
+}
+
+/// Does the cache work the audio thread deferred (see
+/// `EvergreenEngine::take_health_work`) without holding the engine lock, then
+/// swaps in the fallback bundle after a fault.
+fn record_evergreen_health(evergreen_engine: &Mutex<EvergreenEngine>) {
+    let Some(work) = evergreen_engine
+        .lock()
+        .ok()
+        .and_then(|mut engine| engine.take_health_work())
+    else {
+        return;
+    };
+
+    let outcome = work.run();
+    let Ok(mut engine) = evergreen_engine.lock() else {
+        log_to_file("Evergreen health: failed to lock evergreen engine");
+        return;
+    };
+    let before = engine.last_error().cloned();
+    let previous = engine.finish_health_work(outcome);
+    if let Some(error) = engine
+        .last_error()
+        .filter(|error| Some(*error) != before.as_ref())
+    {
+        log_to_file(&format!("Evergreen health ({}): {}", error.kind(), error));
+    }
+    drop(engine);
+    drop(previous);
+}
+
+/// Dev watch mode: re-runs the update check whenever the local bundle source
+/// changes on disk, until the plugin is dropped. A rebuilt bundle replaces
+/// the running one even when the project is pinned to another.
+fn spawn_evergreen_source_watcher(
+    mut watcher: SourceWatcher,
+    evergreen_engine: Weak<Mutex<EvergreenEngine>>,
+    params: Weak<TonelabParams>,
+) {
+    let result = std::thread::Builder::new()
+        .name("tonelab-evergreen-watch".to_string())
+        .spawn(move || loop {
+            std::thread::sleep(WATCH_INTERVAL);
+            let (Some(evergreen_engine), Some(params)) =
+                (evergreen_engine.upgrade(), params.upgrade())
+            else {
+                return;
+            };
+            if !watcher.poll() {
+                continue;
+            }
+            log_to_file("Evergreen local bundle source changed, reloading");
+            if let Ok(mut engine) = evergreen_engine.lock() {
+                engine.set_session_pin(None);
+            }
+            check_for_evergreen_update(&evergreen_engine, &params);
+        });
+    if let Err(error) = result {
+        log_to_file(&format!(
+            "Failed to start Evergreen watch thread: {}",
+            error
+        ));
+    }
+}
+
+/// Applies a release channel chosen in the UI for `scope` (`project` or
+/// `user`). An empty channel clears that scope's choice.
+fn set_evergreen_channel(
+    evergreen_engine: &Mutex<EvergreenEngine>,
+    params: &TonelabParams,
+    scope: &str,
+    channel: &str,
+) -> Result<(), String> {
+    let channel = match channel.trim() {
+        "" => None,
+        value => Some(ReleaseChannel::parse(value)?),
+    };
+    let mut engine = evergreen_engine
+        .lock()
+        .map_err(|_| "failed to lock evergreen engine".to_string())?;
+    match scope {
+        "project" => {
+            if let Ok(mut saved) = params.evergreen_channel.lock() {
+                *saved = channel
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default();
+            }
+            engine.set_project_channel(channel);
+            // Picking a channel for the project is consent to leave its
+            // saved bundle.
+            engine.set_session_pin(None);
+            Ok(())
+        }
+        "user" => Ok(engine.set_user_channel(channel)?),
+        other => Err(format!("unknown channel scope '{}'", other)),
+    }
+}
+
+impl TonelabParams {
+    fn remember_bundle(&self, engine: &EvergreenEngine) {
+        if let (Some(bundle), Ok(mut saved)) =
+            (engine.project_bundle(), self.evergreen_bundle.lock())
+        {
+            *saved = Some(bundle);
+        }
+    }
+
+    /// Saves the chain last applied, param changes included, so the project
+    /// restores the values that were running. This runs on every IPC change,
+    /// so it uses the host's copy: reading the chain back from a wasm engine
+    /// holds the engine lock through a guest call, and the audio thread
+    /// outputs dry blocks while it can't take that lock.
+    fn remember_chain(&self, engine: &EvergreenEngine) {
+        if let (Some(chain_json), Ok(mut saved)) =
+            (engine.last_chain_json(), self.chain_json.lock())
+        {
+            *saved = chain_json.to_string();
+        }
+    }
+}
+
 impl Vst3Plugin for TonelabPlugin {
     const VST3_CLASS_ID: [u8; 16] = *b"TonelabAudioFx03";
     const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] =
@@ -388,25 +625,43 @@ impl Plugin for TonelabPlugin {
     const SAMPLE_ACCURATE_AUTOMATION: bool = true;
 
     type SysExMessage = ();
-    type BackgroundTask = ();
+    type BackgroundTask = TonelabTask;
 
     fn params(&self) -> Arc<dyn Params> {
         self.params.clone()
     }
 
-    fn editor(&mut self, _async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
-        let (evergreen_web_ui_url, evergreen_icons_url, evergreen_effects_url) = self
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let evergreen_engine = self.evergreen_engine.clone();
+        let params = self.params.clone();
+        Box::new(move |task| match task {
+            TonelabTask::CheckForEvergreenUpdate => {
+                check_for_evergreen_update(&evergreen_engine, &params)
+            }
+            TonelabTask::RecordEvergreenHealth => record_evergreen_health(&evergreen_engine),
+        })
+    }
+
+    fn editor(&mut self, async_executor: AsyncExecutor<Self>) -> Option<Box<dyn Editor>> {
+        let (evergreen_web_ui_url, evergreen_icons_url, evergreen_effects_url, asset_server) = self
             .evergreen_engine
             .lock()
             .ok()
             .map(|engine| {
+                // Pinned assets are only ever loaded from the verified cache.
+                let asset_server = engine.asset_server();
                 (
                     engine.web_ui_url().map(|value| value.to_string()),
-                    engine.icons_url().map(|value| value.to_string()),
-                    engine.effects_url().map(|value| value.to_string()),
+                    asset_server
+                        .url(PinnedAsset::Icons)
+                        .or_else(|| engine.icons_url().map(|value| value.to_string())),
+                    asset_server
+                        .url(PinnedAsset::Effects)
+                        .or_else(|| engine.effects_url().map(|value| value.to_string())),
+                    Some(asset_server),
                 )
             })
-            .unwrap_or((None, None, None));
+            .unwrap_or((None, None, None, None));
 
         Some(Box::new(TonelabEditor {
             params: self.params.clone(),
@@ -414,6 +669,8 @@ impl Plugin for TonelabPlugin {
             evergreen_web_ui_url,
             evergreen_icons_url,
             evergreen_effects_url,
+            asset_server,
+            async_executor,
             scale_factor_bits: AtomicU32::new(1.0f32.to_bits()),
             is_open: Arc::new(AtomicBool::new(false)),
         }))
@@ -426,18 +683,85 @@ impl Plugin for TonelabPlugin {
         _context: &mut impl InitContext<Self>,
     ) -> bool {
         self.sample_rate = buffer_config.sample_rate;
+        let block_len = buffer_config.max_buffer_size as usize * 2;
+        self.block_input.resize(block_len, 0.0);
+        self.block_output.resize(block_len, 0.0);
 
         if let Ok(mut evergreen_engine) = self.evergreen_engine.lock() {
             evergreen_engine.set_sample_rate(self.sample_rate);
 
+            let project_channel = self
+                .params
+                .evergreen_channel
+                .lock()
+                .map(|channel| channel.trim().to_string())
+                .unwrap_or_default();
+            let project_channel = match project_channel.as_str() {
+                "" => None,
+                value => ReleaseChannel::parse(value)
+                    .map_err(|error| {
+                        log_to_file(&format!("Ignoring saved Evergreen channel: {}", error))
+                    })
+                    .ok(),
+            };
+            evergreen_engine.set_project_channel(project_channel);
+            let saved_bundle = self
+                .params
+                .evergreen_bundle
+                .lock()
+                .ok()
+                .and_then(|bundle| bundle.clone());
+            evergreen_engine.set_session_pin(saved_bundle);
+
             if let Err(error) = evergreen_engine.bootstrap() {
                 log_to_file(&format!("Evergreen bootstrap failed: {}", error));
-            } else {
-                if let Some(version) = evergreen_engine.active_version() {
-                    log_to_file(&format!("Evergreen bundle active: {}", version));
+            } else if let Some(version) = evergreen_engine.active_version() {
+                log_to_file(&format!(
+                    "Evergreen bundle active (cached): {} ({}), following {}",
+                    version,
+                    evergreen_engine.active_channel().unwrap_or("stable"),
+                    evergreen_engine.channel()
+                ));
+            }
+            log_to_file(&format!(
+                "DSP backend active: {}",
+                evergreen_engine.backend().as_str()
+            ));
+            self.params.remember_bundle(&evergreen_engine);
+
+            if !self.source_watch_started {
+                if let Some(watcher) = evergreen_engine.source_watcher() {
+                    spawn_evergreen_source_watcher(
+                        watcher,
+                        Arc::downgrade(&self.evergreen_engine),
+                        Arc::downgrade(&self.params),
+                    );
+                    self.source_watch_started = true;
                 }
-                if let Some(error) = evergreen_engine.last_error() {
-                    log_to_file(error);
+            }
+
+            let saved_chain = self
+                .params
+                .chain_json
+                .lock()
+                .map(|chain_json| chain_json.clone())
+                .unwrap_or_default();
+            if !saved_chain.trim().is_empty()
+                && evergreen_engine.last_chain_json() != Some(saved_chain.as_str())
+            {
+                match evergreen_engine.sync_chain_json(&saved_chain) {
+                    Ok(()) => {
+                        if let Ok(Some(chain_json)) = evergreen_engine.chain_json() {
+                            log_to_file(&format!(
+                                "Restored chain on {} engine: {}",
+                                evergreen_engine.backend().as_str(),
+                                chain_json
+                            ));
+                        }
+                    }
+                    Err(error) => {
+                        log_to_file(&format!("Failed to restore saved chain: {}", error));
+                    }
                 }
             }
         }
@@ -455,70 +779,73 @@ impl Plugin for TonelabPlugin {
         &mut self,
         buffer: &mut Buffer,
         _aux: &mut AuxiliaryBuffers,
-        _context: &mut impl ProcessContext<Self>,
+        context: &mut impl ProcessContext<Self>,
     ) -> ProcessStatus {
+        if std::mem::take(&mut self.update_check_pending) {
+            context.execute_background(TonelabTask::CheckForEvergreenUpdate);
+        }
+
         let mut evergreen_guard = self.evergreen_engine.try_lock().ok();
-        let mut evergreen_ready = evergreen_guard
+        let evergreen_ready = evergreen_guard
             .as_ref()
             .map(|engine| engine.has_runtime())
             .unwrap_or(false);
 
-        for channel_samples in buffer.iter_samples() {
-            let gain = self.params.gain.value();
-
-            // Safety: We expect exactly 2 channels (stereo).
-            // We use into_iter() to get mutable references to the samples avoiding multiple mutable borrows of the container.
-            let mut channels = channel_samples.into_iter();
-            let (l, r) = match (channels.next(), channels.next()) {
-                (Some(l), Some(r)) => (l, r),
-                (Some(l), None) => {
-                    let in_l = *l;
-                    if evergreen_ready {
-                        if let Some(engine) = evergreen_guard.as_mut() {
-                            match engine.process_frame(in_l, in_l) {
-                                Ok((out_l, _)) => {
-                                    *l = out_l * gain;
-                                    continue;
-                                }
-                                Err(error) => {
-                                    log_to_file(&format!(
-                                        "Evergreen process failed, switching to bypass for this block: {}",
-                                        error
-                                    ));
-                                    evergreen_ready = false;
-                                }
-                            }
-                        }
-                    }
-                    *l = Self::bypass_sample(in_l, gain);
-                    continue;
-                }
-                _ => continue,
-            };
+        let num_samples = buffer.samples();
+        let channels = buffer.as_slice();
+        if !channels.is_empty() && num_samples > 0 {
+            // Hosts stay within `max_buffer_size`; this only covers one that doesn't.
+            if self.block_input.len() < num_samples * 2 {
+                self.block_input.resize(num_samples * 2, 0.0);
+                self.block_output.resize(num_samples * 2, 0.0);
+            }
+            let input = &mut self.block_input[..num_samples * 2];
+            let output = &mut self.block_output[..num_samples * 2];
+
+            // A mono layout feeds its one channel to both sides and keeps the left.
+            let stereo = channels.len() > 1;
+            for (i, frame) in input.chunks_exact_mut(2).enumerate() {
+                frame[0] = channels[0][i];
+                frame[1] = channels[usize::from(stereo)][i];
+            }
 
-            let in_l = *l;
-            let in_r = *r;
-            if evergreen_ready {
-                if let Some(engine) = evergreen_guard.as_mut() {
-                    match engine.process_frame(in_l, in_r) {
-                        Ok((out_l, out_r)) => {
-                            *l = out_l * gain;
-                            *r = out_r * gain;
-                            continue;
-                        }
+            let processed = evergreen_ready
+                && evergreen_guard.as_mut().is_some_and(|engine| {
+                    match engine.process_interleaved_stereo(input, output) {
+                        Ok(()) => true,
                         Err(error) => {
                             log_to_file(&format!(
                                 "Evergreen process failed, switching to bypass for this block: {}",
                                 error
                             ));
-                            evergreen_ready = false;
+                            false
                         }
                     }
+                });
+
+            for i in 0..num_samples {
+                let gain = self.params.gain.value();
+                let (l, r) = if processed {
+                    (output[2 * i] * gain, output[2 * i + 1] * gain)
+                } else {
+                    (
+                        Self::bypass_sample(input[2 * i], gain),
+                        Self::bypass_sample(input[2 * i + 1], gain),
+                    )
+                };
+                channels[0][i] = l;
+                if stereo {
+                    channels[1][i] = r;
                 }
             }
+        }
 
-            *l = Self::bypass_sample(in_l, gain);
-            *r = Self::bypass_sample(in_r, gain);
+        // Promoting or rolling back a bundle touches the cache; never here.
+        if evergreen_guard
+            .as_mut()
+            .is_some_and(|engine| engine.take_health_due())
+        {
+            context.execute_background(TonelabTask::RecordEvergreenHealth);
         }
 
         ProcessStatus::Normal
@@ -653,6 +980,40 @@ fn get_token_path() -> std::path::PathBuf {
         .unwrap_or_else(|| std::env::temp_dir().join("tonelab_vst_token"))
 }
 
+/// Serves the cached UI page and verified Evergreen assets to the webview.
+/// Until the page is cached it loads from the network, hence the CORS header.
+fn asset_protocol_response(
+    asset_server: Option<&AssetServer>,
+    path: &str,
+) -> Response<Cow<'static, [u8]>> {
+    let response = match asset_server.and_then(|server| server.respond(path)) {
+        Some((body, content_type)) => Response::builder()
+            .status(200)
+            .header("Content-Type", content_type)
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Cow::Owned(body)),
+        None => Response::builder()
+            .status(404)
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Cow::Borrowed(&[][..])),
+    };
+    response.unwrap_or_else(|_| Response::new(Cow::Borrowed(&[][..])))
+}
+
+/// Script answering the editor's `msg_type` IPC message: a `tonelab:ipc-reply`
+/// event on `window` whose detail is `{ type, ok, error? }`, `error` being
+/// the serialized `EvergreenError`.
+fn ipc_reply_script(msg_type: &str, result: Result<(), &EvergreenError>) -> String {
+    let detail = match result {
+        Ok(()) => serde_json::json!({ "type": msg_type, "ok": true }),
+        Err(error) => serde_json::json!({ "type": msg_type, "ok": false, "error": error }),
+    };
+    format!(
+        "window.dispatchEvent(new CustomEvent('tonelab:ipc-reply', {{ detail: {} }}));",
+        detail
+    )
+}
+
 fn get_data_dir() -> std::path::PathBuf {
     directories::ProjectDirs::from("com", "tonelab", "tonelab_vst")
         .map(|dirs| dirs.data_dir().to_path_buf())
@@ -660,12 +1021,13 @@ fn get_data_dir() -> std::path::PathBuf {
 }
 
 struct TonelabEditor {
-    #[allow(dead_code)] // params are kept alive by Arc but not read directly in Editor struct
     params: Arc<TonelabParams>,
     evergreen_engine: Arc<Mutex<EvergreenEngine>>,
     evergreen_web_ui_url: Option<String>,
     evergreen_icons_url: Option<String>,
     evergreen_effects_url: Option<String>,
+    asset_server: Option<AssetServer>,
+    async_executor: AsyncExecutor<TonelabPlugin>,
     scale_factor_bits: AtomicU32,
     is_open: Arc<AtomicBool>,
 }
@@ -693,7 +1055,7 @@ impl TonelabEditor {
 
 struct TonelabEditorHandle {
     #[allow(dead_code)]
-    webview: WebView,
+    webview: Rc<WebView>,
     is_open: Arc<AtomicBool>,
 }
 
@@ -713,6 +1075,51 @@ impl Editor for TonelabEditor {
         _context: Arc<dyn GuiContext>,
     ) -> Box<dyn std::any::Any + Send> {
         let evergreen_engine = self.evergreen_engine.clone();
+        let params = self.params.clone();
+        let async_executor = self.async_executor.clone();
+        // Filled in once the webview exists, so IPC handlers can reply to it.
+        let reply_webview: Rc<std::cell::OnceCell<std::rc::Weak<WebView>>> = Rc::default();
+        let ipc_webview = reply_webview.clone();
+        let (
+            engine_backend,
+            evergreen_channel,
+            evergreen_channel_scope,
+            evergreen_version,
+            evergreen_upgrade,
+            chain_json,
+        ) = self
+            .evergreen_engine
+            .lock()
+            .ok()
+            .map(|engine| {
+                let scope = if engine.project_channel().is_some() {
+                    "project"
+                } else {
+                    "user"
+                };
+                (
+                    engine.backend(),
+                    engine.channel().to_string(),
+                    scope,
+                    engine.active_version().unwrap_or_default().to_string(),
+                    engine.pending_upgrade().unwrap_or_default().to_string(),
+                    engine
+                        .chain_json()
+                        .unwrap_or_else(|_| engine.last_chain_json().map(str::to_string))
+                        .unwrap_or_default(),
+                )
+            })
+            .unwrap_or((
+                EngineBackend::Null,
+                String::new(),
+                "user",
+                String::new(),
+                String::new(),
+                String::new(),
+            ));
+        // A JSON string literal, so the page can resync its editor from it.
+        let chain_json = Value::String(chain_json).to_string();
+        let engine_backend = engine_backend.as_str();
 
         let device_info = device::get_current_device_info();
         let device_info_json =
@@ -728,9 +1135,14 @@ impl Editor for TonelabEditor {
         let evergreen_icons_url = self.evergreen_icons_url.clone().unwrap_or_default();
         let evergreen_effects_url = self.evergreen_effects_url.clone().unwrap_or_default();
         let ui_url = resolve_evergreen_ui_url(Some(&evergreen_web_ui_url));
+        let page_url = self
+            .asset_server
+            .as_ref()
+            .and_then(|server| server.ui_url(&ui_url))
+            .unwrap_or(ui_url);
 
         let init_script = format!(
-            "window.DEVICE_INFO = {}; window.RUST_AUTH_TOKEN = {:?}; window.TONELAB_API_BASE_URL = {:?}; window.TONELAB_WEB_BASE_URL = {:?}; window.TONELAB_API_PREFIX = {:?}; window.TONELAB_PLUGIN_VERSION = {:?}; window.TONELAB_EVERGREEN_WEB_UI_URL = {:?}; window.TONELAB_EVERGREEN_ICONS_URL = {:?}; window.TONELAB_EVERGREEN_EFFECTS_URL = {:?}; window.TONELAB_RUNTIME_ENV = 'vst-embedded';",
+            "window.DEVICE_INFO = {}; window.RUST_AUTH_TOKEN = {:?}; window.TONELAB_API_BASE_URL = {:?}; window.TONELAB_WEB_BASE_URL = {:?}; window.TONELAB_API_PREFIX = {:?}; window.TONELAB_PLUGIN_VERSION = {:?}; window.TONELAB_EVERGREEN_WEB_UI_URL = {:?}; window.TONELAB_EVERGREEN_ICONS_URL = {:?}; window.TONELAB_EVERGREEN_EFFECTS_URL = {:?}; window.TONELAB_ENGINE_BACKEND = {:?}; window.TONELAB_EVERGREEN_CHANNEL = {:?}; window.TONELAB_EVERGREEN_CHANNEL_SCOPE = {:?}; window.TONELAB_EVERGREEN_VERSION = {:?}; window.TONELAB_EVERGREEN_UPGRADE = {:?}; window.TONELAB_CHAIN_JSON = {}; window.TONELAB_RUNTIME_ENV = 'vst-embedded';",
             device_info_json,
             saved_token,
             api_base_url,
@@ -739,7 +1151,13 @@ impl Editor for TonelabEditor {
             plugin_version,
             evergreen_web_ui_url,
             evergreen_icons_url,
-            evergreen_effects_url
+            evergreen_effects_url,
+            engine_backend,
+            evergreen_channel,
+            evergreen_channel_scope,
+            evergreen_version,
+            evergreen_upgrade,
+            chain_json
         );
 
         let wrapper = ViewWrapper(&parent);
@@ -749,11 +1167,15 @@ impl Editor for TonelabEditor {
         let data_dir = get_data_dir();
         let mut context = wry::WebContext::new(Some(data_dir));
         let (scaled_width, scaled_height) = self.scaled_editor_size();
+        let asset_server = self.asset_server.clone();
 
         let webview_result = WebViewBuilder::new_as_child(&wrapper)
             .with_web_context(&mut context)
             .with_devtools(webview_devtools_enabled())
-            .with_url(&ui_url)
+            .with_url(&page_url)
+            .with_custom_protocol(ASSET_PROTOCOL.to_string(), move |request| {
+                asset_protocol_response(asset_server.as_ref(), request.uri().path())
+            })
             .with_initialization_script(&init_script)
             // Initialize child bounds from logical editor size * host scale factor.
             // This keeps plugin UI placement/sizing aligned with host expectations on Windows/Linux HiDPI.
@@ -766,16 +1188,33 @@ impl Editor for TonelabEditor {
             .with_background_color((30, 30, 30, 255)) // Dark grey background
             .with_ipc_handler(move |req: Request<String>| {
                 let msg = req.body();
+                let reply = |msg_type: &str, result: Result<(), &EvergreenError>| {
+                    if let Some(webview) = ipc_webview.get().and_then(std::rc::Weak::upgrade) {
+                        let _ = webview.evaluate_script(&ipc_reply_script(msg_type, result));
+                    }
+                };
                 let apply_chain = |chain_json: &str, evergreen_engine: &Arc<Mutex<EvergreenEngine>>| {
                     if let Ok(mut engine) = evergreen_engine.lock() {
                         if !engine.has_runtime() {
                             if let Err(error) = engine.bootstrap() {
                                 log_to_file(&format!("IPC sync_chain bootstrap failed: {}", error));
+                                reply("sync_chain", Err(&error));
                                 return;
                             }
                         }
-                        if let Err(error) = engine.sync_chain_json(chain_json) {
-                            log_to_file(&format!("IPC sync_chain apply failed: {}", error));
+                        match engine.sync_chain_json(chain_json) {
+                            Ok(()) => {
+                                params.remember_chain(&engine);
+                                reply("sync_chain", Ok(()));
+                            }
+                            Err(error) => {
+                                log_to_file(&format!(
+                                    "IPC sync_chain apply failed ({}): {}",
+                                    error.kind(),
+                                    error
+                                ));
+                                reply("sync_chain", Err(&error));
+                            }
                         }
                     } else {
                         log_to_file("IPC sync_chain: failed to lock evergreen engine");
@@ -806,16 +1245,22 @@ impl Editor for TonelabEditor {
                                                     "IPC param_change bootstrap failed: {}",
                                                     error
                                                 ));
+                                                reply("param_change", Err(&error));
                                                 return;
                                             }
                                         }
-                                        if let Err(error) =
-                                            engine.set_param(index as i32, key, val as f32)
-                                        {
-                                            log_to_file(&format!(
-                                                "IPC param_change apply failed: index={} key={} error={}",
-                                                index, key, error
-                                            ));
+                                        match engine.set_param(index as i32, key, val as f32) {
+                                            Ok(()) => {
+                                                params.remember_chain(&engine);
+                                                reply("param_change", Ok(()));
+                                            }
+                                            Err(error) => {
+                                                log_to_file(&format!(
+                                                    "IPC param_change apply failed: index={} key={} kind={} error={}",
+                                                    index, key, error.kind(), error
+                                                ));
+                                                reply("param_change", Err(&error));
+                                            }
                                         }
                                     } else {
                                         log_to_file("IPC param_change: failed to lock evergreen engine");
@@ -835,6 +1280,45 @@ impl Editor for TonelabEditor {
                                 if let Some(token) = value.get("token").and_then(|v| v.as_str()) {
                                     save_token_globally(token);
                                 }
+                            } else if msg_type == "set_evergreen_channel" {
+                                let scope =
+                                    value.get("scope").and_then(|v| v.as_str()).unwrap_or("user");
+                                let channel =
+                                    value.get("channel").and_then(|v| v.as_str()).unwrap_or_default();
+                                match set_evergreen_channel(&evergreen_engine, &params, scope, channel)
+                                {
+                                    // Fetches the channel's bundle and hot-swaps to it.
+                                    Ok(()) => async_executor
+                                        .execute_background(TonelabTask::CheckForEvergreenUpdate),
+                                    Err(error) => log_to_file(&format!(
+                                        "IPC set_evergreen_channel failed: {}",
+                                        error
+                                    )),
+                                }
+                            } else if msg_type == "accept_evergreen_upgrade" {
+                                let previous = match evergreen_engine.lock() {
+                                    Ok(mut engine) => {
+                                        let previous = engine.accept_upgrade();
+                                        if previous.is_some() {
+                                            params.remember_bundle(&engine);
+                                            log_to_file(&format!(
+                                                "Evergreen bundle upgraded to {}",
+                                                engine.active_version().unwrap_or("unknown")
+                                            ));
+                                        }
+                                        previous
+                                    }
+                                    Err(_) => {
+                                        log_to_file("IPC accept_evergreen_upgrade: failed to lock evergreen engine");
+                                        None
+                                    }
+                                };
+                                // The old engine is torn down outside the lock.
+                                drop(previous);
+                            } else if msg_type == "dismiss_evergreen_upgrade" {
+                                if let Ok(mut engine) = evergreen_engine.lock() {
+                                    engine.dismiss_upgrade();
+                                }
                             }
                         }
                     }
@@ -844,6 +1328,8 @@ impl Editor for TonelabEditor {
 
         match webview_result {
             Ok(webview) => {
+                let webview = Rc::new(webview);
+                let _ = reply_webview.set(Rc::downgrade(&webview));
                 #[cfg(target_os = "macos")]
                 unsafe {
                     use cocoa::appkit::NSView;
@@ -0,0 +1,333 @@
+//! Offline rendering of a chain JSON over a WAV file, outside of any DAW.
+//!
+//! Used by the `tonelab_render` binary (and `cargo xtask render`) to batch-render
+//! presets and A/B the wasm bundle against the native `dsp_core` build.
+
+use crate::evergreen::WasmRuntime;
+use crate::tone_engine::{NativeEngine, ToneEngine};
+use std::path::{Path, PathBuf};
+
+pub const RENDER_HELP_TEXT: &str = r#"Render a Tonelab chain over a WAV file
+
+Usage:
+  tonelab_render --chain <chain.json> --in <input.wav> --out <output.wav> [options]
+
+Options:
+  --chain <path>         Chain JSON as sent by the UI (array or { "effects": [...] })
+  --in <path>            Input WAV (mono or stereo, int or float)
+  --out <path>           Output WAV (stereo, 32-bit float)
+  --engine <wasm|native> Engine backend to render with (default: native)
+  --wasm <path>          engine.wasm to load for --engine wasm
+                         (default: backend/assets/engine.wasm)
+  --sample-rate <hz>     Expected input sample rate; the render fails if the
+                         input differs, as it is not resampled (default: input rate)
+  --block-size <frames>  Frames per process call (default: 512)
+  -h, --help             Show this help
+"#;
+
+const DEFAULT_WASM_PATH: &str = "backend/assets/engine.wasm";
+const DEFAULT_BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderEngine {
+    Wasm,
+    Native,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub chain: PathBuf,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub engine: RenderEngine,
+    pub wasm: PathBuf,
+    pub sample_rate: Option<u32>,
+    pub block_size: usize,
+}
+
+/// Parses CLI arguments. Returns `Ok(None)` when help was requested.
+pub fn parse_render_args(args: &[String]) -> Result<Option<RenderOptions>, String> {
+    let mut chain = None;
+    let mut input = None;
+    let mut output = None;
+    let mut engine = RenderEngine::Native;
+    let mut wasm = PathBuf::from(DEFAULT_WASM_PATH);
+    let mut sample_rate = None;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+
+    let mut idx = 0usize;
+    while idx < args.len() {
+        let arg = args[idx].as_str();
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+
+        idx += 1;
+        let value = args
+            .get(idx)
+            .ok_or_else(|| format!("{} expects a value", arg))?;
+        match arg {
+            "--chain" => chain = Some(PathBuf::from(value)),
+            "--in" => input = Some(PathBuf::from(value)),
+            "--out" => output = Some(PathBuf::from(value)),
+            "--engine" => {
+                engine = match value.as_str() {
+                    "wasm" => RenderEngine::Wasm,
+                    "native" => RenderEngine::Native,
+                    other => return Err(format!("unknown engine '{}'", other)),
+                }
+            }
+            "--wasm" => wasm = PathBuf::from(value),
+            "--sample-rate" => {
+                let parsed = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|rate| (8_000..=192_000).contains(rate))
+                    .ok_or_else(|| format!("invalid --sample-rate '{}'", value))?;
+                sample_rate = Some(parsed);
+            }
+            "--block-size" => {
+                block_size = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .ok_or_else(|| format!("invalid --block-size '{}'", value))?;
+            }
+            unknown => return Err(format!("unknown option: {}", unknown)),
+        }
+        idx += 1;
+    }
+
+    Ok(Some(RenderOptions {
+        chain: chain.ok_or_else(|| "--chain is required".to_string())?,
+        input: input.ok_or_else(|| "--in is required".to_string())?,
+        output: output.ok_or_else(|| "--out is required".to_string())?,
+        engine,
+        wasm,
+        sample_rate,
+        block_size,
+    }))
+}
+
+pub fn run_render(options: &RenderOptions) -> Result<(), String> {
+    let chain_json = std::fs::read_to_string(&options.chain)
+        .map_err(|e| format!("failed to read chain '{}': {}", options.chain.display(), e))?;
+    let (input, input_rate) = read_wav_stereo(&options.input)?;
+    let sample_rate = checked_sample_rate(options.sample_rate, input_rate, &options.input)?;
+
+    let mut engine = load_engine(options)?;
+    engine.set_sample_rate(sample_rate as f32)?;
+    engine.sync_chain_json(&chain_json)?;
+
+    let mut output = vec![0.0f32; input.len()];
+    render_interleaved(engine.as_mut(), &input, &mut output, options.block_size)?;
+    write_wav_stereo(&options.output, &output, sample_rate)
+}
+
+/// The render runs at the input's rate: there is no resampler, so a
+/// `--sample-rate` that disagrees with the file is refused rather than
+/// written as a header the audio does not match.
+fn checked_sample_rate(
+    requested: Option<u32>,
+    input_rate: u32,
+    input: &Path,
+) -> Result<u32, String> {
+    match requested {
+        Some(rate) if rate != input_rate => Err(format!(
+            "--sample-rate {} does not match '{}' ({} Hz); resample the input first",
+            rate,
+            input.display(),
+            input_rate
+        )),
+        _ => Ok(input_rate),
+    }
+}
+
+fn load_engine(options: &RenderOptions) -> Result<Box<dyn ToneEngine>, String> {
+    match options.engine {
+        RenderEngine::Native => Ok(Box::new(NativeEngine::new())),
+        RenderEngine::Wasm => {
+            let wasm_bytes = std::fs::read(&options.wasm)
+                .map_err(|e| format!("failed to read wasm '{}': {}", options.wasm.display(), e))?;
+            Ok(Box::new(WasmRuntime::from_bytes(&wasm_bytes)?))
+        }
+    }
+}
+
+/// Runs `input` through `engine` in blocks of `block_size` stereo frames.
+pub fn render_interleaved(
+    engine: &mut dyn ToneEngine,
+    input: &[f32],
+    output: &mut [f32],
+    block_size: usize,
+) -> Result<(), String> {
+    if input.len() != output.len() {
+        return Err("render output length must match input length".to_string());
+    }
+    let block_samples = block_size.max(1) * 2;
+    for (in_block, out_block) in input
+        .chunks(block_samples)
+        .zip(output.chunks_mut(block_samples))
+    {
+        engine.process_interleaved_stereo(in_block, out_block)?;
+    }
+    Ok(())
+}
+
+/// Reads a WAV file as interleaved stereo f32. Mono is duplicated to both
+/// channels; anything past the first two channels is dropped.
+fn read_wav_stereo(path: &Path) -> Result<(Vec<f32>, u32), String> {
+    let mut reader = hound::WavReader::open(path)
+        .map_err(|e| format!("failed to open '{}': {}", path.display(), e))?;
+    let spec = reader.spec();
+    let channels = usize::from(spec.channels);
+    if channels == 0 {
+        return Err(format!("'{}' has no channels", path.display()));
+    }
+
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .collect::<Result<_, _>>()
+            .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|value| value as f32 * scale))
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("failed to read '{}': {}", path.display(), e))?
+        }
+    };
+
+    let mut interleaved = Vec::with_capacity(samples.len() / channels * 2);
+    for frame in samples.chunks_exact(channels) {
+        let l = frame[0];
+        let r = if channels > 1 { frame[1] } else { frame[0] };
+        interleaved.push(l);
+        interleaved.push(r);
+    }
+    Ok((interleaved, spec.sample_rate))
+}
+
+fn write_wav_stereo(path: &Path, samples: &[f32], sample_rate: u32) -> Result<(), String> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)
+        .map_err(|e| format!("failed to create '{}': {}", path.display(), e))?;
+    for sample in samples {
+        writer
+            .write_sample(*sample)
+            .map_err(|e| format!("failed to write '{}': {}", path.display(), e))?;
+    }
+    writer
+        .finalize()
+        .map_err(|e| format!("failed to finalize '{}': {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tone_engine::NullEngine;
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_required_and_optional_flags() {
+        let options = parse_render_args(&args(&[
+            "--chain",
+            "chain.json",
+            "--in",
+            "in.wav",
+            "--out",
+            "out.wav",
+            "--engine",
+            "wasm",
+            "--sample-rate",
+            "48000",
+        ]))
+        .expect("arguments should parse")
+        .expect("help was not requested");
+
+        assert_eq!(options.engine, RenderEngine::Wasm);
+        assert_eq!(options.sample_rate, Some(48_000));
+        assert_eq!(options.block_size, DEFAULT_BLOCK_SIZE);
+        assert_eq!(options.wasm, PathBuf::from(DEFAULT_WASM_PATH));
+    }
+
+    #[test]
+    fn rejects_missing_and_invalid_flags() {
+        assert!(parse_render_args(&args(&["--chain", "c.json", "--in", "in.wav"])).is_err());
+        assert!(parse_render_args(&args(&["--engine", "vst"])).is_err());
+        assert!(parse_render_args(&args(&["--sample-rate", "12"])).is_err());
+        assert!(parse_render_args(&args(&["--chain"])).is_err());
+        assert_eq!(parse_render_args(&args(&["--help"])), Ok(None));
+    }
+
+    #[test]
+    fn render_interleaved_covers_partial_last_block() {
+        let input: Vec<f32> = (0..14).map(|i| i as f32 * 0.01).collect();
+        let mut output = vec![0.0f32; input.len()];
+        render_interleaved(&mut NullEngine, &input, &mut output, 4).expect("render");
+        assert_eq!(input, output);
+    }
+
+    #[test]
+    fn native_render_roundtrips_through_wav() {
+        let dir = std::env::temp_dir().join(format!("tonelab_render_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir");
+        let chain = dir.join("chain.json");
+        let input = dir.join("in.wav");
+        let output = dir.join("out.wav");
+
+        std::fs::write(&chain, "[]").expect("write chain");
+        let mono_spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44_100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&input, mono_spec).expect("create input");
+        for i in 0..1000 {
+            writer
+                .write_sample(((i as f32 * 0.05).sin() * 16_000.0) as i16)
+                .expect("write sample");
+        }
+        writer.finalize().expect("finalize input");
+
+        let options = RenderOptions {
+            chain,
+            input: input.clone(),
+            output: output.clone(),
+            engine: RenderEngine::Native,
+            wasm: PathBuf::from(DEFAULT_WASM_PATH),
+            sample_rate: None,
+            block_size: 64,
+        };
+        run_render(&options).expect("render should succeed");
+
+        let (dry, _) = read_wav_stereo(&input).expect("read input");
+        let (wet, rate) = read_wav_stereo(&output).expect("read output");
+        assert_eq!(rate, 44_100);
+        assert_eq!(dry, wet, "empty chain renders as passthrough");
+
+        let resampled = RenderOptions {
+            sample_rate: Some(48_000),
+            ..options.clone()
+        };
+        let error = run_render(&resampled).expect_err("mismatched rate is refused");
+        assert!(error.contains("does not match"), "{error}");
+        let matching = RenderOptions {
+            sample_rate: Some(44_100),
+            ..options
+        };
+        run_render(&matching).expect("matching rate renders");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
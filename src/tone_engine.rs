@@ -0,0 +1,214 @@
+use crate::evergreen::EvergreenError;
+use tonelab_wasm_engine::EngineState;
+
+/// Which DSP implementation is currently driving audio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EngineBackend {
+    /// Signed `engine.wasm` delivered by Evergreen sync.
+    Wasm,
+    /// `dsp_core` compiled into the plugin, used when no bundle can be loaded.
+    Native,
+    /// Passthrough, used before anything has been loaded and while a faulted
+    /// bundle is being replaced.
+    Null,
+}
+
+impl EngineBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EngineBackend::Wasm => "wasm",
+            EngineBackend::Native => "native",
+            EngineBackend::Null => "bypass",
+        }
+    }
+}
+
+/// Common interface for everything that can run a Tonelab effect chain.
+///
+/// `EvergreenEngine` owns one boxed `ToneEngine` and swaps it when a bundle is
+/// loaded or lost; tests can hand it their own implementation.
+pub trait ToneEngine: Send {
+    fn backend(&self) -> EngineBackend;
+
+    /// Processes interleaved stereo samples; `output` must match `input` in length.
+    fn process_interleaved_stereo(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), EvergreenError>;
+
+    fn process_frame(&mut self, l: f32, r: f32) -> Result<(f32, f32), EvergreenError> {
+        let input = [l, r];
+        let mut output = [0.0f32, 0.0f32];
+        self.process_interleaved_stereo(&input, &mut output)?;
+        Ok((output[0], output[1]))
+    }
+
+    fn set_param(&mut self, effect_idx: i32, key: &str, value: f32) -> Result<(), EvergreenError>;
+
+    fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), EvergreenError>;
+
+    /// The chain being run, live param changes included; `None` when this
+    /// engine can't report it.
+    fn chain_json(&self) -> Result<Option<String>, EvergreenError> {
+        Ok(None)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), EvergreenError>;
+}
+
+fn check_interleaved_stereo(input: &[f32], output: &[f32]) -> Result<(), EvergreenError> {
+    if input.len() != output.len() {
+        return Err(EvergreenError::Validation(
+            "engine expected output length to match input length".to_string(),
+        ));
+    }
+    if !input.len().is_multiple_of(2) {
+        return Err(EvergreenError::Validation(
+            "engine expects interleaved stereo input (even sample count)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `dsp_core` linked into the plugin, driven through the same `EngineState`
+/// the wasm guest uses.
+#[derive(Default)]
+pub struct NativeEngine {
+    state: EngineState,
+}
+
+impl NativeEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ToneEngine for NativeEngine {
+    fn backend(&self) -> EngineBackend {
+        EngineBackend::Native
+    }
+
+    fn process_interleaved_stereo(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), EvergreenError> {
+        check_interleaved_stereo(input, output)?;
+        self.state.process(input, output, input.len() / 2);
+        Ok(())
+    }
+
+    fn set_param(&mut self, effect_idx: i32, key: &str, value: f32) -> Result<(), EvergreenError> {
+        let index = usize::try_from(effect_idx).map_err(|_| {
+            EvergreenError::Validation(format!("invalid effect index {}", effect_idx))
+        })?;
+        self.state.set_param(index, key, value);
+        Ok(())
+    }
+
+    fn sync_chain_json(&mut self, chain_json: &str) -> Result<(), EvergreenError> {
+        self.state
+            .set_chain_json(chain_json)
+            .map_err(EvergreenError::Validation)
+    }
+
+    fn chain_json(&self) -> Result<Option<String>, EvergreenError> {
+        self.state
+            .chain_json()
+            .map(Some)
+            .map_err(EvergreenError::Validation)
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f32) -> Result<(), EvergreenError> {
+        self.state.set_sample_rate(sample_rate);
+        Ok(())
+    }
+}
+
+/// Passes audio through untouched and accepts (and ignores) every update.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullEngine;
+
+impl ToneEngine for NullEngine {
+    fn backend(&self) -> EngineBackend {
+        EngineBackend::Null
+    }
+
+    fn process_interleaved_stereo(
+        &mut self,
+        input: &[f32],
+        output: &mut [f32],
+    ) -> Result<(), EvergreenError> {
+        check_interleaved_stereo(input, output)?;
+        output.copy_from_slice(input);
+        Ok(())
+    }
+
+    fn set_param(
+        &mut self,
+        _effect_idx: i32,
+        _key: &str,
+        _value: f32,
+    ) -> Result<(), EvergreenError> {
+        Ok(())
+    }
+
+    fn sync_chain_json(&mut self, _chain_json: &str) -> Result<(), EvergreenError> {
+        Ok(())
+    }
+
+    fn set_sample_rate(&mut self, _sample_rate: f32) -> Result<(), EvergreenError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_engine_is_passthrough() {
+        let mut engine = NullEngine;
+        assert_eq!(engine.process_frame(0.3, -0.7), Ok((0.3, -0.7)));
+    }
+
+    #[test]
+    fn engines_reject_mismatched_buffers() {
+        let input = [0.0f32; 4];
+        let mut short = [0.0f32; 2];
+        let mut odd = [0.0f32; 3];
+
+        let engines: [Box<dyn ToneEngine>; 2] =
+            [Box::new(NativeEngine::new()), Box::new(NullEngine)];
+        for mut engine in engines {
+            assert!(engine
+                .process_interleaved_stereo(&input, &mut short)
+                .is_err());
+            assert!(engine
+                .process_interleaved_stereo(&input[..3], &mut odd)
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn native_engine_reports_its_chain() {
+        let mut engine = NativeEngine::new();
+        engine
+            .sync_chain_json(r#"[{ "type": "Overdrive", "params": { "drive": 0.1, "mix": 1.0, "output_gain": 1.0 } }]"#)
+            .expect("chain applies");
+        engine.set_param(0, "mix", 0.5).expect("param applies");
+        let chain_json = engine
+            .chain_json()
+            .expect("chain serializes")
+            .expect("native reports its chain");
+        assert!(chain_json.contains(r#""mix":0.5"#));
+        assert_eq!(NullEngine.chain_json(), Ok(None));
+    }
+
+    #[test]
+    fn native_engine_rejects_negative_effect_index() {
+        let mut engine = NativeEngine::new();
+        assert!(engine.set_param(-1, "drive", 0.5).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Ed25519 trust store for Evergreen bundles: which keys may sign a bundle,
+//! and how keys are rotated in and revoked over the sync manifest.
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+const KEY_STATEMENT_TAG: &str = "tonelab-evergreen-key-statement-v1";
+/// Hex digits of the key's SHA-256 used as its ID.
+const KEY_ID_LEN: usize = 16;
+const ED25519_PUBLIC_KEY_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    Trust,
+    Revoke,
+}
+
+/// A signed change to the trust store, shipped in the sync manifest. A key
+/// rotation is a `trust` of the new key signed by the old one, usually
+/// followed by a `revoke` of the old key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyStatement {
+    pub action: KeyAction,
+    pub key_id: String,
+    /// Base64 raw Ed25519 key; required for `trust`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub public_key: String,
+    /// ID of the key that signed this statement.
+    pub signed_by: String,
+    /// Base64 Ed25519 signature over `payload()`.
+    pub signature: String,
+}
+
+impl KeyStatement {
+    /// The exact bytes covered by `signature`.
+    pub fn payload(&self) -> Vec<u8> {
+        let action = match self.action {
+            KeyAction::Trust => "trust",
+            KeyAction::Revoke => "revoke",
+        };
+        format!(
+            "{}\naction={}\nkey_id={}\npublic_key={}\n",
+            KEY_STATEMENT_TAG,
+            action,
+            self.key_id.trim(),
+            self.public_key.trim()
+        )
+        .into_bytes()
+    }
+}
+
+/// Stable short ID for a raw Ed25519 public key: the first 16 hex digits of
+/// its SHA-256.
+pub fn key_id(public_key: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, public_key)
+        .as_ref()
+        .iter()
+        .take(KEY_ID_LEN / 2)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn decode_public_key(public_key_b64: &str) -> Result<Vec<u8>, String> {
+    let public_key = base64::engine::general_purpose::STANDARD
+        .decode(public_key_b64.trim().as_bytes())
+        .map_err(|e| format!("invalid base64 public key: {}", e))?;
+    if public_key.len() != ED25519_PUBLIC_KEY_LEN {
+        return Err(format!(
+            "Ed25519 public key must be {} bytes, got {}",
+            ED25519_PUBLIC_KEY_LEN,
+            public_key.len()
+        ));
+    }
+    Ok(public_key)
+}
+
+/// Keys allowed to sign bundles. Roots come from the plugin build or the
+/// environment; further keys are only ever added by statements signed with a
+/// key that is already trusted. Revocation is permanent.
+#[derive(Debug, Clone, Default)]
+pub struct TrustStore {
+    keys: BTreeMap<String, Vec<u8>>,
+    revoked: BTreeSet<String>,
+}
+
+impl TrustStore {
+    /// Builds a store from base64 root keys.
+    pub fn from_roots<'a>(roots_b64: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut store = Self::default();
+        for root in roots_b64 {
+            let public_key = decode_public_key(root)?;
+            store.keys.insert(key_id(&public_key), public_key);
+        }
+        Ok(store)
+    }
+
+    /// True when no key was ever configured, i.e. signing is not set up. A
+    /// store whose keys were all revoked is not empty: it verifies nothing.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn is_trusted(&self, key_id: &str) -> bool {
+        self.keys.contains_key(key_id) && !self.revoked.contains(key_id)
+    }
+
+    /// Applies the statements whose signer is trusted at that point, in
+    /// order, and returns the ones that changed the store so they can be
+    /// persisted. Statements that are forged, malformed or already applied
+    /// are skipped rather than failing the sync.
+    pub fn apply(&mut self, statements: &[KeyStatement]) -> Vec<KeyStatement> {
+        let mut applied = Vec::new();
+        for statement in statements {
+            if self.apply_one(statement).is_ok() {
+                applied.push(statement.clone());
+            }
+        }
+        applied
+    }
+
+    fn apply_one(&mut self, statement: &KeyStatement) -> Result<(), String> {
+        let key_id = statement.key_id.trim();
+        self.verify(
+            Some(statement.signed_by.trim()),
+            &statement.payload(),
+            &statement.signature,
+        )?;
+        match statement.action {
+            KeyAction::Trust => {
+                let public_key = decode_public_key(&statement.public_key)?;
+                if self::key_id(&public_key) != key_id {
+                    return Err(format!(
+                        "key statement ID {} does not match its key",
+                        key_id
+                    ));
+                }
+                if self.revoked.contains(key_id) || self.keys.contains_key(key_id) {
+                    return Err(format!("key {} is already known", key_id));
+                }
+                self.keys.insert(key_id.to_string(), public_key);
+            }
+            KeyAction::Revoke => {
+                if !self.revoked.insert(key_id.to_string()) {
+                    return Err(format!("key {} is already revoked", key_id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies `signature_b64` over `message` with `key_id`, or with any
+    /// trusted key when the signer is not named. Returns the signer's ID.
+    pub fn verify(
+        &self,
+        key_id: Option<&str>,
+        message: &[u8],
+        signature_b64: &str,
+    ) -> Result<String, String> {
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(signature_b64.trim().as_bytes())
+            .map_err(|e| format!("invalid base64 signature: {}", e))?;
+
+        let candidates: Vec<(&String, &Vec<u8>)> = match key_id.map(str::trim) {
+            Some(key_id) if !key_id.is_empty() => {
+                if self.revoked.contains(key_id) {
+                    return Err(format!("signing key {} has been revoked", key_id));
+                }
+                let entry = self
+                    .keys
+                    .get_key_value(key_id)
+                    .ok_or_else(|| format!("signing key {} is not trusted", key_id))?;
+                vec![entry]
+            }
+            _ => self
+                .keys
+                .iter()
+                .filter(|(id, _)| !self.revoked.contains(*id))
+                .collect(),
+        };
+
+        candidates
+            .into_iter()
+            .find(|(_, public_key)| {
+                ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key)
+                    .verify(message, &signature)
+                    .is_ok()
+            })
+            .map(|(id, _)| id.clone())
+            .ok_or_else(|| "Ed25519 signature verification failed".to_string())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_keys {
+    use super::*;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    /// Throwaway signing key for tests.
+    pub struct TestKey(Ed25519KeyPair);
+
+    impl TestKey {
+        pub fn generate() -> Self {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .expect("generate key");
+            Self(Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).expect("parse key"))
+        }
+
+        pub fn id(&self) -> String {
+            key_id(self.0.public_key().as_ref())
+        }
+
+        pub fn public_b64(&self) -> String {
+            base64::engine::general_purpose::STANDARD.encode(self.0.public_key().as_ref())
+        }
+
+        pub fn sign_b64(&self, message: &[u8]) -> String {
+            base64::engine::general_purpose::STANDARD.encode(self.0.sign(message).as_ref())
+        }
+
+        pub fn statement(&self, action: KeyAction, subject: &TestKey) -> KeyStatement {
+            let mut statement = KeyStatement {
+                action,
+                key_id: subject.id(),
+                public_key: match action {
+                    KeyAction::Trust => subject.public_b64(),
+                    KeyAction::Revoke => String::new(),
+                },
+                signed_by: self.id(),
+                signature: String::new(),
+            };
+            statement.signature = self.sign_b64(&statement.payload());
+            statement
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_keys::TestKey;
+    use super::*;
+
+    #[test]
+    fn rotation_hands_trust_to_the_new_key() {
+        let old = TestKey::generate();
+        let new = TestKey::generate();
+        let mut store = TrustStore::from_roots([old.public_b64().as_str()]).expect("root");
+        assert!(store
+            .verify(Some(&new.id()), b"bundle", &new.sign_b64(b"bundle"))
+            .is_err());
+
+        let applied = store.apply(&[
+            old.statement(KeyAction::Trust, &new),
+            new.statement(KeyAction::Revoke, &old),
+        ]);
+        assert_eq!(applied.len(), 2);
+
+        assert_eq!(
+            store.verify(None, b"bundle", &new.sign_b64(b"bundle")),
+            Ok(new.id())
+        );
+        let error = store
+            .verify(Some(&old.id()), b"bundle", &old.sign_b64(b"bundle"))
+            .unwrap_err();
+        assert!(error.contains("revoked"), "{error}");
+        assert!(store
+            .verify(None, b"bundle", &old.sign_b64(b"bundle"))
+            .is_err());
+
+        // Replaying persisted statements on a fresh store reaches the same state.
+        let mut replayed = TrustStore::from_roots([old.public_b64().as_str()]).expect("root");
+        assert_eq!(replayed.apply(&applied), applied);
+        assert!(replayed.is_trusted(&new.id()));
+        assert!(!replayed.is_trusted(&old.id()));
+    }
+
+    #[test]
+    fn untrusted_or_tampered_statements_are_ignored() {
+        let root = TestKey::generate();
+        let stranger = TestKey::generate();
+        let target = TestKey::generate();
+        let mut store = TrustStore::from_roots([root.public_b64().as_str()]).expect("root");
+
+        let mut tampered = root.statement(KeyAction::Trust, &target);
+        tampered.public_key = stranger.public_b64();
+        tampered.key_id = stranger.id();
+        let mut mismatched = root.statement(KeyAction::Trust, &target);
+        mismatched.key_id = stranger.id();
+        mismatched.signature = root.sign_b64(&mismatched.payload());
+
+        let applied = store.apply(&[
+            stranger.statement(KeyAction::Trust, &target),
+            stranger.statement(KeyAction::Revoke, &root),
+            tampered,
+            mismatched,
+        ]);
+        assert!(applied.is_empty());
+        assert!(store.is_trusted(&root.id()));
+        assert!(!store.is_trusted(&target.id()));
+        assert!(!store.is_trusted(&stranger.id()));
+    }
+
+    #[test]
+    fn revoked_keys_cannot_be_trusted_again() {
+        let root = TestKey::generate();
+        let other = TestKey::generate();
+        let mut store = TrustStore::from_roots([root.public_b64().as_str()]).expect("root");
+
+        let applied = store.apply(&[
+            root.statement(KeyAction::Revoke, &other),
+            root.statement(KeyAction::Trust, &other),
+        ]);
+        assert_eq!(applied.len(), 1);
+        assert!(!store.is_trusted(&other.id()));
+    }
+}
@@ -0,0 +1,364 @@
+//! Wasm-vs-native parity: runs the same chains and signals through
+//! `engine.wasm` (via `WasmRuntime`) and the natively compiled `dsp_core`
+//! `Chain`, catching float-behaviour or ABI drift between the two targets.
+//!
+//! The wasm engine comes from `TONELAB_PARITY_WASM` when set, otherwise it is
+//! built from `wasm-engine-rust/`, which needs the `wasm32-unknown-unknown`
+//! target. The tests are ignored by default so a plain `cargo test` never
+//! passes without checking anything; CI runs them with
+//! `cargo test --test wasm_parity -- --ignored`, where a missing engine fails.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+use tonelab_vst::evergreen::{EvergreenError, SandboxLimits, WasmRuntime};
+use tonelab_vst::tone_engine::ToneEngine;
+use tonelab_wasm_engine::dsp_core::Chain;
+
+const ENV_PARITY_WASM: &str = "TONELAB_PARITY_WASM";
+const SAMPLE_RATE: f32 = 48_000.0;
+const FRAMES: usize = 4096;
+const BLOCK_FRAMES: usize = 256;
+/// Not zero: wasm32 links Rust's `libm` for `sin`/`exp`/`tanh` while native
+/// uses the platform's, and reverb/delay feedback accumulates the ULP noise.
+const MAX_ABS_DIFF: f32 = 1e-4;
+
+fn wasm_bytes() -> &'static [u8] {
+    static WASM: OnceLock<Vec<u8>> = OnceLock::new();
+    WASM.get_or_init(load_or_build_wasm)
+}
+
+fn load_or_build_wasm() -> Vec<u8> {
+    if let Ok(path) = std::env::var(ENV_PARITY_WASM) {
+        return std::fs::read(&path).unwrap_or_else(|e| panic!("read {path}: {e}"));
+    }
+
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = root.join("target").join("wasm-parity");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+        .args(["build", "--release", "--target", "wasm32-unknown-unknown"])
+        .arg("--manifest-path")
+        .arg(root.join("wasm-engine-rust").join("Cargo.toml"))
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        outcome => panic!(
+            "failed to build engine.wasm for the parity test ({outcome:?}); \
+             run `rustup target add wasm32-unknown-unknown` or set {ENV_PARITY_WASM}"
+        ),
+    }
+
+    let built: PathBuf = target_dir
+        .join("wasm32-unknown-unknown")
+        .join("release")
+        .join("tonelab_wasm_engine.wasm");
+    std::fs::read(&built).unwrap_or_else(|e| panic!("read {}: {e}", built.display()))
+}
+
+fn impulse() -> Vec<f32> {
+    let mut samples = vec![0.0f32; FRAMES * 2];
+    samples[0] = 1.0;
+    samples[1] = -0.5;
+    samples
+}
+
+fn sweep() -> Vec<f32> {
+    (0..FRAMES)
+        .flat_map(|frame| {
+            let t = frame as f32 / SAMPLE_RATE;
+            let phase = 2.0 * std::f32::consts::PI * (40.0 + 4000.0 * t) * t;
+            [0.6 * phase.sin(), 0.6 * phase.cos()]
+        })
+        .collect()
+}
+
+fn noise() -> Vec<f32> {
+    let mut seed: u64 = 0x0DDB_1A5E_5BAD_5EED;
+    (0..FRAMES * 2)
+        .map(|_| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((seed >> 40) as f32 / (1u64 << 24) as f32 - 0.5) * 0.8
+        })
+        .collect()
+}
+
+fn chains() -> Vec<(&'static str, serde_json::Value)> {
+    vec![
+        ("empty", serde_json::json!([])),
+        (
+            "overdrive",
+            serde_json::json!([{ "type": "Overdrive", "params": { "drive": 0.7, "mix": 0.9, "output_gain": 1.1 } }]),
+        ),
+        (
+            "delay",
+            serde_json::json!([{ "type": "Delay", "params": { "time_ms": 25.0, "feedback": 0.5, "mix": 0.4 } }]),
+        ),
+        (
+            "noise_gate",
+            serde_json::json!([{ "type": "NoiseGate", "params": { "threshold_db": -20.0, "ratio": 6.0, "attack_ms": 1.0, "release_ms": 40.0 } }]),
+        ),
+        (
+            "equalizer",
+            serde_json::json!([{ "type": "Equalizer", "params": {
+                "low_freq": 150.0, "low_gain": 3.0, "mid_freq": 1000.0, "mid_gain": -4.0,
+                "mid_q": 0.9, "high_freq": 6000.0, "high_gain": 2.0
+            } }]),
+        ),
+        (
+            "reverb",
+            serde_json::json!([{ "type": "Reverb", "params": { "room_size": 0.7, "damping": 0.3, "width": 1.0, "mix": 0.5, "pre_delay_ms": 3.0 } }]),
+        ),
+        (
+            "cabinet",
+            serde_json::json!([{ "type": "Cabinet", "params": {
+                "cabinet": 2.0, "mix": 1.0, "low_cut": 80.0, "high_cut": 10000.0,
+                "presence": 1.0, "mid_peak": -2.0, "gain": 0.0
+            } }]),
+        ),
+        (
+            "full_object_form",
+            serde_json::json!({ "effects": [
+                { "type": "NoiseGate", "params": { "threshold_db": -50.0, "ratio": 4.0, "attack_ms": 2.0, "release_ms": 80.0 } },
+                { "type": "Overdrive", "params": { "drive": 0.4, "mix": 1.0, "output_gain": 1.0 } },
+                { "type": "Equalizer", "params": {
+                    "low_freq": 100.0, "low_gain": 1.0, "mid_freq": 800.0, "mid_gain": 2.0,
+                    "mid_q": 1.2, "high_freq": 5000.0, "high_gain": -1.0
+                } },
+                { "type": "Delay", "params": { "time_ms": 40.0, "feedback": 0.3, "mix": 0.2 } },
+                { "type": "Reverb", "params": { "room_size": 0.4, "damping": 0.5, "width": 0.8, "mix": 0.25, "pre_delay_ms": 0.0 } }
+            ] }),
+        ),
+    ]
+}
+
+/// Compiling the module dominates test time, so one runtime is shared per
+/// test; `sync_chain_json` rebuilds and resets the guest chain every case.
+fn load_runtime(wasm: &[u8]) -> WasmRuntime {
+    let mut runtime = WasmRuntime::from_bytes(wasm).expect("wasm engine should load");
+    runtime
+        .set_sample_rate(SAMPLE_RATE)
+        .expect("wasm set_sample_rate");
+    runtime
+}
+
+/// Renders `input` block by block, applying `param_change` to effect 0 at the
+/// halfway block so `set_param_json` is exercised too.
+fn render_wasm(
+    runtime: &mut WasmRuntime,
+    chain_json: &str,
+    input: &[f32],
+    param_change: Option<(&str, f32)>,
+) -> Vec<f32> {
+    runtime
+        .sync_chain_json(chain_json)
+        .expect("wasm should accept chain");
+
+    let mut output = vec![0.0f32; input.len()];
+    for (block, (in_block, out_block)) in input
+        .chunks(BLOCK_FRAMES * 2)
+        .zip(output.chunks_mut(BLOCK_FRAMES * 2))
+        .enumerate()
+    {
+        if block == FRAMES / BLOCK_FRAMES / 2 {
+            if let Some((key, value)) = param_change {
+                runtime.set_param(0, key, value).expect("wasm set_param");
+            }
+        }
+        runtime
+            .process_interleaved_stereo(in_block, out_block)
+            .expect("wasm process");
+    }
+    output
+}
+
+fn render_native(chain_json: &str, input: &[f32], param_change: Option<(&str, f32)>) -> Vec<f32> {
+    let mut chain = Chain::from_json(chain_json).expect("native should accept chain");
+    chain.reset(SAMPLE_RATE);
+
+    let mut output = Vec::with_capacity(input.len());
+    for (frame, samples) in input.chunks_exact(2).enumerate() {
+        if frame == FRAMES / 2 {
+            if let Some((key, value)) = param_change {
+                chain.set_param(0, key, value);
+            }
+        }
+        let (l, r) = chain.process(samples[0], samples[1]);
+        output.push(l);
+        output.push(r);
+    }
+    output
+}
+
+fn assert_parity(label: &str, wasm: &[f32], native: &[f32]) {
+    assert_eq!(wasm.len(), native.len(), "{label}: length mismatch");
+    let mut max_diff = 0.0f32;
+    let mut worst = 0usize;
+    for (idx, (w, n)) in wasm.iter().zip(native).enumerate() {
+        assert!(
+            w.is_finite() == n.is_finite(),
+            "{label}: finiteness differs at sample {idx}: wasm={w} native={n}"
+        );
+        let diff = (w - n).abs();
+        if diff > max_diff {
+            max_diff = diff;
+            worst = idx;
+        }
+    }
+    assert!(
+        max_diff <= MAX_ABS_DIFF,
+        "{label}: wasm and native diverge by {max_diff} at sample {worst} (wasm={} native={})",
+        wasm[worst],
+        native[worst]
+    );
+}
+
+#[test]
+#[ignore = "requires wasm32-unknown-unknown"]
+fn wasm_matches_native_for_every_chain_and_signal() {
+    let wasm = wasm_bytes();
+
+    let mut runtime = load_runtime(wasm);
+    let signals = [
+        ("impulse", impulse()),
+        ("sweep", sweep()),
+        ("noise", noise()),
+    ];
+    for (chain_name, chain) in chains() {
+        let chain_json = chain.to_string();
+        for (signal_name, input) in &signals {
+            let label = format!("{chain_name}/{signal_name}");
+            let wasm_out = render_wasm(&mut runtime, &chain_json, input, None);
+            let native_out = render_native(&chain_json, input, None);
+            assert_parity(&label, &wasm_out, &native_out);
+        }
+    }
+}
+
+#[test]
+#[ignore = "requires wasm32-unknown-unknown"]
+fn wasm_matches_native_across_param_changes() {
+    let wasm = wasm_bytes();
+
+    let changes = [
+        ("overdrive", ("drive", 0.2)),
+        ("delay", ("feedback", 0.8)),
+        ("equalizer", ("mid_gain", 6.0)),
+        ("reverb", ("room_size", 0.2)),
+    ];
+    let mut runtime = load_runtime(wasm);
+    let input = sweep();
+    for (chain_name, change) in changes {
+        let (_, chain) = chains()
+            .into_iter()
+            .find(|(name, _)| *name == chain_name)
+            .expect("chain exists");
+        let chain_json = chain.to_string();
+        let wasm_out = render_wasm(&mut runtime, &chain_json, &input, Some(change));
+        let native_out = render_native(&chain_json, &input, Some(change));
+        assert_parity(
+            &format!("{chain_name}+{}", change.0),
+            &wasm_out,
+            &native_out,
+        );
+    }
+}
+
+#[test]
+#[ignore = "requires wasm32-unknown-unknown"]
+fn wasm_and_native_reject_the_same_invalid_chain() {
+    let wasm = wasm_bytes();
+
+    let invalid =
+        serde_json::json!([{ "type": "Delay", "params": { "time_ms": 100.0, "feedback": 1.5, "mix": 0.5 } }])
+            .to_string();
+    let mut runtime = load_runtime(wasm);
+    let error = runtime
+        .sync_chain_json(&invalid)
+        .expect_err("wasm rejects the chain");
+    let native_error = Chain::from_json(&invalid).expect_err("native rejects the chain");
+    // The guest explains itself through `last_error` with the native text.
+    match error {
+        EvergreenError::GuestStatus { message, .. } => {
+            assert_eq!(message.as_deref(), Some(native_error.trim()))
+        }
+        other => panic!("expected a guest status, got {other:?}"),
+    }
+}
+
+#[test]
+#[ignore = "requires wasm32-unknown-unknown"]
+fn wasm_and_native_report_the_same_chain_after_param_changes() {
+    let wasm = wasm_bytes();
+
+    let mut runtime = load_runtime(wasm);
+    for (name, chain) in chains() {
+        let chain_json = chain.to_string();
+        runtime
+            .sync_chain_json(&chain_json)
+            .expect("wasm should accept chain");
+        runtime.set_param(0, "mix", 0.25).expect("wasm set_param");
+        let native = Chain::from_json(&chain_json).expect("native should accept chain");
+        native.set_param(0, "mix", 0.25);
+
+        let reported = runtime
+            .chain_json()
+            .expect("wasm get_chain_json")
+            .expect("guest reports its chain");
+        let parse =
+            |json: &str| serde_json::from_str::<serde_json::Value>(json).expect("chain JSON");
+        assert_eq!(
+            parse(&reported),
+            parse(&native.to_json().expect("native to_json")),
+            "{name}: reported chain differs"
+        );
+    }
+}
+
+/// Guest I/O buffers must be reused rather than allocated per call, or a
+/// session grows linear memory until the sandbox cap wrongly faults the bundle.
+#[test]
+#[ignore = "requires wasm32-unknown-unknown"]
+fn wasm_runs_minutes_of_audio_under_default_limits() {
+    const BLOCK_SECS: usize = 300;
+    const FRAME_SECS: usize = 60;
+    let mut runtime = WasmRuntime::with_limits(wasm_bytes(), SandboxLimits::default())
+        .expect("wasm engine should load");
+    runtime
+        .set_sample_rate(SAMPLE_RATE)
+        .expect("wasm set_sample_rate");
+    let (_, chain) = chains()
+        .into_iter()
+        .find(|(name, _)| *name == "full_object_form")
+        .expect("full chain");
+    runtime
+        .sync_chain_json(&chain.to_string())
+        .expect("wasm should accept chain");
+
+    let input = noise();
+    let mut output = vec![0.0f32; input.len()];
+    let total_frames = BLOCK_SECS * SAMPLE_RATE as usize;
+    let block_sizes = [32, 64, 128, 256, 512, 1024, 480, 4096];
+    let mut frames = 0;
+    for block_frames in block_sizes.iter().cycle() {
+        if frames >= total_frames {
+            break;
+        }
+        let len = block_frames * 2;
+        runtime
+            .process_interleaved_stereo(&input[..len], &mut output[..len])
+            .unwrap_or_else(|error| panic!("block after {frames} frames: {error}"));
+        assert!(output[..len].iter().all(|sample| sample.is_finite()));
+        frames += block_frames;
+    }
+
+    for frame in 0..FRAME_SECS * SAMPLE_RATE as usize {
+        let i = frame % FRAMES * 2;
+        runtime
+            .process_frame(input[i], input[i + 1])
+            .unwrap_or_else(|error| panic!("frame {frame}: {error}"));
+    }
+}
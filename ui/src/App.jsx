@@ -1,5 +1,5 @@
 import React, { useState, useEffect, useMemo, useCallback, useRef } from 'react';
-import { getIsInsideVST } from './config/runtime';
+import { getEngineChain, getEvergreenUpgrade, getIsInsideVST } from './config/runtime';
 import { applyNodeChanges } from '@xyflow/react';
 import Toolbar from './components/Toolbar';
 import ContextMenu from './components/ContextMenu';
@@ -274,7 +274,7 @@ function App() {
 
 
   const handleLoadChain = (chainData) => {
-    if (!chainData || chainData.length === 0) return;
+    if (!chainData || chainData.length === 0) return [];
 
     pushHistory();
 
@@ -359,8 +359,15 @@ function App() {
 
     setFlowNodes(prev => [...prev, ...newFlowNodes]);
     setWires(prev => [...prev, ...newWires]);
+    return newNodes.map(node => node.id);
   };
 
+  // Rebuild the chain the engine is running when the editor (re)opens.
+  useEffect(() => {
+    const ids = handleLoadChain(getEngineChain());
+    if (ids.length > 0) setActiveChainIds(new Set(ids));
+  }, []);
+
   const handleSpawnNode = (type) => {
     pushHistory();
     const effectDef = EFFECTS_METADATA[type];
@@ -820,6 +827,9 @@ function App() {
   const [updateNoticeText, setUpdateNoticeText] = useState('Update available. Click to install.');
   const [isInstallingUpdate, setIsInstallingUpdate] = useState(false);
   const latestUpdateInfoRef = useRef(null);
+  // The host keeps a project on the engine bundle it was saved with and only
+  // offers newer ones here.
+  const [evergreenUpgrade, setEvergreenUpgrade] = useState(() => getEvergreenUpgrade());
 
   React.useEffect(() => {
     let mounted = true;
@@ -873,6 +883,12 @@ function App() {
       setIsInstallingUpdate(false);
     }
   }, [isInstallingUpdate]);
+
+  const handleEvergreenUpgrade = useCallback(() => {
+    postIpcMessage({ type: 'accept_evergreen_upgrade' });
+    setEvergreenUpgrade(null);
+  }, []);
+
   const allPlayButtonContexts = useMemo(() => {
     // 1. Find all distinct chains (connected components)
     const visited = new Set();
@@ -936,6 +952,13 @@ function App() {
         />
       )}
 
+      {!showUpdateNotice && evergreenUpgrade && (
+        <UpdateNotice
+          onClick={handleEvergreenUpgrade}
+          text={`Engine ${evergreenUpgrade.version} available${evergreenUpgrade.currentVersion ? ` (project saved with ${evergreenUpgrade.currentVersion})` : ''}. Click to upgrade.`}
+        />
+      )}
+
       {effectsBootstrapError && (
         <div
           style={{
@@ -113,6 +113,32 @@ export function getEvergreenEffectsUrl() {
     return readFirstStringValue([readWindowString('TONELAB_EVERGREEN_EFFECTS_URL')]);
 }
 
+// Newer engine bundle the host is holding back until the user accepts it,
+// with the version the project was saved with.
+export function getEvergreenUpgrade() {
+    const version = readFirstStringValue([readWindowString('TONELAB_EVERGREEN_UPGRADE')]);
+    if (!version) return null;
+    return {
+        version,
+        currentVersion: readFirstStringValue([readWindowString('TONELAB_EVERGREEN_VERSION')])
+    };
+}
+
+// Chain the engine was running when the editor opened, as `{ type, params }`
+// items. The host sends either a bare array or `{ effects: [...] }`.
+export function getEngineChain() {
+    const json = readWindowString('TONELAB_CHAIN_JSON');
+    if (!json.trim()) return [];
+    try {
+        const chain = JSON.parse(json);
+        const effects = Array.isArray(chain) ? chain : chain?.effects;
+        if (!Array.isArray(effects)) return [];
+        return effects.filter(effect => typeof effect?.type === 'string');
+    } catch {
+        return [];
+    }
+}
+
 export function detectRuntimeEnvironment() {
     if (typeof window === 'undefined') return RUNTIME_ENV_SSR;
 
@@ -5,6 +5,8 @@ import {
     buildWebUrl,
     detectRuntimeEnvironment,
     getApiBaseUrl,
+    getEngineChain,
+    getEvergreenUpgrade,
     getWebBaseUrl
 } from './runtime';
 
@@ -89,4 +91,24 @@ describe('runtime config', () => {
         expect(getWebBaseUrl()).toBe('https://tonelab.dev');
         expect(buildWebUrl('/user/cabinet')).toBe('https://tonelab.dev/user/cabinet');
     });
+
+    it('reports an engine upgrade only when the host offers one', () => {
+        globalThis.window = { TONELAB_EVERGREEN_UPGRADE: '', TONELAB_EVERGREEN_VERSION: '1.0.0' };
+        expect(getEvergreenUpgrade()).toBeNull();
+
+        globalThis.window.TONELAB_EVERGREEN_UPGRADE = '1.1.0';
+        expect(getEvergreenUpgrade()).toEqual({ version: '1.1.0', currentVersion: '1.0.0' });
+    });
+
+    it('reads the engine chain in either form the host sends', () => {
+        const overdrive = { type: 'Overdrive', params: { drive: 0.9 } };
+        globalThis.window = { TONELAB_CHAIN_JSON: JSON.stringify({ effects: [overdrive], token: null }) };
+        expect(getEngineChain()).toEqual([overdrive]);
+
+        globalThis.window.TONELAB_CHAIN_JSON = JSON.stringify([overdrive]);
+        expect(getEngineChain()).toEqual([overdrive]);
+
+        globalThis.window.TONELAB_CHAIN_JSON = '{not json';
+        expect(getEngineChain()).toEqual([]);
+    });
 });
@@ -1,8 +1,16 @@
 import { useEffect, useRef } from 'react';
-import { postIpcMessage } from '../utils/ipcBridge';
+import { onIpcReply, postIpcMessage } from '../utils/ipcBridge';
 
 export function useBridge(nodes) {
-    const lastSentRef = useRef("");
+    // Starts as the empty chain so opening the editor does not clear the
+    // engine before the chain it reported has been rebuilt.
+    const lastSentRef = useRef(JSON.stringify({ type: 'sync_chain', data: [] }));
+
+    useEffect(() => onIpcReply((reply) => {
+        if (reply?.ok === false) {
+            console.warn(`Tonelab ${reply.type} rejected (${reply.error?.kind}):`, reply.error?.message);
+        }
+    }), []);
 
     useEffect(() => {
 
@@ -43,3 +43,10 @@ export function hasIpcBridge() {
         typeof window.chrome?.webview?.postMessage === 'function'
     );
 }
+
+export function onIpcReply(handler) {
+    if (typeof window === 'undefined') return () => {};
+    const listener = (event) => handler(event.detail);
+    window.addEventListener('tonelab:ipc-reply', listener);
+    return () => window.removeEventListener('tonelab:ipc-reply', listener);
+}
@@ -0,0 +1,318 @@
+//! Golden-audio regression tests.
+//!
+//! Every effect is rendered at fixed params over an impulse, a log sine sweep
+//! and seeded noise at 44.1 kHz and 48 kHz. Each render is reduced to its first
+//! `HEAD_FRAMES` samples plus per-block RMS, stored under `tests/golden/`, and
+//! compared against new output with a tolerance.
+//!
+//! After an intentional DSP change, regenerate the fixtures with:
+//!
+//! ```bash
+//! TONELAB_BLESS_GOLDEN=1 cargo test --manifest-path wasm-engine-rust/Cargo.toml golden
+//! ```
+
+use super::Chain;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const BLESS_ENV: &str = "TONELAB_BLESS_GOLDEN";
+const SAMPLE_RATES: [u32; 2] = [44_100, 48_000];
+const RENDER_FRAMES: usize = 8192;
+const HEAD_FRAMES: usize = 64;
+const RMS_BLOCK_FRAMES: usize = 256;
+const ABS_TOLERANCE: f32 = 1e-4;
+const REL_TOLERANCE: f32 = 1e-3;
+
+#[derive(Debug, Clone, Copy)]
+enum Signal {
+    Impulse,
+    Sweep,
+    Noise,
+}
+
+impl Signal {
+    const ALL: [Signal; 3] = [Signal::Impulse, Signal::Sweep, Signal::Noise];
+
+    fn name(self) -> &'static str {
+        match self {
+            Signal::Impulse => "impulse",
+            Signal::Sweep => "sweep",
+            Signal::Noise => "noise",
+        }
+    }
+
+    /// Interleaved stereo input. Channels differ so L/R swaps show up.
+    fn render(self, sample_rate: u32) -> Vec<f32> {
+        let mut samples = vec![0.0f32; RENDER_FRAMES * 2];
+        match self {
+            Signal::Impulse => {
+                samples[0] = 1.0;
+                samples[1] = 0.5;
+            }
+            Signal::Sweep => {
+                let (start_hz, end_hz) = (20.0f64, 20_000.0f64);
+                let duration = RENDER_FRAMES as f64 / sample_rate as f64;
+                let k = (end_hz / start_hz).ln();
+                for frame in 0..RENDER_FRAMES {
+                    let t = frame as f64 / sample_rate as f64;
+                    let phase = 2.0 * std::f64::consts::PI * start_hz * duration / k
+                        * ((t * k / duration).exp() - 1.0);
+                    samples[frame * 2] = (0.5 * phase.sin()) as f32;
+                    samples[frame * 2 + 1] = (0.5 * phase.cos()) as f32;
+                }
+            }
+            Signal::Noise => {
+                let mut seed: u64 = 0x5EED_1234_ABCD_0042;
+                for sample in samples.iter_mut() {
+                    seed = seed
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    let unit = (seed >> 40) as f32 / (1u64 << 24) as f32;
+                    *sample = (unit * 2.0 - 1.0) * 0.5;
+                }
+            }
+        }
+        samples
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GoldenCase {
+    signal: String,
+    sample_rate: u32,
+    head_l: Vec<f32>,
+    head_r: Vec<f32>,
+    rms_l: Vec<f32>,
+    rms_r: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GoldenFixture {
+    chain: serde_json::Value,
+    cases: Vec<GoldenCase>,
+}
+
+fn fixture_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{name}.json"))
+}
+
+fn render_case(chain_json: &str, signal: Signal, sample_rate: u32) -> GoldenCase {
+    let mut chain = Chain::from_json(chain_json).expect("golden chain should parse");
+    chain.reset(sample_rate as f32);
+
+    let input = signal.render(sample_rate);
+    let mut left = Vec::with_capacity(RENDER_FRAMES);
+    let mut right = Vec::with_capacity(RENDER_FRAMES);
+    for frame in input.chunks_exact(2) {
+        let (l, r) = chain.process(frame[0], frame[1]);
+        left.push(l);
+        right.push(r);
+    }
+
+    let rms = |channel: &[f32]| -> Vec<f32> {
+        channel
+            .chunks(RMS_BLOCK_FRAMES)
+            .map(|block| {
+                let sum: f64 = block.iter().map(|s| (*s as f64) * (*s as f64)).sum();
+                (sum / block.len() as f64).sqrt() as f32
+            })
+            .collect()
+    };
+
+    GoldenCase {
+        signal: signal.name().to_string(),
+        sample_rate,
+        head_l: left[..HEAD_FRAMES].to_vec(),
+        head_r: right[..HEAD_FRAMES].to_vec(),
+        rms_l: rms(&left),
+        rms_r: rms(&right),
+    }
+}
+
+fn compare_series(label: &str, expected: &[f32], actual: &[f32]) -> Result<(), String> {
+    if expected.len() != actual.len() {
+        return Err(format!(
+            "{label}: length changed from {} to {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    for (idx, (want, got)) in expected.iter().zip(actual).enumerate() {
+        let tolerance = ABS_TOLERANCE + REL_TOLERANCE * want.abs();
+        if !got.is_finite() || (want - got).abs() > tolerance {
+            return Err(format!(
+                "{label}[{idx}]: expected {want}, got {got} (tolerance {tolerance})"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// One case per line: small enough to commit, still readable in a diff.
+fn format_fixture(fixture: &GoldenFixture) -> String {
+    let cases: Vec<String> = fixture
+        .cases
+        .iter()
+        .map(|case| serde_json::to_string(case).expect("serialize golden case"))
+        .collect();
+    format!(
+        "{{\n\"chain\": {},\n\"cases\": [\n{}\n]\n}}\n",
+        fixture.chain,
+        cases.join(",\n")
+    )
+}
+
+fn check_golden(name: &str, chain: serde_json::Value) {
+    let chain_json = chain.to_string();
+    let mut cases = Vec::new();
+    for sample_rate in SAMPLE_RATES {
+        for signal in Signal::ALL {
+            cases.push(render_case(&chain_json, signal, sample_rate));
+        }
+    }
+
+    let path = fixture_path(name);
+    if std::env::var(BLESS_ENV).is_ok_and(|value| value == "1") {
+        std::fs::create_dir_all(path.parent().expect("fixture dir")).expect("create fixture dir");
+        std::fs::write(&path, format_fixture(&GoldenFixture { chain, cases }))
+            .expect("write fixture");
+        return;
+    }
+
+    let bytes = std::fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "missing golden fixture '{}' ({e}); run with {BLESS_ENV}=1 to create it",
+            path.display()
+        )
+    });
+    let fixture: GoldenFixture = serde_json::from_slice(&bytes).expect("parse golden fixture");
+    assert_eq!(
+        fixture.chain, chain,
+        "{name}: golden params changed; rerun with {BLESS_ENV}=1"
+    );
+    assert_eq!(
+        fixture.cases.len(),
+        cases.len(),
+        "{name}: case count changed"
+    );
+
+    let mut failures = Vec::new();
+    for (expected, actual) in fixture.cases.iter().zip(&cases) {
+        let prefix = format!("{name}/{}@{}", actual.signal, actual.sample_rate);
+        for (series, want, got) in [
+            ("head_l", &expected.head_l, &actual.head_l),
+            ("head_r", &expected.head_r, &actual.head_r),
+            ("rms_l", &expected.rms_l, &actual.rms_l),
+            ("rms_r", &expected.rms_r, &actual.rms_r),
+        ] {
+            if let Err(error) = compare_series(&format!("{prefix} {series}"), want, got) {
+                failures.push(error);
+            }
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "golden mismatch (rerun with {BLESS_ENV}=1 if intentional):\n{}",
+        failures.join("\n")
+    );
+}
+
+fn single_effect(effect_type: &str, params: serde_json::Value) -> serde_json::Value {
+    serde_json::json!([{ "type": effect_type, "params": params }])
+}
+
+#[test]
+fn golden_overdrive() {
+    check_golden(
+        "overdrive",
+        single_effect(
+            "Overdrive",
+            serde_json::json!({ "drive": 0.6, "mix": 0.8, "output_gain": 0.9 }),
+        ),
+    );
+}
+
+#[test]
+fn golden_delay() {
+    check_golden(
+        "delay",
+        single_effect(
+            "Delay",
+            serde_json::json!({ "time_ms": 60.0, "feedback": 0.45, "mix": 0.35 }),
+        ),
+    );
+}
+
+#[test]
+fn golden_noise_gate() {
+    check_golden(
+        "noise_gate",
+        single_effect(
+            "NoiseGate",
+            serde_json::json!({
+                "threshold_db": -24.0,
+                "ratio": 8.0,
+                "attack_ms": 1.5,
+                "release_ms": 60.0
+            }),
+        ),
+    );
+}
+
+#[test]
+fn golden_equalizer() {
+    check_golden(
+        "equalizer",
+        single_effect(
+            "Equalizer",
+            serde_json::json!({
+                "low_freq": 120.0,
+                "low_gain": 4.0,
+                "mid_freq": 900.0,
+                "mid_gain": -3.0,
+                "mid_q": 1.4,
+                "high_freq": 5000.0,
+                "high_gain": 2.5
+            }),
+        ),
+    );
+}
+
+#[test]
+fn golden_reverb() {
+    check_golden(
+        "reverb",
+        single_effect(
+            "Reverb",
+            serde_json::json!({
+                "room_size": 0.6,
+                "damping": 0.35,
+                "width": 0.9,
+                "mix": 0.4,
+                "pre_delay_ms": 5.0
+            }),
+        ),
+    );
+}
+
+#[test]
+fn golden_cabinet() {
+    check_golden(
+        "cabinet",
+        single_effect(
+            "Cabinet",
+            serde_json::json!({
+                "cabinet": 1.0,
+                "mix": 1.0,
+                "low_cut": 90.0,
+                "high_cut": 9000.0,
+                "presence": 2.0,
+                "mid_peak": 1.5,
+                "gain": 0.0
+            }),
+        ),
+    );
+}
@@ -48,6 +48,9 @@ impl AudioEffect {
     }
 }
 
+#[cfg(test)]
+mod golden;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -141,6 +144,28 @@ mod tests {
         assert_eq!(reparsed.effects.len(), chain.effects.len());
     }
 
+    #[test]
+    fn chain_to_json_reflects_set_param() {
+        let json = serde_json::json!([
+            {
+                "type": "Overdrive",
+                "params": { "drive": 0.2, "mix": 1.0, "output_gain": 1.0 }
+            }
+        ])
+        .to_string();
+
+        let chain = Chain::from_json(&json).expect("chain should parse");
+        chain.set_param(0, "drive", 0.8);
+
+        let serialized: serde_json::Value =
+            serde_json::from_str(&chain.to_json().expect("serialization should succeed"))
+                .expect("serialized chain should be JSON");
+        let drive = serialized["effects"][0]["params"]["drive"]
+            .as_f64()
+            .expect("drive should be serialized");
+        assert!((drive - 0.8).abs() < 1e-6);
+    }
+
     #[test]
     fn chain_set_param_changes_processing_result() {
         let json = serde_json::json!([
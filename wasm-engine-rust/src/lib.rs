@@ -3,12 +3,52 @@
 use serde::Deserialize;
 use std::cell::RefCell;
 
-mod dsp_core;
+pub mod dsp_core;
 
 use dsp_core::Chain;
 
+/// Version of the export set below. Bump only for breaking changes (renamed or
+/// re-typed exports, changed pointer conventions); additions are advertised
+/// through `CAPABILITIES` instead.
+pub const ABI_VERSION: u32 = 1;
+
+/// `set_param_json` is exported and applies live param changes.
+pub const CAP_SET_PARAM_JSON: u32 = 1 << 0;
+
+/// `last_error` is exported and explains the last non-zero status.
+pub const CAP_LAST_ERROR: u32 = 1 << 1;
+
+/// `get_chain_json` is exported and reports the chain the engine is running.
+pub const CAP_GET_CHAIN_JSON: u32 = 1 << 2;
+
+/// Capability bits this build of the guest advertises.
+pub const CAPABILITIES: u32 = CAP_SET_PARAM_JSON | CAP_LAST_ERROR | CAP_GET_CHAIN_JSON;
+
 thread_local! {
     static ENGINE: RefCell<EngineState> = RefCell::new(EngineState::new());
+    /// Why the last status-returning export failed; empty after a success.
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+    /// Chain JSON handed out by the last `get_chain_json` call.
+    static CHAIN_JSON: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Records `message` for `last_error` and returns `status`.
+fn fail(status: i32, message: impl Into<String>) -> i32 {
+    LAST_ERROR.with(|last| *last.borrow_mut() = message.into());
+    status
+}
+
+fn clear_error() {
+    LAST_ERROR.with(|last| last.borrow_mut().clear());
+}
+
+/// `text` as `ptr << 32 | len` for the host to read, or `0` when empty.
+fn pack_str(text: &str) -> i64 {
+    if text.is_empty() {
+        0
+    } else {
+        ((text.as_ptr() as u32 as i64) << 32) | text.len() as u32 as i64
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -18,20 +58,29 @@ struct ParamChange {
     value: f32,
 }
 
-struct EngineState {
+/// Chain plus sample rate as driven by the guest exports. The host links this
+/// crate natively and wraps the same state as its fallback engine, so both
+/// backends share one definition of how chain/param updates are applied.
+pub struct EngineState {
     chain: Chain,
     sample_rate: f32,
 }
 
+impl Default for EngineState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EngineState {
-    fn new() -> Self {
+    pub fn new() -> Self {
         let mut chain = Chain::new();
         let sample_rate = 44_100.0;
         chain.reset(sample_rate);
         Self { chain, sample_rate }
     }
 
-    fn process(&mut self, input: &[f32], output: &mut [f32], frames: usize) {
+    pub fn process(&mut self, input: &[f32], output: &mut [f32], frames: usize) {
         for frame in 0..frames {
             let i = frame * 2;
             let in_l = input[i];
@@ -42,23 +91,46 @@ impl EngineState {
         }
     }
 
-    fn set_sample_rate(&mut self, sample_rate: f32) {
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
         self.sample_rate = sample_rate.clamp(8_000.0, 192_000.0);
         self.chain.reset(self.sample_rate);
     }
 
-    fn set_chain_json(&mut self, chain_json: &str) -> Result<(), String> {
+    pub fn set_chain_json(&mut self, chain_json: &str) -> Result<(), String> {
         let mut chain = Chain::from_json(chain_json)?;
         chain.reset(self.sample_rate);
         self.chain = chain;
         Ok(())
     }
 
-    fn set_param(&self, index: usize, key: &str, value: f32) {
+    pub fn set_param(&self, index: usize, key: &str, value: f32) {
         self.chain.set_param(index, key, value);
     }
+
+    /// The running chain as JSON, live param changes included.
+    pub fn chain_json(&self) -> Result<String, String> {
+        self.chain
+            .to_json()
+            .map_err(|e| format!("chain could not be serialized: {}", e))
+    }
 }
 
+// Guest ABI, only built for wasm32. The exports take guest pointers as `i32`
+// and dereference them, which is only sound inside the sandbox; the native
+// host drives `EngineState` instead and must not be able to call these.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn abi_version() -> i32 {
+    ABI_VERSION as i32
+}
+
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn capabilities() -> i32 {
+    CAPABILITIES as i32
+}
+
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn alloc(size: i32) -> i32 {
     if size <= 0 {
@@ -70,6 +142,7 @@ pub extern "C" fn alloc(size: i32) -> i32 {
     ptr as i32
 }
 
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn alloc_bytes(size: i32) -> i32 {
     if size <= 0 {
@@ -81,6 +154,7 @@ pub extern "C" fn alloc_bytes(size: i32) -> i32 {
     ptr as i32
 }
 
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn process(input_ptr: i32, output_ptr: i32, samples: i32) {
     if input_ptr <= 0 || output_ptr <= 0 || samples <= 0 {
@@ -102,52 +176,84 @@ pub extern "C" fn process(input_ptr: i32, output_ptr: i32, samples: i32) {
     });
 }
 
+/// UTF-8 message behind the last non-zero status, as `ptr << 32 | len`, or
+/// `0` when the last call succeeded. Valid until the next export call.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn last_error() -> i64 {
+    LAST_ERROR.with(|last| pack_str(&last.borrow()))
+}
+
+/// The running chain as UTF-8 JSON, packed like `last_error` and valid until
+/// the next `get_chain_json` call. `0` when it can't be serialized, with the
+/// reason in `last_error`.
+#[cfg(target_arch = "wasm32")]
+#[no_mangle]
+pub extern "C" fn get_chain_json() -> i64 {
+    clear_error();
+    match ENGINE.with(|engine| engine.borrow().chain_json()) {
+        Ok(json) => CHAIN_JSON.with(|buffer| {
+            let mut buffer = buffer.borrow_mut();
+            *buffer = json;
+            pack_str(&buffer)
+        }),
+        Err(error) => {
+            fail(1, error);
+            0
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn set_sample_rate(sample_rate: f32) -> i32 {
+    clear_error();
     ENGINE.with(|engine| {
         engine.borrow_mut().set_sample_rate(sample_rate);
     });
     0
 }
 
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn set_chain_json(ptr: i32, len: i32) -> i32 {
+    clear_error();
     if ptr <= 0 || len <= 0 {
-        return 1;
+        return fail(1, "chain JSON payload is empty");
     }
 
     let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
     let chain_json = match std::str::from_utf8(bytes) {
         Ok(value) => value,
-        Err(_) => return 2,
+        Err(_) => return fail(2, "chain JSON payload is not valid UTF-8"),
     };
 
-    let result = ENGINE.with(|engine| engine.borrow_mut().set_chain_json(chain_json));
-    if result.is_ok() {
-        0
-    } else {
-        3
+    match ENGINE.with(|engine| engine.borrow_mut().set_chain_json(chain_json)) {
+        Ok(()) => 0,
+        Err(error) => fail(3, error),
     }
 }
 
+#[cfg(target_arch = "wasm32")]
 #[no_mangle]
 pub extern "C" fn set_param_json(ptr: i32, len: i32) -> i32 {
+    clear_error();
     if ptr <= 0 || len <= 0 {
-        return 1;
+        return fail(1, "param JSON payload is empty");
     }
 
     let bytes = unsafe { std::slice::from_raw_parts(ptr as *const u8, len as usize) };
     let payload = match std::str::from_utf8(bytes) {
         Ok(value) => value,
-        Err(_) => return 2,
+        Err(_) => return fail(2, "param JSON payload is not valid UTF-8"),
     };
     let payload: ParamChange = match serde_json::from_str(payload) {
         Ok(value) => value,
-        Err(_) => return 3,
+        Err(error) => return fail(3, format!("invalid param change: {}", error)),
     };
 
     if payload.index < 0 {
-        return 4;
+        return fail(4, format!("invalid effect index {}", payload.index));
     }
 
     ENGINE.with(|engine| {
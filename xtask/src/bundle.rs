@@ -0,0 +1,715 @@
+//! Evergreen bundle tooling: building `engine.wasm`, Ed25519 signing keys and
+//! signed sync manifests in the format the plugin verifies.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine as _;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Must match `MANIFEST_SIGNATURE_TAG` in the plugin.
+const MANIFEST_SIGNATURE_TAG: &str = "tonelab-evergreen-manifest-v1";
+/// Hex digits of a key ID, as in the plugin's trust store.
+const KEY_ID_LEN: usize = 16;
+const PEM_LABEL: &str = "PRIVATE KEY";
+pub const WASM_TARGET: &str = "wasm32-unknown-unknown";
+pub const WASM_CRATE_DIR: &str = "wasm-engine-rust";
+const WASM_ARTIFACT: &str = "tonelab_wasm_engine.wasm";
+/// Local dev key shared with `backend/security/sign_bundle.sh`.
+pub const DEV_KEY_PATH: &str = "backend/security/keys/dev_ed25519_private.pem";
+/// Public half of the dev key, as `sign_bundle.sh` exports it.
+pub const DEV_PUBLIC_KEY_PATH: &str = "backend/security/dev_ed25519_public_key.b64";
+pub const WASM_FILE: &str = "engine.wasm";
+pub const ICONS_FILE: &str = "icons.zip";
+pub const EFFECTS_FILE: &str = "effects_manifest.json";
+/// Manifest file of a bundle directory, as the plugin caches and reads it.
+pub const MANIFEST_FILE: &str = "sync_manifest.json";
+/// Offline manifest signature the backend attaches to `/vst/sync`.
+pub const MANIFEST_SIGNATURE_FILE: &str = "engine.manifest.sig.json";
+/// Patches to a bundle, next to its wasm and named `<old sha256>.zst`; the
+/// backend advertises what it finds here.
+pub const DELTAS_DIR: &str = "deltas";
+const DELTA_SUFFIX: &str = ".zst";
+const DELTA_LEVEL: i32 = 19;
+/// Must not exceed `DELTA_WINDOW_LOG_MAX` in the plugin, or it cannot apply
+/// the patch.
+const DELTA_WINDOW_LOG_MAX: u32 = 27;
+
+/// Mirror of the plugin's `SyncManifest`. Fields xtask does not interpret
+/// are carried through untouched.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncManifest {
+    pub version: String,
+    pub wasm_url: String,
+    #[serde(default)]
+    pub signature: String,
+    #[serde(default)]
+    pub assets: SyncAssets,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub abi_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_host_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_statements: Vec<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deltas: Vec<WasmDelta>,
+}
+
+/// Mirror of the plugin's `WasmDelta`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasmDelta {
+    pub from_sha256: String,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncAssets {
+    #[serde(default)]
+    pub icons_url: String,
+    #[serde(default)]
+    pub web_ui_url: String,
+    #[serde(default)]
+    pub effects_url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icons_sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effects_sha256: Option<String>,
+}
+
+impl SyncManifest {
+    /// Same bytes as `SyncManifest::signed_payload` in the plugin.
+    pub fn signed_payload(&self) -> Result<Vec<u8>> {
+        fn normalized_sha(sha256: &Option<String>) -> String {
+            sha256
+                .as_deref()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase()
+        }
+
+        let abi_version = self.abi_version.map(|v| v.to_string()).unwrap_or_default();
+        let sha256 = normalized_sha(&self.sha256);
+        let icons_sha256 = normalized_sha(&self.assets.icons_sha256);
+        let effects_sha256 = normalized_sha(&self.assets.effects_sha256);
+        let fields = [
+            ("version", self.version.trim()),
+            ("wasm_url", self.wasm_url.trim()),
+            ("sha256", sha256.as_str()),
+            ("abi_version", abi_version.as_str()),
+            (
+                "min_host_version",
+                self.min_host_version.as_deref().unwrap_or_default().trim(),
+            ),
+            ("web_ui_url", self.assets.web_ui_url.trim()),
+            ("icons_url", self.assets.icons_url.trim()),
+            ("effects_url", self.assets.effects_url.trim()),
+            ("icons_sha256", icons_sha256.as_str()),
+            ("effects_sha256", effects_sha256.as_str()),
+        ];
+
+        let mut payload = format!("{MANIFEST_SIGNATURE_TAG}\n");
+        for (name, value) in fields {
+            if value.contains('\n') {
+                bail!("sync manifest field {name} contains a newline");
+            }
+            payload.push_str(&format!("{name}={value}\n"));
+        }
+        Ok(payload.into_bytes())
+    }
+
+    /// Pins `wasm` by hash and signs both the wasm (for older plugins) and
+    /// the manifest with `key`.
+    pub fn sign(&mut self, wasm: &[u8], key: &SigningKey) -> Result<()> {
+        self.sha256 = Some(sha256_hex(wasm));
+        self.key_id = Some(key.key_id());
+        self.signature = key.sign_b64(wasm);
+        self.manifest_signature = Some(key.sign_b64(&self.signed_payload()?));
+        Ok(())
+    }
+}
+
+/// What goes into a manifest besides the wasm itself.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestSpec {
+    pub version: String,
+    /// URL prefix the wasm and asset files are served from. Without one the
+    /// manifest names them relative to its own directory, which is what a
+    /// local bundle source expects.
+    pub assets_url: Option<String>,
+    /// Directory holding `icons.zip` and `effects_manifest.json`; the ones
+    /// present are pinned by hash.
+    pub assets_dir: Option<PathBuf>,
+    pub web_ui_url: String,
+    pub abi_version: Option<u32>,
+    pub min_host_version: Option<String>,
+    /// Directory of patches from earlier bundles to advertise. They are not
+    /// signed; the plugin checks the patched wasm against the signed hash.
+    pub deltas_dir: Option<PathBuf>,
+}
+
+impl ManifestSpec {
+    /// Builds and signs the manifest for `wasm`.
+    pub fn sign(&self, wasm: &[u8], key: &SigningKey) -> Result<SyncManifest> {
+        let url = |file: &str| match &self.assets_url {
+            Some(base) => format!("{}/{file}", base.trim_end_matches('/')),
+            None => file.to_string(),
+        };
+        let pinned = |file: &str| -> Result<(String, Option<String>)> {
+            let sha256 = match &self.assets_dir {
+                Some(dir) => file_sha256(&dir.join(file))?,
+                None => None,
+            };
+            Ok(match sha256 {
+                Some(sha256) => (url(file), Some(sha256)),
+                None => (String::new(), None),
+            })
+        };
+        let (icons_url, icons_sha256) = pinned(ICONS_FILE)?;
+        let (effects_url, effects_sha256) = pinned(EFFECTS_FILE)?;
+        let mut manifest = SyncManifest {
+            version: self.version.clone(),
+            wasm_url: url(WASM_FILE),
+            assets: SyncAssets {
+                icons_url,
+                web_ui_url: self.web_ui_url.clone(),
+                effects_url,
+                icons_sha256,
+                effects_sha256,
+            },
+            abi_version: self.abi_version,
+            min_host_version: self.min_host_version.clone(),
+            ..SyncManifest::default()
+        };
+        manifest.sign(wasm, key)?;
+        if let Some(dir) = &self.deltas_dir {
+            manifest.deltas = list_deltas(dir)?
+                .into_iter()
+                .map(|(from_sha256, size)| WasmDelta {
+                    url: url(&format!("{DELTAS_DIR}/{}", delta_file_name(&from_sha256))),
+                    from_sha256,
+                    size: Some(size),
+                })
+                .collect();
+        }
+        Ok(manifest)
+    }
+}
+
+pub fn delta_file_name(from_sha256: &str) -> String {
+    format!("{from_sha256}{DELTA_SUFFIX}")
+}
+
+/// The patches in `dir`: the hash of the wasm each applies to, and its size.
+pub fn list_deltas(dir: &Path) -> Result<Vec<(String, u64)>> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Ok(Vec::new());
+    };
+    let mut deltas = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("Failed to list {}", dir.display()))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(from_sha256) = name.strip_suffix(DELTA_SUFFIX) else {
+            continue;
+        };
+        if from_sha256.len() != 64 || !from_sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let size = entry
+            .metadata()
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?
+            .len();
+        deltas.push((from_sha256.to_ascii_lowercase(), size));
+    }
+    deltas.sort();
+    Ok(deltas)
+}
+
+/// A zstd `--patch-from` diff that turns `old` into `new`; the same patch
+/// `zstd --patch-from=old new` writes.
+pub fn make_delta(old: &[u8], new: &[u8]) -> Result<Vec<u8>> {
+    let span = (old.len() + new.len()).max(1);
+    if span > 1 << DELTA_WINDOW_LOG_MAX {
+        bail!("Bundles are too large to diff ({span} bytes together)");
+    }
+    let window_log = span.next_power_of_two().trailing_zeros().max(10);
+    let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), DELTA_LEVEL, old)
+        .context("Failed to start zstd encoder")?;
+    encoder.long_distance_matching(true)?;
+    encoder.window_log(window_log)?;
+    encoder.include_checksum(true)?;
+    encoder.write_all(new)?;
+    Ok(encoder.finish()?)
+}
+
+/// Applies a patch from `make_delta` to `old`.
+pub fn apply_delta(old: &[u8], patch: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(patch, old)
+        .context("Failed to start zstd decoder")?;
+    decoder.window_log_max(DELTA_WINDOW_LOG_MAX)?;
+    let mut new = Vec::new();
+    decoder
+        .read_to_end(&mut new)
+        .context("Failed to apply delta")?;
+    Ok(new)
+}
+
+/// `engine.manifest.sig.json`: the manifest signature plus the exact payload
+/// it covers, so the backend can tell when its config drifted from it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestSignatureFile {
+    pub key_id: String,
+    pub signature: String,
+    pub payload_b64: String,
+}
+
+impl ManifestSignatureFile {
+    pub fn for_manifest(manifest: &SyncManifest) -> Result<Self> {
+        Ok(Self {
+            key_id: manifest.key_id.clone().unwrap_or_default(),
+            signature: manifest
+                .manifest_signature
+                .clone()
+                .ok_or_else(|| anyhow!("manifest is not signed"))?,
+            payload_b64: base64::engine::general_purpose::STANDARD
+                .encode(manifest.signed_payload()?),
+        })
+    }
+}
+
+/// An Ed25519 private key, stored as PKCS#8 PEM like the keys `openssl
+/// genpkey -algorithm Ed25519` writes.
+pub struct SigningKey {
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Generates a key and writes it to `path`, refusing to overwrite one.
+    pub fn generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            bail!("Refusing to overwrite existing key {}", path.display());
+        }
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| anyhow!("Failed to generate Ed25519 key"))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(path, encode_pem(pkcs8.as_ref()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Self::load(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let pem = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read key {}", path.display()))?;
+        let der = decode_pem(&pem).with_context(|| format!("Invalid key {}", path.display()))?;
+        let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|_| anyhow!("{} is not an Ed25519 PKCS#8 key", path.display()))?;
+        Ok(Self { pair })
+    }
+
+    /// Loads the key at `path`, generating it first if it does not exist.
+    pub fn load_or_generate(path: &Path) -> Result<Self> {
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Self::generate(path)
+        }
+    }
+
+    /// Raw public key, base64 encoded: the format of
+    /// `TONELAB_EVERGREEN_ED25519_PUBLIC_KEY_B64`.
+    pub fn public_key_b64(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.pair.public_key().as_ref())
+    }
+
+    pub fn key_id(&self) -> String {
+        key_id(self.pair.public_key().as_ref())
+    }
+
+    pub fn sign_b64(&self, message: &[u8]) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.pair.sign(message).as_ref())
+    }
+}
+
+/// Same derivation as the plugin's `trust::key_id`.
+pub fn key_id(public_key: &[u8]) -> String {
+    sha256_hex(public_key)[..KEY_ID_LEN].to_string()
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn encode_pem(der: &[u8]) -> String {
+    let body = base64::engine::general_purpose::STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {PEM_LABEL}-----\n");
+    for line in body.as_bytes().chunks(64) {
+        pem.push_str(&String::from_utf8_lossy(line));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {PEM_LABEL}-----\n"));
+    pem
+}
+
+fn decode_pem(pem: &str) -> Result<Vec<u8>> {
+    let begin = format!("-----BEGIN {PEM_LABEL}-----");
+    let end = format!("-----END {PEM_LABEL}-----");
+    let body = pem
+        .split_once(&begin)
+        .and_then(|(_, rest)| rest.split_once(&end))
+        .map(|(body, _)| body)
+        .ok_or_else(|| anyhow!("expected a PEM block labelled {PEM_LABEL}"))?;
+    let body: String = body.split_whitespace().collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .context("PEM body is not valid base64")
+}
+
+/// Builds the wasm engine crate for `WASM_TARGET` and returns the artifact.
+pub fn build_wasm(workspace_root: &Path, release: bool) -> Result<PathBuf> {
+    let crate_dir = workspace_root.join(WASM_CRATE_DIR);
+    let mut command = Command::new("cargo");
+    command
+        .args(["build", "--manifest-path"])
+        .arg(crate_dir.join("Cargo.toml"))
+        .args(["--target", WASM_TARGET])
+        .current_dir(workspace_root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    if release {
+        command.arg("--release");
+    }
+    let status = command
+        .status()
+        .context("Failed to run cargo build for the wasm engine")?;
+    if !status.success() {
+        bail!("wasm engine build failed with status {}", status);
+    }
+
+    let profile = if release { "release" } else { "debug" };
+    let artifact = crate_dir
+        .join("target")
+        .join(WASM_TARGET)
+        .join(profile)
+        .join(WASM_ARTIFACT);
+    if !artifact.is_file() {
+        bail!("wasm build did not produce {}", artifact.display());
+    }
+    Ok(artifact)
+}
+
+/// Decodes a base64 raw Ed25519 public key.
+pub fn decode_public_key(public_key_b64: &str) -> Result<Vec<u8>> {
+    let public_key = base64::engine::general_purpose::STANDARD
+        .decode(public_key_b64.trim())
+        .context("Public key is not valid base64")?;
+    if public_key.len() != 32 {
+        bail!(
+            "Ed25519 public key must be 32 bytes, got {}",
+            public_key.len()
+        );
+    }
+    Ok(public_key)
+}
+
+/// Local copy of a file a manifest points at: relative URLs resolve against
+/// `dir`, absolute ones by their file name.
+pub fn local_file(dir: &Path, url: &str) -> PathBuf {
+    let url = url.trim();
+    match url.split_once("://") {
+        Some((_, rest)) => {
+            let path = rest.split(['?', '#']).next().unwrap_or_default();
+            dir.join(path.rsplit('/').next().unwrap_or_default())
+        }
+        None => dir.join(url),
+    }
+}
+
+/// Checks a bundle the way the plugin does before loading it: the wasm
+/// against the manifest's hash, both signatures against `public_keys`, and
+/// every pinned asset found under `assets_dir`. Returns what was checked.
+pub fn verify_bundle(
+    manifest: &SyncManifest,
+    wasm: &[u8],
+    public_keys: &[Vec<u8>],
+    assets_dir: &Path,
+) -> Result<Vec<String>> {
+    let mut passed = Vec::new();
+    let mut failures = Vec::new();
+
+    if !wasm.starts_with(b"\0asm") {
+        failures.push("engine.wasm is not a wasm module".to_string());
+    }
+    let wasm_sha256 = sha256_hex(wasm);
+    match manifest.sha256.as_deref().map(str::trim) {
+        Some(expected) if expected.eq_ignore_ascii_case(&wasm_sha256) => {
+            passed.push(format!("wasm sha256 {wasm_sha256}"))
+        }
+        Some(expected) => failures.push(format!(
+            "wasm sha256 {wasm_sha256} does not match manifest {expected}"
+        )),
+        None if manifest.manifest_signature.is_some() => {
+            failures.push("signed manifest has no sha256".to_string())
+        }
+        None => {}
+    }
+
+    let key_id = manifest
+        .key_id
+        .as_deref()
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+    let keys: Vec<&Vec<u8>> = public_keys
+        .iter()
+        .filter(|key| key_id.is_none_or(|id| self::key_id(key) == id))
+        .collect();
+    if keys.is_empty() {
+        failures.push(match key_id {
+            Some(id) => format!("signed by key {id}, which is not one of the given public keys"),
+            None => "no public key to verify against".to_string(),
+        });
+    }
+    let verify = |message: &[u8], signature_b64: &str| {
+        let Ok(signature) = base64::engine::general_purpose::STANDARD.decode(signature_b64.trim())
+        else {
+            return None;
+        };
+        keys.iter()
+            .find(|key| {
+                UnparsedPublicKey::new(&ED25519, key.as_slice())
+                    .verify(message, &signature)
+                    .is_ok()
+            })
+            .map(|key| self::key_id(key))
+    };
+    let manifest_signature = manifest
+        .manifest_signature
+        .as_deref()
+        .filter(|v| !v.trim().is_empty());
+    let signature = Some(manifest.signature.as_str()).filter(|v| !v.trim().is_empty());
+    if manifest_signature.is_none() && signature.is_none() {
+        failures.push("bundle is unsigned".to_string());
+    }
+    if let Some(manifest_signature) = manifest_signature {
+        match verify(&manifest.signed_payload()?, manifest_signature) {
+            Some(id) => passed.push(format!("manifest signature (key {id})")),
+            None => failures.push("manifest signature does not verify".to_string()),
+        }
+    }
+    if let Some(signature) = signature {
+        match verify(wasm, signature) {
+            Some(id) => passed.push(format!("wasm signature (key {id})")),
+            None => failures.push("wasm signature does not verify".to_string()),
+        }
+    }
+
+    let pins = [
+        (&manifest.assets.icons_url, &manifest.assets.icons_sha256),
+        (
+            &manifest.assets.effects_url,
+            &manifest.assets.effects_sha256,
+        ),
+    ];
+    for (url, expected) in pins {
+        let Some(expected) = expected.as_deref().map(str::trim).filter(|v| !v.is_empty()) else {
+            continue;
+        };
+        let path = local_file(assets_dir, url);
+        match file_sha256(&path)? {
+            Some(actual) if actual.eq_ignore_ascii_case(expected) => {
+                passed.push(format!("{} sha256 {actual}", path.display()))
+            }
+            Some(actual) => failures.push(format!(
+                "{} sha256 {actual} does not match manifest {expected}",
+                path.display()
+            )),
+            None => failures.push(format!(
+                "pinned asset {url} not found at {}",
+                path.display()
+            )),
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(passed)
+    } else {
+        bail!("{}", failures.join("\n"))
+    }
+}
+
+/// Hash of the file at `path`, if there is one.
+pub fn file_sha256(path: &Path) -> Result<Option<String>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(sha256_hex(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tonelab_xtask_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn signed_payload_matches_plugin_format() {
+        let manifest = SyncManifest {
+            version: "1.2.0".to_string(),
+            wasm_url: "http://localhost/assets/engine.wasm".to_string(),
+            sha256: Some("ABCD".to_string()),
+            abi_version: Some(1),
+            assets: SyncAssets {
+                effects_url: "http://localhost/assets/effects_manifest.json".to_string(),
+                effects_sha256: Some("EF".to_string()),
+                ..SyncAssets::default()
+            },
+            ..SyncManifest::default()
+        };
+        let payload = String::from_utf8(manifest.signed_payload().unwrap()).unwrap();
+        assert_eq!(
+            payload,
+            "tonelab-evergreen-manifest-v1\nversion=1.2.0\nwasm_url=http://localhost/assets/engine.wasm\nsha256=abcd\nabi_version=1\nmin_host_version=\nweb_ui_url=\nicons_url=\neffects_url=http://localhost/assets/effects_manifest.json\nicons_sha256=\neffects_sha256=ef\n"
+        );
+    }
+
+    #[test]
+    fn generated_key_round_trips_and_signs_manifests() {
+        let path = temp_path("key.pem");
+        let _ = fs::remove_file(&path);
+        let key = SigningKey::generate(&path).expect("generate key");
+        assert!(
+            SigningKey::generate(&path).is_err(),
+            "keys are never overwritten"
+        );
+        let loaded = SigningKey::load(&path).expect("load key");
+        assert_eq!(loaded.public_key_b64(), key.public_key_b64());
+        assert_eq!(loaded.key_id().len(), KEY_ID_LEN);
+
+        let wasm = b"\0asm fake engine";
+        let mut manifest = SyncManifest {
+            version: "1.0.0".to_string(),
+            wasm_url: "engine.wasm".to_string(),
+            ..SyncManifest::default()
+        };
+        manifest.sign(wasm, &loaded).expect("sign manifest");
+        let public_key = base64::engine::general_purpose::STANDARD
+            .decode(key.public_key_b64())
+            .unwrap();
+        let verifier = UnparsedPublicKey::new(&ED25519, &public_key);
+        let signature = |b64: &str| {
+            base64::engine::general_purpose::STANDARD
+                .decode(b64)
+                .unwrap()
+        };
+        assert!(verifier
+            .verify(wasm, &signature(&manifest.signature))
+            .is_ok());
+        assert!(verifier
+            .verify(
+                &manifest.signed_payload().unwrap(),
+                &signature(manifest.manifest_signature.as_deref().unwrap())
+            )
+            .is_ok());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn verify_bundle_checks_hashes_signatures_and_assets() {
+        let dir = temp_path("verify");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(EFFECTS_FILE), b"{}").unwrap();
+        let key = SigningKey::generate(&dir.join("key.pem")).unwrap();
+        let public_keys = [decode_public_key(&key.public_key_b64()).unwrap()];
+
+        let wasm = b"\0asm engine";
+        let spec = ManifestSpec {
+            version: "1.0.0".to_string(),
+            assets_url: Some("https://example.com/assets/".to_string()),
+            assets_dir: Some(dir.clone()),
+            ..ManifestSpec::default()
+        };
+        let manifest = spec.sign(wasm, &key).unwrap();
+        assert_eq!(manifest.wasm_url, "https://example.com/assets/engine.wasm");
+        assert_eq!(
+            manifest.assets.icons_url, "",
+            "absent assets are not pinned"
+        );
+        let passed = verify_bundle(&manifest, wasm, &public_keys, &dir).unwrap();
+        assert_eq!(passed.len(), 4, "{passed:?}");
+
+        let error = verify_bundle(&manifest, b"\0asm other", &public_keys, &dir)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("does not match manifest"), "{error}");
+        let other = SigningKey::generate(&dir.join("other.pem")).unwrap();
+        let other_key = decode_public_key(&other.public_key_b64()).unwrap();
+        assert!(verify_bundle(&manifest, wasm, &[other_key], &dir).is_err());
+        fs::write(dir.join(EFFECTS_FILE), b"{ }").unwrap();
+        assert!(verify_bundle(&manifest, wasm, &public_keys, &dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deltas_round_trip_and_are_advertised() {
+        let dir = temp_path("delta");
+        let _ = fs::remove_dir_all(&dir);
+        let deltas_dir = dir.join(DELTAS_DIR);
+        fs::create_dir_all(&deltas_dir).unwrap();
+        let old: Vec<u8> = (0..64 * 1024u32).flat_map(|n| n.to_le_bytes()).collect();
+        let mut new = old.clone();
+        new[1000..1016].copy_from_slice(b"patched section!");
+        let patch = make_delta(&old, &new).unwrap();
+        assert!(patch.len() < new.len() / 10, "{} bytes", patch.len());
+        assert_eq!(apply_delta(&old, &patch).unwrap(), new);
+        assert!(
+            apply_delta(&vec![0u8; old.len()], &patch).is_err(),
+            "checksum catches a wrong base"
+        );
+
+        let from_sha256 = sha256_hex(&old);
+        fs::write(deltas_dir.join(delta_file_name(&from_sha256)), &patch).unwrap();
+        fs::write(deltas_dir.join("notes.zst"), b"ignored").unwrap();
+        let key = SigningKey::generate(&dir.join("key.pem")).unwrap();
+        let spec = ManifestSpec {
+            version: "2.0.0".to_string(),
+            deltas_dir: Some(deltas_dir),
+            ..ManifestSpec::default()
+        };
+        let manifest = spec.sign(&new, &key).unwrap();
+        assert_eq!(
+            manifest.deltas,
+            vec![WasmDelta {
+                url: format!("deltas/{from_sha256}.zst"),
+                from_sha256,
+                size: Some(patch.len() as u64),
+            }]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -10,12 +10,20 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time::{SystemTime, UNIX_EPOCH};
 
+mod bundle;
+mod publish;
+mod serve;
+
 const HELP_TEXT: &str = r#"Tonelab installer/updater helpers
 
 Usage:
   cargo run -p xtask -- install [options]
   cargo run -p xtask -- update [options]
   cargo run -p xtask -- doctor [options]
+  cargo run -p xtask -- render --chain <chain.json> --in <in.wav> --out <out.wav> [render options]
+  cargo run -p xtask -- serve [serve options]
+  cargo run -p xtask -- wasm <build|sign> [options]
+  cargo run -p xtask -- keygen|manifest|delta|verify [options]
 
 Options:
   --release              Build release bundle (default)
@@ -27,6 +35,23 @@ Options:
   --force                Force reinstall even when already up-to-date
   -h, --help             Show this help
 
+Render options (see `render --help`):
+  --engine <wasm|native> Render with engine.wasm or the native dsp_core build
+  --wasm <path>          engine.wasm to load (default: backend/assets/engine.wasm)
+  --sample-rate <hz>     Expected input sample rate (no resampling)
+
+Serve options (see `serve --help`):
+  --port <port>          Port for the local /vst/sync server (default: 8787)
+  --wasm <path>          Serve an existing engine.wasm instead of building one
+
+Bundle commands (see `wasm --help`):
+  wasm build             Build wasm-engine-rust into backend/assets/engine.wasm
+  wasm sign --key <pem>  Write the wasm-only signature
+  keygen                 Create an Ed25519 signing key
+  manifest --key <pem>   Write a signed sync_manifest.json and engine.manifest.sig.json
+  delta --from <wasm>    Write a patch from an earlier engine.wasm to the current one
+  verify [<manifest>]    Check a bundle's hashes, signatures and pinned assets
+
 Override env vars:
   TONELAB_VST3_DIR       Highest priority install dir override
   VST3_INSTALL_DIR       Generic install dir override
@@ -95,6 +120,13 @@ fn main() -> Result<()> {
         "install" => run_installer(InstallerMode::Install, &remaining),
         "update" => run_installer(InstallerMode::Update, &remaining),
         "doctor" => run_installer(InstallerMode::Doctor, &remaining),
+        "render" => run_render(&remaining),
+        "serve" => serve::run(&workspace_metadata()?.workspace_root, &remaining),
+        "wasm" => publish::run_wasm(&workspace_metadata()?.workspace_root, &remaining),
+        "keygen" => publish::run_keygen(&workspace_metadata()?.workspace_root, &remaining),
+        "manifest" => publish::run_manifest(&workspace_metadata()?.workspace_root, &remaining),
+        "delta" => publish::run_delta(&workspace_metadata()?.workspace_root, &remaining),
+        "verify" => publish::run_verify(&workspace_metadata()?.workspace_root, &remaining),
         "--help" | "-h" | "help" => {
             println!("{HELP_TEXT}");
             Ok(())
@@ -103,6 +135,34 @@ fn main() -> Result<()> {
     }
 }
 
+/// Forwards to the `tonelab_render` binary of the plugin crate, which links
+/// both the wasmtime host and the native `dsp_core` build.
+fn run_render(raw_args: &[String]) -> Result<()> {
+    let metadata = workspace_metadata()?;
+    let status = Command::new("cargo")
+        .args(["run", "--release", "--quiet", "-p"])
+        .arg(&metadata.package_name)
+        .args(["--bin", "tonelab_render", "--"])
+        .args(raw_args)
+        .current_dir(&metadata.workspace_root)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .context("Failed to run tonelab_render")?;
+
+    if !status.success() {
+        bail!("tonelab_render failed with status {}", status);
+    }
+    Ok(())
+}
+
+/// The value following a flag at `args[idx]`.
+fn option_value<'a>(args: &'a [String], idx: usize, flag: &str) -> Result<&'a String> {
+    args.get(idx)
+        .ok_or_else(|| anyhow!("{flag} expects a value"))
+}
+
 fn run_installer(mode: InstallerMode, raw_args: &[String]) -> Result<()> {
     let options = parse_options(raw_args)?;
     let metadata = workspace_metadata()?;
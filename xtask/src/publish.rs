@@ -0,0 +1,425 @@
+//! `xtask wasm`, `keygen`, `manifest`, `delta` and `verify`: producing and
+//! checking signed Evergreen bundles without `sign_bundle.sh` and openssl.
+
+use crate::bundle::{
+    self, ManifestSignatureFile, ManifestSpec, SigningKey, SyncManifest, DELTAS_DIR, MANIFEST_FILE,
+    MANIFEST_SIGNATURE_FILE,
+};
+use crate::option_value;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const HELP_TEXT: &str = r#"Build, sign and check Evergreen bundles
+
+Usage:
+  cargo run -p xtask -- wasm build [--debug] [--out <path>]
+  cargo run -p xtask -- wasm sign --key <key.pem> [--wasm <path>] [--out <sig.b64>]
+  cargo run -p xtask -- keygen [--out <key.pem>] [--public-out <path>]
+  cargo run -p xtask -- manifest --key <key.pem> [manifest options]
+  cargo run -p xtask -- delta --from <old.wasm> [--from <old.wasm>...] [--wasm <path>] [--out-dir <dir>]
+  cargo run -p xtask -- verify [<manifest.json|dir>] [verify options]
+
+wasm build               Builds wasm-engine-rust and copies it to --out (default: backend/assets/engine.wasm)
+wasm sign                Writes the wasm-only signature older plugins check (default: <wasm>.sig.b64)
+keygen                   Creates an Ed25519 key (default: backend/security/keys/dev_ed25519_private.pem)
+                         and prints the public key for TONELAB_EVERGREEN_ED25519_PUBLIC_KEY_B64
+
+Manifest options:
+  --wasm <path>          Bundle wasm (default: backend/assets/engine.wasm)
+  --version <version>    Bundle version (default: 1.0.0-dev)
+  --assets-url <url>     URL the wasm and assets are served from, e.g. https://api.example.com/assets
+                         (default: paths relative to the manifest, for local bundle sources)
+  --assets <dir>         Directory whose icons.zip / effects_manifest.json get pinned (default: the wasm's)
+  --web-ui-url <url>     assets.web_ui_url
+  --abi <n>              Guest ABI the wasm was built against
+  --min-host-version <v> Oldest plugin version allowed to load it
+  --out <path>           Manifest to write (default: sync_manifest.json next to the wasm); the backend's
+                         engine.manifest.sig.json is written next to it
+                         Patches in deltas/ next to the wasm are advertised as `deltas`
+
+delta                    Writes <out-dir>/<sha256 of old>.zst for each --from, a zstd --patch-from diff
+                         plugins with that bundle cached download instead of the full wasm
+                         (default --wasm: backend/assets/engine.wasm, --out-dir: deltas/ next to it)
+
+Verify options:
+  --wasm <path>          Bundle wasm (default: wasm_url resolved next to the manifest)
+  --assets <dir>         Where pinned assets are looked up (default: the manifest's directory)
+  --public-key <b64>     Trusted public key; repeatable
+  --public-key-file <p>  File holding a base64 public key; repeatable
+                         (default: backend/security/dev_ed25519_public_key.b64)
+"#;
+
+const DEFAULT_VERSION: &str = "1.0.0-dev";
+const SIGNATURE_SUFFIX: &str = ".sig.b64";
+
+fn default_wasm(workspace_root: &Path) -> PathBuf {
+    workspace_root
+        .join("backend")
+        .join("assets")
+        .join(bundle::WASM_FILE)
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+fn write_file(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn print_help_and_exit() -> ! {
+    println!("{HELP_TEXT}");
+    std::process::exit(0);
+}
+
+pub fn run_wasm(workspace_root: &Path, args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("build") => run_wasm_build(workspace_root, &args[1..]),
+        Some("sign") => run_wasm_sign(workspace_root, &args[1..]),
+        Some("-h" | "--help") => print_help_and_exit(),
+        Some(other) => bail!("Unknown wasm command: {other}\n\n{HELP_TEXT}"),
+        None => bail!("Expected `wasm build` or `wasm sign`\n\n{HELP_TEXT}"),
+    }
+}
+
+fn run_wasm_build(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut release = true;
+    let mut out = default_wasm(workspace_root);
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--debug" => release = false,
+            "--release" => release = true,
+            "--out" => {
+                idx += 1;
+                out = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown => bail!("Unknown wasm build option: {unknown}\n\n{HELP_TEXT}"),
+        }
+        idx += 1;
+    }
+
+    let artifact = bundle::build_wasm(workspace_root, release)?;
+    let wasm = read_file(&artifact)?;
+    write_file(&out, &wasm)?;
+    println!("Built {} ({})", out.display(), bundle::sha256_hex(&wasm));
+    Ok(())
+}
+
+fn run_wasm_sign(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut key = None;
+    let mut wasm_path = default_wasm(workspace_root);
+    let mut out = None;
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--key" => {
+                idx += 1;
+                key = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--wasm" => {
+                idx += 1;
+                wasm_path = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "--out" => {
+                idx += 1;
+                out = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown => bail!("Unknown wasm sign option: {unknown}\n\n{HELP_TEXT}"),
+        }
+        idx += 1;
+    }
+
+    let key = SigningKey::load(&key.context("wasm sign requires --key")?)?;
+    let out = out.unwrap_or_else(|| {
+        let mut name = wasm_path.as_os_str().to_owned();
+        name.push(SIGNATURE_SUFFIX);
+        PathBuf::from(name)
+    });
+    let wasm = read_file(&wasm_path)?;
+    write_file(&out, key.sign_b64(&wasm))?;
+    println!(
+        "Signed {} with key {}: {}",
+        wasm_path.display(),
+        key.key_id(),
+        out.display()
+    );
+    Ok(())
+}
+
+pub fn run_keygen(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut out = workspace_root.join(bundle::DEV_KEY_PATH);
+    let mut public_out = None;
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--out" => {
+                idx += 1;
+                out = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "--public-out" => {
+                idx += 1;
+                public_out = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown => bail!("Unknown keygen option: {unknown}\n\n{HELP_TEXT}"),
+        }
+        idx += 1;
+    }
+
+    let key = SigningKey::generate(&out)?;
+    if let Some(public_out) = &public_out {
+        write_file(public_out, key.public_key_b64())?;
+    }
+    println!("Private key: {} (keep it out of git)", out.display());
+    println!("Key ID:      {}", key.key_id());
+    println!("Public key:  {}", key.public_key_b64());
+    Ok(())
+}
+
+pub fn run_manifest(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut key = None;
+    let mut wasm_path = default_wasm(workspace_root);
+    let mut spec = ManifestSpec {
+        version: DEFAULT_VERSION.to_string(),
+        ..ManifestSpec::default()
+    };
+    let mut out = None;
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--key" => {
+                idx += 1;
+                key = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--wasm" => {
+                idx += 1;
+                wasm_path = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "--version" => {
+                idx += 1;
+                spec.version = option_value(args, idx, arg)?.clone();
+            }
+            "--assets-url" => {
+                idx += 1;
+                spec.assets_url = Some(option_value(args, idx, arg)?.clone());
+            }
+            "--assets" => {
+                idx += 1;
+                spec.assets_dir = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--web-ui-url" => {
+                idx += 1;
+                spec.web_ui_url = option_value(args, idx, arg)?.clone();
+            }
+            "--abi" => {
+                idx += 1;
+                let value = option_value(args, idx, arg)?;
+                spec.abi_version = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("Invalid ABI version '{value}'"))?,
+                );
+            }
+            "--min-host-version" => {
+                idx += 1;
+                spec.min_host_version = Some(option_value(args, idx, arg)?.clone());
+            }
+            "--out" => {
+                idx += 1;
+                out = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown => bail!("Unknown manifest option: {unknown}\n\n{HELP_TEXT}"),
+        }
+        idx += 1;
+    }
+
+    let key = SigningKey::load(&key.context("manifest requires --key")?)?;
+    let bundle_dir = parent_dir(&wasm_path);
+    if spec.assets_dir.is_none() {
+        spec.assets_dir = Some(bundle_dir.clone());
+    }
+    if spec.deltas_dir.is_none() {
+        spec.deltas_dir = Some(bundle_dir.join(DELTAS_DIR));
+    }
+    let out = out.unwrap_or_else(|| bundle_dir.join(MANIFEST_FILE));
+    let manifest = spec.sign(&read_file(&wasm_path)?, &key)?;
+    write_file(&out, serde_json::to_vec_pretty(&manifest)?)?;
+    let signature_path = parent_dir(&out).join(MANIFEST_SIGNATURE_FILE);
+    write_file(
+        &signature_path,
+        serde_json::to_vec(&ManifestSignatureFile::for_manifest(&manifest)?)?,
+    )?;
+
+    println!(
+        "Manifest {} for {} (key {}): {}",
+        manifest.version,
+        manifest.sha256.as_deref().unwrap_or_default(),
+        key.key_id(),
+        out.display()
+    );
+    println!("Manifest signature: {}", signature_path.display());
+    for delta in &manifest.deltas {
+        println!("Delta from {}: {}", delta.from_sha256, delta.url);
+    }
+    Ok(())
+}
+
+pub fn run_delta(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut from = Vec::new();
+    let mut wasm_path = default_wasm(workspace_root);
+    let mut out_dir = None;
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--from" => {
+                idx += 1;
+                from.push(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--wasm" => {
+                idx += 1;
+                wasm_path = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "--out-dir" => {
+                idx += 1;
+                out_dir = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown => bail!("Unknown delta option: {unknown}\n\n{HELP_TEXT}"),
+        }
+        idx += 1;
+    }
+
+    if from.is_empty() {
+        bail!("delta requires at least one --from <old.wasm>\n\n{HELP_TEXT}");
+    }
+    let out_dir = out_dir.unwrap_or_else(|| parent_dir(&wasm_path).join(DELTAS_DIR));
+    let wasm = read_file(&wasm_path)?;
+    for old_path in &from {
+        let old = read_file(old_path)?;
+        let from_sha256 = bundle::sha256_hex(&old);
+        if from_sha256 == bundle::sha256_hex(&wasm) {
+            println!(
+                "Skipping {}: same bundle as {}",
+                old_path.display(),
+                wasm_path.display()
+            );
+            continue;
+        }
+        let patch = bundle::make_delta(&old, &wasm)?;
+        if bundle::apply_delta(&old, &patch)? != wasm {
+            bail!(
+                "Delta from {} does not reproduce {}",
+                old_path.display(),
+                wasm_path.display()
+            );
+        }
+        let out = out_dir.join(bundle::delta_file_name(&from_sha256));
+        write_file(&out, &patch)?;
+        println!(
+            "Delta from {} ({} -> {} bytes): {}",
+            old_path.display(),
+            wasm.len(),
+            patch.len(),
+            out.display()
+        );
+    }
+    println!("Re-run `manifest` to advertise the deltas in sync_manifest.json");
+    Ok(())
+}
+
+pub fn run_verify(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let mut source = None;
+    let mut wasm_path = None;
+    let mut assets_dir = None;
+    let mut public_keys = Vec::new();
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--wasm" => {
+                idx += 1;
+                wasm_path = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--assets" => {
+                idx += 1;
+                assets_dir = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--public-key" => {
+                idx += 1;
+                public_keys.push(bundle::decode_public_key(option_value(args, idx, arg)?)?);
+            }
+            "--public-key-file" => {
+                idx += 1;
+                public_keys.push(read_public_key(Path::new(option_value(args, idx, arg)?))?);
+            }
+            "-h" | "--help" => print_help_and_exit(),
+            unknown if unknown.starts_with("--") => {
+                bail!("Unknown verify option: {unknown}\n\n{HELP_TEXT}")
+            }
+            path => source = Some(PathBuf::from(path)),
+        }
+        idx += 1;
+    }
+
+    let source = source.unwrap_or_else(|| workspace_root.join("backend").join("assets"));
+    let manifest_path = if source.is_dir() {
+        source.join(MANIFEST_FILE)
+    } else {
+        source
+    };
+    let manifest: SyncManifest = serde_json::from_slice(&read_file(&manifest_path)?)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    let bundle_dir = parent_dir(&manifest_path);
+    let wasm_path =
+        wasm_path.unwrap_or_else(|| bundle::local_file(&bundle_dir, &manifest.wasm_url));
+    let assets_dir = assets_dir.unwrap_or(bundle_dir);
+    if public_keys.is_empty() {
+        public_keys.push(read_public_key(
+            &workspace_root.join(bundle::DEV_PUBLIC_KEY_PATH),
+        )?);
+    }
+
+    let wasm = read_file(&wasm_path)?;
+    let passed = bundle::verify_bundle(&manifest, &wasm, &public_keys, &assets_dir)
+        .with_context(|| format!("Bundle {} failed verification", manifest_path.display()))?;
+    println!(
+        "Bundle {} ({}) verified:",
+        manifest.version,
+        wasm_path.display()
+    );
+    for check in passed {
+        println!("  ok  {check}");
+    }
+    Ok(())
+}
+
+fn read_public_key(path: &Path) -> Result<Vec<u8>> {
+    let public_key_b64 = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read public key {}", path.display()))?;
+    bundle::decode_public_key(&public_key_b64)
+        .with_context(|| format!("Invalid public key {}", path.display()))
+}
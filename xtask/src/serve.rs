@@ -0,0 +1,456 @@
+//! `xtask serve`: a local stand-in for the backend's `/vst/sync`, serving a
+//! freshly built and signed bundle plus the UI and effect assets.
+
+use crate::bundle::{self, ManifestSpec, SigningKey, SyncManifest, WASM_FILE};
+use crate::option_value;
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const HELP_TEXT: &str = r#"Serve a locally built, signed Evergreen bundle
+
+Usage:
+  cargo run -p xtask -- serve [options]
+
+Options:
+  --port <port>          Port to listen on (default: 8787)
+  --wasm <path>          Serve this engine.wasm instead of building wasm-engine-rust
+  --debug                Build the wasm engine without --release
+  --key <path>           Ed25519 PKCS#8 PEM key (default: backend/security/keys/dev_ed25519_private.pem, created if missing)
+  --version <version>    Bundle version in the manifest (default: 0.0.0-dev)
+  --ui <dir>             Built UI to serve under /ui/ (default: ui/dist when it exists)
+  --assets <dir>         Directory with icons.zip and effects_manifest.json (default: backend/assets)
+  -h, --help             Show this help
+
+Point the plugin at it with the printed TONELAB_EVERGREEN_SYNC_URL and
+TONELAB_EVERGREEN_ED25519_PUBLIC_KEY_B64.
+"#;
+
+const DEFAULT_PORT: u16 = 8787;
+const DEFAULT_VERSION: &str = "0.0.0-dev";
+const UI_ENTRY_FILE: &str = "index.html";
+const MAX_REQUEST_HEAD: usize = 16 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub port: u16,
+    pub wasm: Option<PathBuf>,
+    pub release: bool,
+    pub key: PathBuf,
+    pub version: String,
+    pub ui_dir: Option<PathBuf>,
+    pub assets_dir: PathBuf,
+}
+
+impl ServeOptions {
+    fn defaults(workspace_root: &Path) -> Self {
+        let ui_dist = workspace_root.join("ui").join("dist");
+        Self {
+            port: DEFAULT_PORT,
+            wasm: None,
+            release: true,
+            key: workspace_root.join(bundle::DEV_KEY_PATH),
+            version: DEFAULT_VERSION.to_string(),
+            ui_dir: ui_dist.is_dir().then_some(ui_dist),
+            assets_dir: workspace_root.join("backend").join("assets"),
+        }
+    }
+}
+
+pub fn parse_options(workspace_root: &Path, args: &[String]) -> Result<ServeOptions> {
+    let mut options = ServeOptions::defaults(workspace_root);
+    let mut idx = 0usize;
+
+    while idx < args.len() {
+        let arg = &args[idx];
+        match arg.as_str() {
+            "--port" => {
+                idx += 1;
+                let value = option_value(args, idx, arg)?;
+                options.port = value
+                    .parse()
+                    .with_context(|| format!("Invalid port '{value}'"))?;
+            }
+            "--wasm" => {
+                idx += 1;
+                options.wasm = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--debug" => options.release = false,
+            "--key" => {
+                idx += 1;
+                options.key = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "--version" => {
+                idx += 1;
+                options.version = option_value(args, idx, arg)?.clone();
+            }
+            "--ui" => {
+                idx += 1;
+                options.ui_dir = Some(PathBuf::from(option_value(args, idx, arg)?));
+            }
+            "--assets" => {
+                idx += 1;
+                options.assets_dir = PathBuf::from(option_value(args, idx, arg)?);
+            }
+            "-h" | "--help" => {
+                println!("{HELP_TEXT}");
+                std::process::exit(0);
+            }
+            unknown => {
+                bail!("Unknown serve option: {unknown}\n\n{HELP_TEXT}");
+            }
+        }
+        idx += 1;
+    }
+
+    Ok(options)
+}
+
+pub fn run(workspace_root: &Path, args: &[String]) -> Result<()> {
+    let options = parse_options(workspace_root, args)?;
+    let wasm_path = match &options.wasm {
+        Some(path) => path.clone(),
+        None => bundle::build_wasm(workspace_root, options.release)?,
+    };
+    let key = SigningKey::load_or_generate(&options.key)?;
+    let server = DevServer::bind(&options, &wasm_path, &key)?;
+
+    let manifest = &server.site.manifest;
+    println!(
+        "Serving Evergreen bundle {} on {}",
+        manifest.version,
+        server.base_url()
+    );
+    println!(
+        "  wasm:     {} ({})",
+        wasm_path.display(),
+        manifest.sha256.as_deref().unwrap_or_default()
+    );
+    println!("  key:      {} ({})", options.key.display(), key.key_id());
+    match &options.ui_dir {
+        Some(dir) => println!("  ui:       {}", dir.display()),
+        None => println!("  ui:       <none, build ui/ or pass --ui>"),
+    }
+    println!();
+    println!(
+        "export TONELAB_EVERGREEN_SYNC_URL={}/vst/sync",
+        server.base_url()
+    );
+    println!(
+        "export TONELAB_EVERGREEN_ED25519_PUBLIC_KEY_B64={}",
+        key.public_key_b64()
+    );
+    server.serve_forever()
+}
+
+/// Everything the server answers with, fixed at startup.
+struct Site {
+    manifest: SyncManifest,
+    wasm: Vec<u8>,
+    assets_dir: PathBuf,
+    ui_dir: Option<PathBuf>,
+}
+
+pub struct DevServer {
+    listener: TcpListener,
+    site: Arc<Site>,
+}
+
+impl DevServer {
+    /// Binds to localhost and signs a manifest whose URLs point back at the
+    /// bound address.
+    pub fn bind(options: &ServeOptions, wasm_path: &Path, key: &SigningKey) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", options.port))
+            .with_context(|| format!("Failed to listen on port {}", options.port))?;
+        let base_url = format!("http://{}", listener.local_addr()?);
+        let wasm = fs::read(wasm_path)
+            .with_context(|| format!("Failed to read {}", wasm_path.display()))?;
+
+        let web_ui_url = match &options.ui_dir {
+            Some(_) => format!("{base_url}/ui/{UI_ENTRY_FILE}"),
+            None => String::new(),
+        };
+        let manifest = ManifestSpec {
+            version: options.version.clone(),
+            assets_url: Some(format!("{base_url}/assets")),
+            assets_dir: Some(options.assets_dir.clone()),
+            web_ui_url,
+            ..ManifestSpec::default()
+        }
+        .sign(&wasm, key)?;
+
+        Ok(Self {
+            listener,
+            site: Arc::new(Site {
+                manifest,
+                wasm,
+                assets_dir: options.assets_dir.clone(),
+                ui_dir: options.ui_dir.clone(),
+            }),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn base_url(&self) -> String {
+        self.local_addr()
+            .map(|addr| format!("http://{addr}"))
+            .unwrap_or_default()
+    }
+
+    pub fn manifest(&self) -> &SyncManifest {
+        &self.site.manifest
+    }
+
+    /// Answers requests one thread per connection until the process exits.
+    pub fn serve_forever(self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(error) => {
+                    eprintln!("serve: failed to accept connection: {error}");
+                    continue;
+                }
+            };
+            let site = self.site.clone();
+            std::thread::spawn(move || {
+                if let Err(error) = handle_connection(stream, &site) {
+                    eprintln!("serve: {error:#}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+struct Reply {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{status}\n").into_bytes(),
+        }
+    }
+}
+
+fn handle_connection(stream: TcpStream, site: &Site) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Headers are not needed; read past them so the client sees a clean close.
+    let mut head_len = request_line.len();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line)?;
+        head_len += read;
+        if read == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+        if head_len > MAX_REQUEST_HEAD {
+            return write_reply(
+                stream,
+                &Reply::error("431 Request Header Fields Too Large"),
+                false,
+            );
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let reply = match method {
+        "GET" | "HEAD" => route(site, target),
+        _ => Reply::error("405 Method Not Allowed"),
+    };
+    println!("{} {} -> {}", method, target, reply.status);
+    write_reply(stream, &reply, method == "HEAD")
+}
+
+fn route(site: &Site, target: &str) -> Reply {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    if path == "/vst/sync" {
+        // Channels and host checks belong to the real backend; the dev
+        // server always answers with the one bundle it built.
+        return match serde_json::to_vec_pretty(&site.manifest) {
+            Ok(body) => Reply::ok("application/json", body),
+            Err(_) => Reply::error("500 Internal Server Error"),
+        };
+    }
+    if let Some(file) = path.strip_prefix("/assets/") {
+        if file == WASM_FILE {
+            return Reply::ok("application/wasm", site.wasm.clone());
+        }
+        return serve_file(&site.assets_dir, file);
+    }
+    if let Some(ui_dir) = &site.ui_dir {
+        if path == "/ui" || path == "/ui/" {
+            return serve_file(ui_dir, UI_ENTRY_FILE);
+        }
+        if let Some(file) = path.strip_prefix("/ui/") {
+            return serve_file(ui_dir, file);
+        }
+    }
+    Reply::error("404 Not Found")
+}
+
+/// Serves `relative` from inside `root`; anything that could step outside it
+/// is refused.
+fn serve_file(root: &Path, relative: &str) -> Reply {
+    let escapes = relative.split('/').any(|part| {
+        part.is_empty() || part == "." || part == ".." || part.contains('\\') || part.contains(':')
+    });
+    if escapes {
+        return Reply::error("404 Not Found");
+    }
+    match fs::read(root.join(relative)) {
+        Ok(body) => Reply::ok(content_type(relative), body),
+        Err(_) => Reply::error("404 Not Found"),
+    }
+}
+
+fn content_type(file: &str) -> &'static str {
+    match Path::new(file).extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json") => "application/json",
+        Some("wasm") => "application/wasm",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("zip") => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_reply(mut stream: TcpStream, reply: &Reply, head_only: bool) -> Result<()> {
+    // The editor loads the UI from the tonelab:// protocol, so assets are
+    // fetched cross-origin.
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        reply.status,
+        reply.content_type,
+        reply.body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        stream.write_all(&reply.body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::EFFECTS_FILE;
+    use base64::Engine as _;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+    use std::io::Read;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "tonelab_xtask_serve_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn get(addr: SocketAddr, target: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("response head");
+        let head = String::from_utf8_lossy(&response[..split]).to_string();
+        let status = head.lines().next().unwrap_or_default().to_string();
+        (status, response[split + 4..].to_vec())
+    }
+
+    #[test]
+    fn serves_a_signed_manifest_with_its_wasm_and_assets() {
+        let dir = temp_dir("site");
+        let wasm_path = dir.join("engine.wasm");
+        fs::write(&wasm_path, b"\0asm dev engine").unwrap();
+        let assets_dir = dir.join("assets");
+        fs::create_dir_all(&assets_dir).unwrap();
+        fs::write(assets_dir.join(EFFECTS_FILE), br#"{"effects":[]}"#).unwrap();
+        let ui_dir = dir.join("ui");
+        fs::create_dir_all(&ui_dir).unwrap();
+        fs::write(ui_dir.join(UI_ENTRY_FILE), b"<html></html>").unwrap();
+
+        let options = ServeOptions {
+            port: 0,
+            wasm: Some(wasm_path.clone()),
+            release: true,
+            key: dir.join("key.pem"),
+            version: "9.9.9-dev".to_string(),
+            ui_dir: Some(ui_dir),
+            assets_dir,
+        };
+        let key = SigningKey::load_or_generate(&options.key).unwrap();
+        let server = DevServer::bind(&options, &wasm_path, &key).unwrap();
+        let addr = server.local_addr().unwrap();
+        let base_url = server.base_url();
+        std::thread::spawn(move || server.serve_forever());
+
+        let (status, body) = get(addr, "/vst/sync?host_version=0.2.0&abi=1&channel=stable");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let manifest: SyncManifest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(manifest.version, "9.9.9-dev");
+        assert_eq!(manifest.wasm_url, format!("{base_url}/assets/engine.wasm"));
+        assert_eq!(
+            manifest.assets.icons_url, "",
+            "missing assets are not advertised"
+        );
+
+        let public_key = base64::engine::general_purpose::STANDARD
+            .decode(key.public_key_b64())
+            .unwrap();
+        let signature = base64::engine::general_purpose::STANDARD
+            .decode(manifest.manifest_signature.as_deref().unwrap())
+            .unwrap();
+        assert!(UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(&manifest.signed_payload().unwrap(), &signature)
+            .is_ok());
+
+        let (_, wasm) = get(addr, "/assets/engine.wasm");
+        assert_eq!(Some(bundle::sha256_hex(&wasm)), manifest.sha256);
+        let (_, effects) = get(addr, "/assets/effects_manifest.json");
+        assert_eq!(
+            Some(bundle::sha256_hex(&effects)),
+            manifest.assets.effects_sha256
+        );
+        assert_eq!(get(addr, "/ui/").1, b"<html></html>");
+        assert_eq!(get(addr, "/assets/../key.pem").0, "HTTP/1.1 404 Not Found");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}